@@ -1,10 +1,13 @@
 use crate::adapter::Adapter;
 use crate::checkpoint::Checkpoint;
-use crate::interface::{BitcoinConfig, ChangeRates, Dest, Validator, Xpub};
+use crate::constants::MAX_RECOVERY_SCRIPTS;
+use crate::interface::{BitcoinConfig, ChangeRates, CoinbaseProof, Dest, Validator, Xpub};
 use crate::signatory::SignatoryKeys;
 use crate::state::{
-    get_full_btc_denom, get_validators, BITCOIN_CONFIG, CONFIG, CONFIRMED_INDEX, FEE_POOL,
-    FIRST_UNHANDLED_CONFIRMED_INDEX, SIGNERS, SIG_KEYS, VALIDATORS, XPUBS,
+    get_full_btc_denom, get_validators, BITCOIN_CONFIG, CONFIG, CONFIRMED_INDEX, DEPOSIT_BLOCKS,
+    FEE_POOL, FIRST_UNHANDLED_CONFIRMED_INDEX, HEADER_HASHES, HEADER_REWARDS,
+    PENDING_HEADER_REWARDS, RECOVERY_SCRIPTS, RECOVERY_SCRIPT_COUNT, REORGED_DEPOSITS, SIGNERS,
+    SIG_KEYS, VALIDATORS, XPUBS,
 };
 use crate::threshold_sig;
 
@@ -14,24 +17,119 @@ use super::recovery::{RecoveryTxInput, RecoveryTxs};
 use super::checkpoint::BatchType;
 use super::checkpoint::CheckpointQueue;
 use super::error::{ContractError, ContractResult};
-use super::header::HeaderQueue;
-use bitcoin::Script;
+use super::header::{HeaderQueue, Reorg, WorkHeader, WrappedHeader};
 use bitcoin::{util::merkleblock::PartialMerkleTree, Transaction};
+use bitcoin::{BlockHash, Script};
+use cosmwasm_schema::schemars::JsonSchema;
 use cosmwasm_schema::serde::{Deserialize, Serialize};
-use cosmwasm_std::{Addr, Coin, Env, Order, Storage, Uint128};
+use cosmwasm_std::{Addr, Binary, Coin, Env, Order, QuerierWrapper, StdResult, Storage, Uint128};
+use cw_storage_plus::Bound;
+use libsecp256k1_core::curve::ECMultContext;
 
 use super::outpoint_set::OutpointSet;
 use super::signatory::SignatorySet;
 use std::collections::HashMap;
 
-pub const NETWORK: ::bitcoin::Network = ::bitcoin::Network::Bitcoin;
-
 /// Calculates the bridge fee for a deposit of the given amount of BTC, in
 /// satoshis.
 pub fn calc_deposit_fee(_: Uint128) -> u64 {
     0
 }
 
+/// Checks that a merkle proof commits to exactly the passed transaction and
+/// matches the merkle root of the passed header.
+///
+/// A 64-byte transaction can be confused with an inner node of the merkle tree
+/// (CVE-2017-12842), so such transactions are rejected. If a coinbase proof is
+/// given, the proof must also have the same depth as the real tree.
+pub fn verify_tx_proof(
+    btc_tx: &Transaction,
+    btc_proof: &PartialMerkleTree,
+    btc_header: &WorkHeader,
+    coinbase_proof: Option<&CoinbaseProof>,
+) -> ContractResult<()> {
+    let mut txids = vec![];
+    let mut block_indexes = vec![];
+    let proof_merkle_root = btc_proof
+        .extract_matches(&mut txids, &mut block_indexes)
+        .map_err(|_| ContractError::BitcoinMerkleBlockError)?;
+    if proof_merkle_root != btc_header.merkle_root() {
+        return Err(ContractError::App(
+            "Bitcoin merkle proof does not match header".to_string(),
+        ))?;
+    }
+    if txids.len() != 1 {
+        return Err(ContractError::App(
+            "Bitcoin merkle proof contains an invalid number of txids".to_string(),
+        ))?;
+    }
+    if txids[0] != btc_tx.txid() {
+        return Err(ContractError::App(
+            "Bitcoin merkle proof does not match transaction".to_string(),
+        ))?;
+    }
+
+    if let Some(coinbase_proof) = coinbase_proof {
+        verify_coinbase_proof(coinbase_proof, btc_header)?;
+        if merkle_tree_depth(coinbase_proof.proof.num_transactions())
+            != merkle_tree_depth(btc_proof.num_transactions())
+        {
+            return Err(ContractError::App(
+                "Bitcoin merkle proof depth does not match the coinbase proof".to_string(),
+            ))?;
+        }
+    }
+
+    if btc_tx.strippedsize() == 64 {
+        return Err(ContractError::App(
+            "Transactions of 64 bytes are not accepted".to_string(),
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// Checks that a coinbase proof proves a coinbase transaction as the first
+/// transaction of the block with the passed header.
+fn verify_coinbase_proof(
+    coinbase_proof: &CoinbaseProof,
+    btc_header: &WorkHeader,
+) -> ContractResult<()> {
+    if !coinbase_proof.tx.is_coin_base() {
+        return Err(ContractError::App(
+            "Coinbase proof does not contain a coinbase transaction".to_string(),
+        ))?;
+    }
+
+    let mut txids = vec![];
+    let mut block_indexes = vec![];
+    let proof_merkle_root = coinbase_proof
+        .proof
+        .extract_matches(&mut txids, &mut block_indexes)
+        .map_err(|_| ContractError::BitcoinMerkleBlockError)?;
+    if proof_merkle_root != btc_header.merkle_root() {
+        return Err(ContractError::App(
+            "Coinbase merkle proof does not match header".to_string(),
+        ))?;
+    }
+    if txids != [coinbase_proof.tx.txid()] || block_indexes != [0] {
+        return Err(ContractError::App(
+            "Coinbase merkle proof does not match coinbase transaction".to_string(),
+        ))?;
+    }
+
+    Ok(())
+}
+
+/// The depth of a merkle tree with the passed number of transactions.
+fn merkle_tree_depth(num_transactions: u32) -> u32 {
+    let mut depth = 0;
+    while (1u64 << depth) < num_transactions as u64 {
+        depth += 1;
+    }
+    depth
+}
+
 /// The main structure where Bitcoin bridge state is held.
 ///
 /// This structure is the main entry point for interacting with the Bitcoin
@@ -58,7 +156,6 @@ pub struct Bitcoin {
 
     /// The public keys declared by signatories, which are used to sign Bitcoin
     /// transactions.
-    // TODO: store recovery script data in account struct
     pub signatory_keys: SignatoryKeys, // ?
 
     /// A pool of BTC where bridge fees are collected.
@@ -75,6 +172,28 @@ pub struct Bitcoin {
 /// A Tendermint/CometBFT public key.
 pub type ConsensusKey = [u8; 32];
 
+/// A deposit credited to a checkpoint, kept by the block it was relayed from
+/// so it can be undone if that block leaves the best chain.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "cosmwasm_schema::serde")]
+#[schemars(crate = "cosmwasm_schema::schemars")]
+pub struct RelayedDeposit {
+    /// The index of the checkpoint the deposit was added to.
+    pub checkpoint_index: u32,
+
+    /// The outpoint of the deposit, spent by an input of the checkpoint.
+    pub outpoint: Adapter<bitcoin::OutPoint>,
+
+    /// The destination credited by the deposit.
+    pub dest: Dest,
+
+    /// The pending nBTC credit of the deposit, after fees.
+    pub nbtc: Coin,
+
+    /// The fee taken from the deposit and given to the fee pool.
+    pub fee: Uint128,
+}
+
 impl Bitcoin {
     pub fn default() -> Self {
         Self {
@@ -137,7 +256,12 @@ impl Bitcoin {
             .load(store, signer.as_str())
             .map_err(|_| ContractError::App("Signer does not have a consensus key".to_string()))?;
 
-        if signatory_key.network != self.network() {
+        // Extended keys only encode mainnet or testnet, so signet and regtest
+        // keys are decoded as testnet keys.
+        let network = self.network(store)?;
+        if (signatory_key.network == bitcoin::Network::Bitcoin)
+            != (network == bitcoin::Network::Bitcoin)
+        {
             return Err(ContractError::App(
                 "Signatory key network does not match network".to_string(),
             ));
@@ -149,6 +273,80 @@ impl Bitcoin {
         Ok(())
     }
 
+    /// Sets the Bitcoin script of the account of `signer` which its nBTC balance
+    /// is paid to in the emergency disbursal, and which its expired deposits
+    /// are refunded to.
+    ///
+    /// The script must be a standard output script no longer than the maximum
+    /// withdrawal script length, so that it can be paid to on the network.
+    pub fn set_recovery_script(
+        &mut self,
+        store: &mut dyn Storage,
+        signer: Addr,
+        script: Adapter<Script>,
+    ) -> ContractResult<()> {
+        let config = self.config(store)?;
+        if script.len() as u64 > config.max_withdrawal_script_length {
+            return Err(ContractError::App(
+                "Script exceeds maximum length".to_string(),
+            ));
+        }
+
+        let network = self.network(store)?;
+        if bitcoin::Address::from_script(&script, network).is_none() {
+            return Err(ContractError::App(
+                "Script is not a standard script for the network".to_string(),
+            ));
+        }
+
+        if !RECOVERY_SCRIPTS.has(store, &signer) {
+            let count = RECOVERY_SCRIPT_COUNT.may_load(store)?.unwrap_or_default();
+            if count >= MAX_RECOVERY_SCRIPTS {
+                return Err(ContractError::App(
+                    "Maximum number of recovery scripts reached".to_string(),
+                ));
+            }
+            RECOVERY_SCRIPT_COUNT.save(store, &(count + 1))?;
+        }
+        RECOVERY_SCRIPTS.save(store, &signer, &script)?;
+
+        Ok(())
+    }
+
+    /// The recovery script registered for the given account, if any.
+    pub fn recovery_script(
+        &self,
+        store: &dyn Storage,
+        addr: &Addr,
+    ) -> ContractResult<Option<Adapter<Script>>> {
+        Ok(RECOVERY_SCRIPTS.may_load(store, addr)?)
+    }
+
+    /// Adds MuSig2 public nonces to the checkpoint with the given index, for
+    /// the signatory key submitted by `signer`.
+    ///
+    /// Unlike signatures, nonces can not be verified, so they are only accepted
+    /// from the owner of the signatory key.
+    pub fn submit_checkpoint_nonces(
+        &mut self,
+        store: &mut dyn Storage,
+        signer: Addr,
+        nonces: Vec<Binary>,
+        cp_index: u32,
+    ) -> ContractResult<()> {
+        let consensus_key = SIGNERS
+            .load(store, signer.as_str())
+            .map_err(|_| ContractError::App("Signer does not have a consensus key".to_string()))?;
+        let xpub = self
+            .signatory_keys
+            .get(store, consensus_key)?
+            .ok_or_else(|| {
+                ContractError::App("Signer does not have a signatory key".to_string())
+            })?;
+
+        self.checkpoints.add_nonces(store, &xpub, nonces, cp_index)
+    }
+
     pub fn calc_minimum_deposit_fees(
         &self,
         store: &dyn Storage,
@@ -189,18 +387,19 @@ impl Bitcoin {
         store: &mut dyn Storage,
         btc_tx: Adapter<Transaction>,
         btc_height: u32,
+        btc_block_hash: Option<BlockHash>,
         btc_proof: Adapter<PartialMerkleTree>,
         btc_vout: u32,
         sigset_index: u32,
         dest: Dest,
+        btc_coinbase_proof: Option<CoinbaseProof>,
     ) -> ContractResult<()> {
         let config = self.config(store)?;
         let now = env.block.time.seconds();
 
         let btc_header = self
             .headers
-            .get_by_height(store, btc_height, None)?
-            .ok_or_else(|| ContractError::App("Invalid bitcoin block height".to_string()))?;
+            .get_for_proof(store, btc_height, btc_block_hash)?;
 
         if self.headers.height(store)? - btc_height < config.min_confirmations {
             return Err(ContractError::App(
@@ -208,26 +407,12 @@ impl Bitcoin {
             ));
         }
 
-        let mut txids = vec![];
-        let mut block_indexes = vec![];
-        let proof_merkle_root = btc_proof
-            .extract_matches(&mut txids, &mut block_indexes)
-            .map_err(|_| ContractError::BitcoinMerkleBlockError)?;
-        if proof_merkle_root != btc_header.merkle_root() {
-            return Err(ContractError::App(
-                "Bitcoin merkle proof does not match header".to_string(),
-            ))?;
-        }
-        if txids.len() != 1 {
-            return Err(ContractError::App(
-                "Bitcoin merkle proof contains an invalid number of txids".to_string(),
-            ))?;
-        }
-        if txids[0] != btc_tx.txid() {
-            return Err(ContractError::App(
-                "Bitcoin merkle proof does not match transaction".to_string(),
-            ))?;
-        }
+        verify_tx_proof(
+            &btc_tx,
+            &btc_proof,
+            &btc_header,
+            btc_coinbase_proof.as_ref(),
+        )?;
 
         if btc_vout as usize >= btc_tx.output.len() {
             return Err(ContractError::App(
@@ -248,7 +433,8 @@ impl Bitcoin {
         let dest_bytes = dest.commitment_bytes()?;
         let threshold = self.checkpoints.config(store).sigset_threshold;
 
-        let expected_script = sigset.output_script(&dest_bytes, threshold)?;
+        let ctx = ECMultContext::new_boxed();
+        let expected_script = sigset.output_script(&ctx, &dest_bytes, threshold)?;
         if output.script_pubkey != expected_script {
             return Err(ContractError::App(
                 "Output script does not match signature set".to_string(),
@@ -273,14 +459,20 @@ impl Bitcoin {
         if now > deposit_timeout {
             let checkpoint = self.checkpoints.building(store)?;
             let checkpoint_config = self.checkpoints.config(store);
+            let recovery_script = match &dest {
+                Dest::Address(addr) => self.recovery_script(store, addr)?,
+                Dest::Ibc(_) => None,
+            };
             self.recovery_txs.create_recovery_tx(
                 store,
+                &ctx,
                 RecoveryTxInput {
                     expired_tx: btc_tx.into_inner(),
                     vout: btc_vout,
                     old_sigset: &sigset,
                     new_sigset: &checkpoint.sigset,
                     dest,
+                    recovery_script,
                     fee_rate: checkpoint.fee_rate,
                     //TODO: Hold checkpoint config on state
                     threshold: checkpoint_config.sigset_threshold,
@@ -295,6 +487,7 @@ impl Bitcoin {
             vout: btc_vout,
         };
         let input = Input::new(
+            &ctx,
             prevout,
             &sigset,
             &dest_bytes,
@@ -334,22 +527,204 @@ impl Bitcoin {
         // let deposit_fee = nbtc.take(calc_deposit_fee(nbtc.amount.into()))?;
         // self.give_rewards(deposit_fee)?;
 
-        building_mut.insert_pending(dest, nbtc)?;
+        building_mut.insert_pending_deposit(outpoint, dest.clone(), nbtc.clone())?;
 
         let index = self.checkpoints.index(store);
         self.checkpoints.set(store, index, &building_mut)?;
 
+        // remember the block of the deposit so the credit can be undone if the
+        // block is reorged out of the best chain
+        let block_hash = btc_header.block_hash();
+        let block_key = (btc_header.height(), &block_hash[..]);
+        let mut deposits = DEPOSIT_BLOCKS
+            .may_load(store, block_key)?
+            .unwrap_or_default();
+        deposits.push(RelayedDeposit {
+            checkpoint_index: index,
+            outpoint: Adapter::new(outpoint),
+            dest,
+            nbtc,
+            fee,
+        });
+        DEPOSIT_BLOCKS.save(store, block_key, &deposits)?;
+
         Ok(())
     }
 
+    /// Undoes the deposits relayed from blocks which were removed from the best
+    /// chain by the passed reorg.
+    ///
+    /// Deposits in the `Building` checkpoint have their input, pending credit
+    /// and fees removed, and their outpoint can be relayed again. Deposits in a
+    /// checkpoint which is already signing can not be undone, so they are
+    /// recorded in `REORGED_DEPOSITS` for operators to handle.
+    pub fn invalidate_deposits(
+        &mut self,
+        store: &mut dyn Storage,
+        reorg: &Reorg,
+    ) -> ContractResult<()> {
+        let start = reorg.fork_height + 1;
+        let end = start + reorg.depth;
+        let blocks: Vec<_> = DEPOSIT_BLOCKS
+            .range(
+                store,
+                Some(Bound::inclusive((start, &[][..]))),
+                Some(Bound::exclusive((end, &[][..]))),
+                Order::Ascending,
+            )
+            .collect::<StdResult<_>>()?;
+
+        let mut building = None;
+        for ((height, hash), deposits) in blocks {
+            if HEADER_HASHES.may_load(store, &hash)? == Some(height) {
+                continue;
+            }
+            DEPOSIT_BLOCKS.remove(store, (height, &hash));
+
+            for deposit in deposits {
+                if deposit.checkpoint_index != self.checkpoints.index(store) {
+                    REORGED_DEPOSITS.save(store, &deposit.outpoint.to_string(), &deposit)?;
+                    continue;
+                }
+
+                if building.is_none() {
+                    building = Some(self.checkpoints.building(store)?);
+                }
+                let checkpoint = building.as_mut().unwrap();
+                checkpoint.remove_deposit(&deposit)?;
+                self.processed_outpoints.remove(store, *deposit.outpoint);
+                self.take_miner_fee(store, checkpoint, deposit.fee)?;
+            }
+        }
+
+        if let Some(checkpoint) = building {
+            let index = self.checkpoints.index(store);
+            self.checkpoints.set(store, index, &checkpoint)?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the deposits relayed from blocks below the front of the header
+    /// queue, which can not be reorged anymore.
+    pub fn prune_deposit_blocks(&mut self, store: &mut dyn Storage) -> ContractResult<()> {
+        let initial_height = self.headers.get_initial_height(store)?;
+        let stale: Vec<_> = DEPOSIT_BLOCKS
+            .keys(
+                store,
+                None,
+                Some(Bound::exclusive((initial_height, &[][..]))),
+                Order::Ascending,
+            )
+            .collect::<StdResult<_>>()?;
+        for (height, hash) in stale {
+            DEPOSIT_BLOCKS.remove(store, (height, &hash));
+        }
+
+        Ok(())
+    }
+
+    /// Records the relayer of newly accepted headers, and credits the reward
+    /// for every pending header which is now deep enough.
+    ///
+    /// A header is rewarded once it has `header_reward_confirmations`
+    /// confirmations, only if it is still on the best chain at that point.
+    /// Headers which were reorged out are dropped without a reward. Rewards
+    /// paid from the fee pool stay pending while it can not pay them.
+    pub fn reward_headers(
+        &mut self,
+        store: &mut dyn Storage,
+        relayer: &Addr,
+        headers: &[WrappedHeader],
+    ) -> ContractResult<()> {
+        let config = self.config(store)?;
+
+        if config.header_reward > 0 {
+            for header in headers {
+                let block_hash = header.block_hash();
+                let key = (header.height(), &block_hash[..]);
+                if !PENDING_HEADER_REWARDS.has(store, key) {
+                    PENDING_HEADER_REWARDS.save(store, key, relayer)?;
+                }
+            }
+        }
+
+        let tip_height = self.headers.height(store)?;
+        let Some(mature_height) = tip_height.checked_sub(config.header_reward_confirmations) else {
+            return Ok(());
+        };
+        let mature: Vec<_> = PENDING_HEADER_REWARDS
+            .range(
+                store,
+                None,
+                Some(Bound::exclusive((mature_height + 1, &[][..]))),
+                Order::Ascending,
+            )
+            .collect::<StdResult<_>>()?;
+
+        for ((height, hash), relayer) in mature {
+            if HEADER_HASHES.may_load(store, &hash)? != Some(height) {
+                PENDING_HEADER_REWARDS.remove(store, (height, &hash));
+                continue;
+            }
+
+            let denom = match config.header_reward_denom.as_ref() {
+                Some(denom) => denom.clone(),
+                None => {
+                    // nBTC rewards are paid out of the fee pool, and stay
+                    // pending until it holds enough to pay them
+                    let mut fee_pool = self.fee_pool(store)?;
+                    if fee_pool < config.header_reward as i64 {
+                        break;
+                    }
+                    fee_pool -= config.header_reward as i64;
+                    FEE_POOL.save(store, &fee_pool)?;
+                    get_full_btc_denom(CONFIG.load(store)?.token_factory_addr.as_str())
+                }
+            };
+            PENDING_HEADER_REWARDS.remove(store, (height, &hash));
+            HEADER_REWARDS.update(store, (&relayer, &denom), |amount| -> StdResult<_> {
+                Ok(amount.unwrap_or_default() + Uint128::from(config.header_reward))
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// The header rewards which the passed relayer can claim.
+    pub fn header_rewards(&self, store: &dyn Storage, relayer: &Addr) -> ContractResult<Vec<Coin>> {
+        let rewards = HEADER_REWARDS
+            .prefix(relayer)
+            .range(store, None, None, Order::Ascending)
+            .map(|item| item.map(|(denom, amount)| Coin { denom, amount }))
+            .collect::<StdResult<_>>()?;
+        Ok(rewards)
+    }
+
+    /// Removes and returns the header rewards of the passed relayer, to be
+    /// paid out.
+    pub fn take_header_rewards(
+        &mut self,
+        store: &mut dyn Storage,
+        relayer: &Addr,
+    ) -> ContractResult<Vec<Coin>> {
+        let rewards = self.header_rewards(store, relayer)?;
+        for reward in rewards.iter() {
+            HEADER_REWARDS.remove(store, (relayer, &reward.denom));
+        }
+        Ok(rewards)
+    }
+
     /// Records proof that a checkpoint produced by the network has been
     /// confirmed into a Bitcoin block.    
     pub fn relay_checkpoint(
         &mut self,
         store: &mut dyn Storage,
         btc_height: u32,
+        btc_block_hash: Option<BlockHash>,
         btc_proof: Adapter<PartialMerkleTree>,
         cp_index: u32,
+        btc_coinbase_proof: Option<CoinbaseProof>,
     ) -> ContractResult<()> {
         let config = self.config(store)?;
         if let Some(conf_index) = self.checkpoints.confirmed_index(store) {
@@ -362,8 +737,7 @@ impl Bitcoin {
 
         let btc_header = self
             .headers
-            .get_by_height(store, btc_height, None)?
-            .ok_or_else(|| ContractError::App("Invalid bitcoin block height".to_string()))?;
+            .get_for_proof(store, btc_height, btc_block_hash)?;
 
         if self.headers.height(store)? - btc_height < config.min_checkpoint_confirmations {
             return Err(ContractError::App(
@@ -371,28 +745,13 @@ impl Bitcoin {
             ));
         }
 
-        let mut txids = vec![];
-        let mut block_indexes = vec![];
-        let proof_merkle_root = btc_proof
-            .extract_matches(&mut txids, &mut block_indexes)
-            .map_err(|_| ContractError::BitcoinMerkleBlockError)?;
-        if proof_merkle_root != btc_header.merkle_root() {
-            return Err(ContractError::App(
-                "Bitcoin merkle proof does not match header".to_string(),
-            ))?;
-        }
-        if txids.len() != 1 {
-            return Err(ContractError::App(
-                "Bitcoin merkle proof contains an invalid number of txids".to_string(),
-            ))?;
-        }
-
         let btc_tx = self.checkpoints.get(store, cp_index)?.checkpoint_tx()?;
-        if txids[0] != btc_tx.txid() {
-            return Err(ContractError::App(
-                "Bitcoin merkle proof does not match transaction".to_string(),
-            ))?;
-        }
+        verify_tx_proof(
+            &btc_tx,
+            &btc_proof,
+            &btc_header,
+            btc_coinbase_proof.as_ref(),
+        )?;
 
         CONFIRMED_INDEX.save(store, &cp_index)?;
         #[cfg(debug_assertions)]
@@ -478,8 +837,8 @@ impl Bitcoin {
 
     /// The network (e.g. Bitcoin testnet vs mainnet) which is currently
     /// configured.
-    pub fn network(&self) -> bitcoin::Network {
-        self.headers.network()
+    pub fn network(&self, store: &dyn Storage) -> ContractResult<bitcoin::Network> {
+        self.headers.network(store)
     }
 
     /// Gets the rate of change of the reserve output and signatory set over the
@@ -564,11 +923,17 @@ impl Bitcoin {
 
     /// Called once per sidechain block to advance the checkpointing process.        
     /// Can add to clock module
+    ///
+    /// `minted` are the nBTC transfers being credited in the same block, which
+    /// are not yet reflected in account balances when creating the emergency
+    /// disbursal of a checkpoint.
     pub fn begin_block_step(
         &mut self,
         env: &Env,
         store: &mut dyn Storage,
+        querier: &QuerierWrapper,
         timestamping_commitment: Vec<u8>,
+        minted: &[(Dest, Coin)],
     ) -> ContractResult<Vec<ConsensusKey>> {
         let config = self.config(store)?;
         let has_completed_cp =
@@ -590,6 +955,9 @@ impl Bitcoin {
 
         let btc_height = self.headers.height(store)?;
 
+        self.checkpoints
+            .maybe_fallback_to_script_path(env, store, &config)?;
+
         let pushed = self.checkpoints.maybe_step(
             env,
             store,
@@ -597,6 +965,8 @@ impl Bitcoin {
             !reached_capacity_limit,
             timestamping_commitment,
             &config,
+            querier,
+            minted,
         )?;
 
         // TODO: remove expired outpoints from processed_outpoints
@@ -694,6 +1064,7 @@ impl Bitcoin {
             confirmed_dests.push(checkpoint.pending);
             // clear pending
             checkpoint.pending = vec![];
+            checkpoint.pending_outpoints = vec![];
             self.checkpoints.set(store, *confirmed_index, &checkpoint)?;
         }
         if let Some(last_index) = unhandled_confirmed_cps.last() {
@@ -733,6 +1104,7 @@ impl Bitcoin {
             let mut checkpoint = self.checkpoints.get(store, checkpoint_index)?;
             completed_dests.push(checkpoint.pending);
             checkpoint.pending = vec![]; // clear pointer
+            checkpoint.pending_outpoints = vec![];
             self.checkpoints.set(store, checkpoint_index, &checkpoint)?;
         }
 
@@ -762,6 +1134,33 @@ impl Bitcoin {
         Ok(())
     }
 
+    /// Takes back a miner fee given to the fee pool and to the passed
+    /// `Building` checkpoint, e.g. when the deposit which paid it is undone.
+    ///
+    /// If the fee pool no longer holds the whole fee, e.g. after paying out
+    /// header rewards, only what it holds is taken back so it never goes
+    /// negative. The fee is still removed from the checkpoint in full, since
+    /// the deposit no longer contributes to it.
+    fn take_miner_fee(
+        &mut self,
+        store: &mut dyn Storage,
+        checkpoint: &mut Checkpoint,
+        amount: Uint128,
+    ) -> ContractResult<()> {
+        let config = self.config(store)?;
+        let amount: u64 = amount.u128() as u64;
+
+        let mut fee_pool = self.fee_pool(store)?;
+        fee_pool -= fee_pool.clamp(0, amount as i64);
+        FEE_POOL.save(store, &fee_pool)?;
+
+        checkpoint.fees_collected = checkpoint
+            .fees_collected
+            .saturating_sub(amount / config.units_per_sat);
+
+        Ok(())
+    }
+
     // TODO: reward pool ...
     // pub fn give_rewards(&mut self, store: &mut dyn Storage, amount: Uint128) -> ContractResult<()> {
     //     let config = self.config(store)?;
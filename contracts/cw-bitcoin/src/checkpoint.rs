@@ -1,23 +1,36 @@
 use super::{
-    signatory::SignatorySet,
-    threshold_sig::{Signature, ThresholdSig},
+    musig::MusigSession,
+    signatory::{ScriptType, SignatorySet},
+    threshold_sig::{Pubkey, Signature, ThresholdSig},
 };
-use crate::{adapter::Adapter, interface::Xpub, state::BUILDING_INDEX};
+use crate::{adapter::Adapter, app::RelayedDeposit, interface::Xpub, state::BUILDING_INDEX};
 use crate::{
-    constants::DEFAULT_FEE_RATE,
+    constants::{DEFAULT_FEE_RATE, MUSIG_SIGNING_TIMEOUT, RECOVERY_BALANCES_PER_BLOCK},
     error::{ContractError, ContractResult},
-    state::{CHECKPOINT_CONFIG, CONFIRMED_INDEX, FEE_POOL, FIRST_UNHANDLED_CONFIRMED_INDEX},
+    state::{
+        get_full_btc_denom, CHECKPOINT_CONFIG, CONFIG, CONFIRMED_INDEX, FEE_POOL,
+        FIRST_UNHANDLED_CONFIRMED_INDEX, RECOVERY_BALANCES, RECOVERY_BALANCES_CURSOR,
+        RECOVERY_SCRIPTS,
+    },
 };
 use crate::{
-    interface::{BitcoinConfig, CheckpointConfig, Dest},
+    interface::{BitcoinConfig, CheckpointConfig, Dest, EmergencyDisbursalConfig},
     state::CHECKPOINTS,
 };
+use bitcoin::blockdata::opcodes::all::OP_IF;
 use bitcoin::hashes::Hash;
+use bitcoin::util::sighash::{Prevouts, SchnorrSighashType, SighashCache};
+use bitcoin::util::taproot::{LeafVersion, TapLeafHash};
 use bitcoin::{blockdata::transaction::EcdsaSighashType, Sequence, Transaction, TxIn, TxOut};
 use cosmwasm_schema::serde::{Deserialize, Serialize};
 use cosmwasm_schema::{cw_serde, schemars::JsonSchema};
-use cosmwasm_std::{Api, Coin, Env, Storage};
+use cosmwasm_std::{
+    Addr, Api, Binary, Coin, Env, Order, QuerierWrapper, StdResult, Storage, Uint128,
+};
+use cw_storage_plus::Bound;
 use derive_more::{Deref, DerefMut};
+use libsecp256k1_core::curve::ECMultContext;
+use std::collections::BTreeMap;
 
 /// The status of a checkpoint. Checkpoints start as `Building`, and eventually
 /// advance through the three states.
@@ -54,14 +67,21 @@ pub struct Input {
 
     /// The script of the output being spent by this input. In practice, this
     /// will be a pay-to-witness-script-hash (P2WSH) script, containing the hash
-    /// of the script in the `redeem_script` field.
+    /// of the script in the `redeem_script` field, or a pay-to-taproot (P2TR)
+    /// script committing to it as a leaf.
     pub script_pubkey: Adapter<bitcoin::Script>,
 
-    /// The redeem script which `script_pubkey` contains the hash of, supplied
-    /// in the witness of the input when spending. In practice, this will
-    /// represent a multisig tied to the associated signatory set.
+    /// The redeem script which `script_pubkey` commits to, supplied in the
+    /// witness of the input when spending. In practice, this will represent a
+    /// multisig tied to the associated signatory set.
     pub redeem_script: Adapter<bitcoin::Script>,
 
+    /// The taproot control block proving `redeem_script` is a leaf of
+    /// `script_pubkey`, supplied after it in the witness. This is `None` for
+    /// P2WSH inputs.
+    #[serde(default)]
+    pub control_block: Option<Vec<u8>>,
+
     /// The index of the signatory set which this input is associated with.
     pub sigset_index: u32,
 
@@ -89,8 +109,17 @@ impl Input {
     /// actual Bitcoin transaction to be broadcast.
     pub fn to_txin(&self) -> ContractResult<TxIn> {
         let mut witness = self.signatures.to_witness()?;
-        if self.signatures.signed() {
+        // Key-path spends only contain the signature.
+        if self.signatures.signed() && self.signatures.musig.is_none() {
+            // Scripts with a backup branch start with `OP_IF`, take the
+            // signatory branch.
+            if self.redeem_script.as_bytes().first() == Some(&OP_IF.to_u8()) {
+                witness.push(vec![1]);
+            }
             witness.push(self.redeem_script.to_bytes());
+            if let Some(control_block) = &self.control_block {
+                witness.push(control_block.clone());
+            }
         }
 
         Ok(bitcoin::TxIn {
@@ -104,32 +133,99 @@ impl Input {
     /// Creates an `Input` which spends the given Bitcoin outpoint, populating
     /// it with an empty signing state to be signed by the given signatory set.
     pub fn new(
+        ctx: &ECMultContext,
         prevout: bitcoin::OutPoint,
         sigset: &SignatorySet,
         dest: &[u8],
         amount: u64,
         threshold: (u64, u64),
     ) -> ContractResult<Self> {
-        let script_pubkey = sigset.output_script(dest, threshold)?;
+        let script_pubkey = sigset.output_script(ctx, dest, threshold)?;
         let redeem_script = sigset.redeem_script(dest, threshold)?;
+        let control_block = sigset.control_block(ctx, dest, threshold)?;
+
+        let mut signatures = ThresholdSig::from_sigset(sigset);
+        if sigset.script_type == ScriptType::Musig2 {
+            let tweak = sigset.taproot_tweak(ctx, dest, threshold)?;
+            signatures.musig = Some(MusigSession::new(tweak, sigset.len()));
+        }
 
         Ok(Input {
             prevout: Adapter::new(prevout),
             script_pubkey: Adapter::new(script_pubkey),
             redeem_script: Adapter::new(redeem_script),
+            control_block,
             sigset_index: sigset.index(),
             dest: dest.to_vec(),
             amount,
             est_witness_vsize: sigset.est_witness_vsize(),
-            signatures: ThresholdSig::from_sigset(sigset),
+            signatures,
         })
     }
 
+    /// Makes an input spending an aggregated-key output be spent through the
+    /// multisig leaf instead of the key path, so that it can be signed by a
+    /// threshold of the signatories rather than all of them. This has no effect
+    /// on other inputs.
+    pub fn spend_script_path(mut self, sigset: &SignatorySet) -> Self {
+        if self.signatures.musig.take().is_some() {
+            self.est_witness_vsize = sigset.est_script_path_witness_vsize();
+        }
+        self
+    }
+
     /// The estimated size of the input, including the worst-case size of the
     /// witness once fully signed, in virtual bytes.
     pub fn est_vsize(&self) -> u64 {
         self.est_witness_vsize + 40
     }
+
+    /// The output being spent by this input.
+    pub fn prevout_txout(&self) -> TxOut {
+        TxOut {
+            value: self.amount,
+            script_pubkey: self.script_pubkey.clone().into_inner(),
+        }
+    }
+
+    /// Calculates the sighash to be signed for this input, which is at the
+    /// given index of the transaction. Taproot inputs use the BIP341 sighash,
+    /// which commits to the outputs spent by all inputs in `prevouts`, while
+    /// P2WSH inputs use the BIP143 sighash.
+    fn sighash(
+        &self,
+        sc: &mut SighashCache<&Transaction>,
+        input_index: usize,
+        prevouts: &[TxOut],
+    ) -> ContractResult<[u8; 32]> {
+        if self.signatures.musig.is_some() {
+            let sighash = sc.taproot_key_spend_signature_hash(
+                input_index,
+                &Prevouts::All(prevouts),
+                SchnorrSighashType::Default,
+            )?;
+            return Ok(sighash.into_inner());
+        }
+
+        if self.control_block.is_some() {
+            let leaf_hash = TapLeafHash::from_script(&self.redeem_script, LeafVersion::TapScript);
+            let sighash = sc.taproot_script_spend_signature_hash(
+                input_index,
+                &Prevouts::All(prevouts),
+                leaf_hash,
+                SchnorrSighashType::Default,
+            )?;
+            return Ok(sighash.into_inner());
+        }
+
+        let sighash = sc.segwit_signature_hash(
+            input_index,
+            &self.redeem_script,
+            self.amount,
+            EcdsaSighashType::All,
+        )?;
+        Ok(sighash.into_inner())
+    }
 }
 
 /// A bitcoin transaction output, wrapped to implement the core `orga` traits.
@@ -159,14 +255,24 @@ pub struct BitcoinTx {
 impl BitcoinTx {
     /// Converts the `BitcoinTx` to a `bitcoin::Transaction`.
     pub fn to_bitcoin_tx(&self) -> ContractResult<Transaction> {
+        let mut input: Vec<TxIn> = self
+            .input
+            .iter()
+            .map(|input| input.to_txin())
+            .collect::<ContractResult<_>>()?;
+
+        // The locktime is only enforced if at least one input has a non-final
+        // sequence number.
+        if self.lock_time != 0 {
+            for txin in input.iter_mut() {
+                txin.sequence = Sequence::ENABLE_LOCKTIME_NO_RBF;
+            }
+        }
+
         Ok(bitcoin::Transaction {
             version: 1,
             lock_time: bitcoin::PackedLockTime(self.lock_time),
-            input: self
-                .input
-                .iter()
-                .map(|input| input.to_txin())
-                .collect::<ContractResult<_>>()?,
+            input,
             output: self
                 .output
                 .iter()
@@ -222,20 +328,15 @@ impl BitcoinTx {
     /// coordination of signing will begin.
     pub fn populate_input_sig_message(&mut self, input_index: usize) -> ContractResult<()> {
         let bitcoin_tx = self.to_bitcoin_tx()?;
-        let mut sc = bitcoin::util::sighash::SighashCache::new(&bitcoin_tx);
+        let mut sc = SighashCache::new(&bitcoin_tx);
+        let prevouts: Vec<_> = self.input.iter().map(Input::prevout_txout).collect();
         let input = self
             .input
             .get_mut(input_index)
             .ok_or(ContractError::InputIndexOutOfBounds(input_index))?;
 
-        let sighash = sc.segwit_signature_hash(
-            input_index,
-            &input.redeem_script,
-            input.amount,
-            EcdsaSighashType::All,
-        )?;
-
-        input.signatures.set_message(sighash.into_inner());
+        let sighash = input.sighash(&mut sc, input_index, &prevouts)?;
+        input.signatures.set_message(sighash);
 
         Ok(())
     }
@@ -308,6 +409,23 @@ impl BitcoinTx {
 /// checkpoint.
 #[derive(Debug)]
 pub enum BatchType {
+    /// The batch containing the "final emergency disbursal transactions".
+    ///
+    /// This batch will contain one or more transactions, each of which spends
+    /// an output of the intermediate emergency disbursal transaction and pays
+    /// out to the recovery scripts of nBTC account holders. It is empty if
+    /// there was nothing to disburse when the checkpoint advanced to `Signing`.
+    Disbursal,
+
+    /// The batch containing the "intermediate emergency disbursal
+    /// transaction".
+    ///
+    /// This batch will contain at most one transaction, which spends the
+    /// reserve output of the checkpoint transaction once its locktime has
+    /// passed, and pays out to the signatory set in one output per final
+    /// emergency disbursal transaction.
+    IntermediateTx,
+
     /// The batch containing the checkpoint transaction. This batch will always
     /// contain exactly one transaction, the "checkpoint transaction".
     ///
@@ -331,6 +449,26 @@ impl<T> std::ops::IndexMut<BatchType> for Vec<T> {
     }
 }
 
+/// The data a signatory needs to create a MuSig2 partial signature for a
+/// key-path input.
+#[cw_serde]
+pub struct MusigSignRequest {
+    /// The sighash to be signed.
+    pub message: [u8; 32],
+
+    /// The index of the signatory set which the input is associated with.
+    pub sigset_index: u32,
+
+    /// The pubkeys of the signatories, in key aggregation order.
+    pub pubkeys: Vec<Pubkey>,
+
+    /// The x-only taproot tweak applied to the aggregate key.
+    pub tweak: Binary,
+
+    /// The public nonces of the signatories, in the same order as `pubkeys`.
+    pub nonces: Vec<Binary>,
+}
+
 /// A batch of transactions in a checkpoint.
 ///
 /// A batch is a collection of transactions which are atomically signed
@@ -392,8 +530,17 @@ pub struct Checkpoint {
     /// These transfers can be initiated by a simple nBTC send or by a deposit.    
     pub pending: Vec<(Dest, Coin)>,
 
+    /// The outpoints of the relayed deposits which made the transfers in
+    /// `pending`, by position, so the transfer of a deposit can be removed if
+    /// its block is reorged out of the best chain. `None` for transfers which
+    /// were not made by a deposit.
+    #[serde(default)]
+    pub pending_outpoints: Vec<Option<Adapter<bitcoin::OutPoint>>>,
+
     /// The batches of transactions in the checkpoint, to each be signed
-    /// atomically, in order. Currently we have only one batch which is
+    /// atomically, in order. The first batch contains the "final emergency
+    /// disbursal transactions", the second contains the "intermediate
+    /// emergency disbursal transaction", and the third contains the
     /// "checkpoint transaction".
     pub batches: Vec<Batch>,
 
@@ -430,9 +577,8 @@ impl Checkpoint {
     /// Creates a new checkpoint with the given signatory set.
     ///
     /// The checkpoint will be initialized with a single empty checkpoint
-    /// transaction, a single empty intermediate emergency disbursal
-    /// transaction, and an empty batch of final emergency disbursal
-    /// transactions.
+    /// transaction, and empty batches of emergency disbursal transactions which
+    /// are filled in once the checkpoint advances to `Signing`.
     pub fn new(sigset: SignatorySet) -> ContractResult<Self> {
         let mut checkpoint = Checkpoint {
             status: CheckpointStatus::default(),
@@ -442,9 +588,16 @@ impl Checkpoint {
             sigset,
             fees_collected: 0,
             pending: vec![],
+            pending_outpoints: vec![],
             batches: vec![],
         };
 
+        let disbursal_batch = Batch::default();
+        checkpoint.batches.push(disbursal_batch);
+
+        let intermediate_tx_batch = Batch::default();
+        checkpoint.batches.push(intermediate_tx_batch);
+
         let checkpoint_tx = BitcoinTx::default();
         let mut checkpoint_batch = Batch::default();
         checkpoint_batch.push(checkpoint_tx);
@@ -476,12 +629,84 @@ impl Checkpoint {
     fn sign(
         &mut self,
         api: &dyn Api,
+        ctx: &ECMultContext,
         xpub: &Xpub,
         sigs: Vec<Signature>,
         btc_height: u32,
     ) -> ContractResult<()> {
         let cp_was_signed = self.signed();
-        let mut sig_index = 0;
+
+        self.apply_to_inputs(
+            xpub,
+            &sigs,
+            "signatures",
+            |signatures, pubkey| signatures.needs_sig(pubkey),
+            |signatures, pubkey, sig| signatures.sign(api, ctx, pubkey, sig),
+        )?;
+
+        // If these signatures made the checkpoint fully signed, record the
+        // height at which it was signed.
+        if self.signed() && !cp_was_signed {
+            self.signed_at_btc_height = Some(btc_height);
+        }
+
+        Ok(())
+    }
+
+    /// Processes a batch of MuSig2 public nonces from a signatory, applying
+    /// them to the key-path inputs of transaction batches which are ready to
+    /// be signed, in the same way as `sign()`.
+    fn add_nonces(&mut self, xpub: &Xpub, nonces: Vec<Binary>) -> ContractResult<()> {
+        self.apply_to_inputs(
+            xpub,
+            &nonces,
+            "nonces",
+            |signatures, pubkey| signatures.needs_nonce(pubkey),
+            |signatures, pubkey, nonce| signatures.add_nonce(pubkey, nonce),
+        )
+    }
+
+    /// Processes a batch of MuSig2 partial signatures from a signatory,
+    /// applying them to the key-path inputs of transaction batches which are
+    /// ready to be signed and have received all nonces, in the same way as
+    /// `sign()`.
+    fn add_partial_sigs(
+        &mut self,
+        ctx: &ECMultContext,
+        xpub: &Xpub,
+        partial_sigs: Vec<Binary>,
+        btc_height: u32,
+    ) -> ContractResult<()> {
+        let cp_was_signed = self.signed();
+
+        self.apply_to_inputs(
+            xpub,
+            &partial_sigs,
+            "partial signatures",
+            |signatures, pubkey| signatures.needs_partial_sig(pubkey),
+            |signatures, pubkey, partial_sig| signatures.add_partial_sig(ctx, pubkey, partial_sig),
+        )?;
+
+        if self.signed() && !cp_was_signed {
+            self.signed_at_btc_height = Some(btc_height);
+        }
+
+        Ok(())
+    }
+
+    /// Applies the items submitted by a signatory, one per input for which
+    /// `needs` returns `true`, to the inputs of transaction batches which are
+    /// ready to be signed, and updates the counters of signed inputs and
+    /// transactions.
+    fn apply_to_inputs<T>(
+        &mut self,
+        xpub: &Xpub,
+        items: &[T],
+        kind: &str,
+        needs: impl Fn(&ThresholdSig, Pubkey) -> bool,
+        mut apply: impl FnMut(&mut ThresholdSig, Pubkey, &T) -> ContractResult<()>,
+    ) -> ContractResult<()> {
+        let mut item_index = 0;
 
         // Iterate over all batches in the checkpoint, breaking once iterating
         // to a batch which is not ready to be signed.
@@ -495,41 +720,39 @@ impl Checkpoint {
                 // Iterate over all inputs in the transaction.
                 for k in 0..tx.input.len() {
                     let input = &mut tx.input[k];
-                    let pubkey = xpub.derive_pubkey(input.sigset_index)?;
+                    let pubkey: Pubkey = xpub.derive_pubkey(input.sigset_index)?.into();
 
                     // Skip input if either the signatory is not part of this
                     // input's signatory set, or the signatory has already
-                    // submitted a signature for this input.
-                    if !input.signatures.needs_sig(pubkey.into()) {
+                    // submitted an item for this input.
+                    if !needs(&input.signatures, pubkey.clone()) {
                         continue;
                     }
 
-                    // Error if there are no remaining supplied signatures - the
-                    // signatory supplied less signatures than we require from
-                    // them.
-                    if sig_index >= sigs.len() {
-                        return Err(ContractError::Checkpoint(
-                            "Not enough signatures supplied".into(),
-                        ));
+                    // Error if there are no remaining supplied items - the
+                    // signatory supplied less items than we require from them.
+                    if item_index >= items.len() {
+                        return Err(ContractError::Checkpoint(format!(
+                            "Not enough {} supplied",
+                            kind
+                        )));
                     }
-                    let sig = &sigs[sig_index];
-                    sig_index += 1;
+                    let item = &items[item_index];
+                    item_index += 1;
 
-                    // Apply the signature.
+                    // Apply the item.
                     let input_was_signed = input.signatures.signed();
-                    input.signatures.sign(api, pubkey.into(), sig)?;
+                    apply(&mut input.signatures, pubkey, item)?;
 
-                    // If this signature made the input fully signed, increase
-                    // the counter of fully-signed inputs in the containing
-                    // transaction.
+                    // If this made the input fully signed, increase the counter
+                    // of fully-signed inputs in the containing transaction.
                     if !input_was_signed && input.signatures.signed() {
                         tx.signed_inputs += 1;
                     }
                 }
 
-                // If these signatures made the transaction fully signed,
-                // increase the counter of fully-signed transactions in the
-                // containing batch.
+                // If this made the transaction fully signed, increase the
+                // counter of fully-signed transactions in the containing batch.
                 if !tx_was_signed && tx.signed() {
                     batch.signed_txs += 1;
                 }
@@ -541,18 +764,13 @@ impl Checkpoint {
             }
         }
 
-        // Error if there are remaining supplied signatures - the signatory
-        // supplied more signatures than we require from them.
-        if sig_index != sigs.len() {
-            return Err(ContractError::Checkpoint(
-                "Excess signatures supplied".into(),
-            ));
-        }
-
-        // If these signatures made the checkpoint fully signed, record the
-        // height at which it was signed.
-        if self.signed() && !cp_was_signed {
-            self.signed_at_btc_height = Some(btc_height);
+        // Error if there are remaining supplied items - the signatory supplied
+        // more items than we require from them.
+        if item_index != items.len() {
+            return Err(ContractError::Checkpoint(format!(
+                "Excess {} supplied",
+                kind
+            )));
         }
 
         Ok(())
@@ -594,23 +812,71 @@ impl Checkpoint {
     pub fn to_sign(&self, xpub: &Xpub) -> ContractResult<Vec<([u8; 32], u32)>> {
         let mut msgs = vec![];
 
-        for batch in &self.batches {
-            for tx in &batch.batch {
-                for input in &tx.input {
-                    let pubkey = xpub.derive_pubkey(input.sigset_index)?;
-                    if input.signatures.needs_sig(pubkey.into()) {
-                        msgs.push((input.signatures.message(), input.sigset_index));
-                    }
-                }
+        for input in self.ready_inputs() {
+            let pubkey = xpub.derive_pubkey(input.sigset_index)?;
+            if input.signatures.needs_sig(pubkey.into()) {
+                msgs.push((input.signatures.message(), input.sigset_index));
             }
-            if !batch.signed() {
-                break;
+        }
+
+        Ok(msgs)
+    }
+
+    /// Returns a list of all key-path inputs in the checkpoint for which the
+    /// signatory with the given extended public key should submit a MuSig2
+    /// public nonce, as `(sighash, sigset_index)` tuples.
+    pub fn nonces_to_submit(&self, xpub: &Xpub) -> ContractResult<Vec<([u8; 32], u32)>> {
+        let mut msgs = vec![];
+
+        for input in self.ready_inputs() {
+            let pubkey = xpub.derive_pubkey(input.sigset_index)?;
+            if input.signatures.needs_nonce(pubkey.into()) {
+                msgs.push((input.signatures.message(), input.sigset_index));
             }
         }
 
         Ok(msgs)
     }
 
+    /// Returns a list of all key-path inputs in the checkpoint for which the
+    /// signatory with the given extended public key should submit a MuSig2
+    /// partial signature, along with the data needed to create it.
+    pub fn partial_sigs_to_submit(&self, xpub: &Xpub) -> ContractResult<Vec<MusigSignRequest>> {
+        let mut requests = vec![];
+
+        for input in self.ready_inputs() {
+            let pubkey = xpub.derive_pubkey(input.sigset_index)?;
+            if !input.signatures.needs_partial_sig(pubkey.into()) {
+                continue;
+            }
+            let session = input.signatures.musig.as_ref().unwrap();
+            requests.push(MusigSignRequest {
+                message: input.signatures.message(),
+                sigset_index: input.sigset_index,
+                pubkeys: input
+                    .signatures
+                    .shares()
+                    .into_iter()
+                    .map(|(pubkey, _)| pubkey)
+                    .collect(),
+                tweak: session.tweak.clone(),
+                nonces: session.nonces.iter().flatten().cloned().collect(),
+            });
+        }
+
+        Ok(requests)
+    }
+
+    /// An iterator over the inputs of all transaction batches which are ready
+    /// to be signed (see `sign()`).
+    fn ready_inputs(&self) -> impl Iterator<Item = &Input> {
+        let ready_batches = (self.signed_batches() + 1).min(self.batches.len());
+        self.batches[..ready_batches]
+            .iter()
+            .flat_map(|batch| batch.iter())
+            .flat_map(|tx| tx.input.iter())
+    }
+
     /// Returns the number of fully-signed batches in the checkpoint.
     fn signed_batches(&self) -> usize {
         let mut signed_batches = 0;
@@ -666,15 +932,17 @@ impl Checkpoint {
 
     pub fn base_fee(
         &self,
+        ctx: &ECMultContext,
         config: &CheckpointConfig,
         timestamping_commitment: &[u8],
     ) -> ContractResult<u64> {
-        let est_vsize = self.est_vsize(config, timestamping_commitment)?;
+        let est_vsize = self.est_vsize(ctx, config, timestamping_commitment)?;
         Ok(est_vsize * self.fee_rate)
     }
 
     fn est_vsize(
         &self,
+        ctx: &ECMultContext,
         config: &CheckpointConfig,
         timestamping_commitment: &[u8],
     ) -> ContractResult<u64> {
@@ -683,7 +951,7 @@ impl Checkpoint {
         let mut tx = cp.to_bitcoin_tx()?;
 
         tx.output = self
-            .additional_outputs(config, timestamping_commitment)?
+            .additional_outputs(ctx, config, timestamping_commitment)?
             .into_iter()
             .chain(tx.output)
             .take(config.max_outputs as usize)
@@ -735,6 +1003,7 @@ impl Checkpoint {
 
     fn additional_outputs(
         &self,
+        ctx: &ECMultContext,
         config: &CheckpointConfig,
         timestamping_commitment: &[u8],
     ) -> ContractResult<Vec<bitcoin::TxOut>> {
@@ -742,7 +1011,9 @@ impl Checkpoint {
         // contains all funds held in reserve by the network.
         let reserve_out = bitcoin::TxOut {
             value: 0, // will be updated after counting ins/outs and fees
-            script_pubkey: self.sigset.output_script(&[0u8], config.sigset_threshold)?,
+            script_pubkey: self
+                .sigset
+                .output_script(ctx, &[0u8], config.sigset_threshold)?,
         };
 
         // The timestamping commitment output is the second output of the
@@ -799,11 +1070,12 @@ impl SigningCheckpoint {
     pub fn sign(
         &mut self,
         api: &dyn Api,
+        ctx: &ECMultContext,
         xpub: Xpub,
         sigs: Vec<Signature>,
         btc_height: u32,
     ) -> ContractResult<()> {
-        self.0.sign(api, &xpub, sigs, btc_height)?;
+        self.0.sign(api, ctx, &xpub, sigs, btc_height)?;
         Ok(())
     }
 }
@@ -835,13 +1107,14 @@ impl BuildingCheckpoint {
     /// change.    
     pub fn advance(
         &mut self,
+        ctx: &ECMultContext,
         timestamping_commitment: Vec<u8>,
         cp_fees: u64,
         config: &CheckpointConfig,
     ) -> ContractResult<BuildingAdvanceRes> {
         self.0.status = CheckpointStatus::Signing;
 
-        let outs = self.additional_outputs(config, &timestamping_commitment)?;
+        let outs = self.additional_outputs(ctx, config, &timestamping_commitment)?;
         let checkpoint_batch = &mut self.batches[BatchType::Checkpoint];
         let checkpoint_tx = &mut checkpoint_batch[0];
         for out in outs.iter().rev() {
@@ -885,16 +1158,16 @@ impl BuildingCheckpoint {
         // Prepare the checkpoint tx's inputs to be signed by calculating their
         // sighashes.
         let bitcoin_tx = checkpoint_tx.to_bitcoin_tx()?;
-        let mut sc = bitcoin::util::sighash::SighashCache::new(&bitcoin_tx);
+        let mut sc = SighashCache::new(&bitcoin_tx);
+        let prevouts: Vec<_> = checkpoint_tx
+            .input
+            .iter()
+            .map(Input::prevout_txout)
+            .collect();
         for i in 0..checkpoint_tx.input.len() {
             let input = &mut checkpoint_tx.input[i];
-            let sighash = sc.segwit_signature_hash(
-                i,
-                &input.redeem_script,
-                input.amount,
-                EcdsaSighashType::All,
-            )?;
-            input.signatures.set_message(sighash.into_inner());
+            let sighash = input.sighash(&mut sc, i, &prevouts)?;
+            input.signatures.set_message(sighash);
         }
 
         let reserve_outpoint = bitcoin::OutPoint {
@@ -911,6 +1184,152 @@ impl BuildingCheckpoint {
         ))
     }
 
+    /// Creates the emergency disbursal transactions of the checkpoint, which
+    /// spend its reserve output to pay the given outputs once `lock_time` has
+    /// passed.
+    ///
+    /// The reserve output is spent by the intermediate transaction, which has
+    /// one output for each final transaction so that the final transactions
+    /// can be kept within the maximum transaction size, and a change output
+    /// back to the signatory set for any funds which are not disbursed. If the
+    /// reserve is not large enough to pay all outputs, they are reduced in
+    /// proportion to their values.
+    ///
+    /// Outputs below `min_tx_amt`, including after being reduced, and outputs
+    /// which can not pay their share of the miner fee are left out. If there
+    /// are no outputs to pay, the disbursal batches are left empty.
+    pub fn generate_emergency_disbursal_txs(
+        &mut self,
+        ctx: &ECMultContext,
+        lock_time: u32,
+        reserve_outpoint: bitcoin::OutPoint,
+        reserve_value: u64,
+        outputs: Vec<TxOut>,
+        config: &CheckpointConfig,
+        disbursal_config: &EmergencyDisbursalConfig,
+    ) -> ContractResult<()> {
+        let threshold = config.sigset_threshold;
+        let reserve_script = self.sigset.output_script(ctx, &[0u8], threshold)?;
+        let input = Input::new(ctx, reserve_outpoint, &self.sigset, &[0u8], 0, threshold)?;
+
+        // Split the outputs into final transactions, each of which spends an
+        // output of the intermediate transaction. Their inputs are linked once
+        // the intermediate transaction is complete.
+        let mut final_txs: Vec<BitcoinTx> = vec![];
+        for output in outputs {
+            if output.value < disbursal_config.min_tx_amt {
+                continue;
+            }
+
+            let is_full = match final_txs.last() {
+                Some(tx) => tx.est_vsize()? >= disbursal_config.max_tx_size,
+                None => true,
+            };
+            if is_full {
+                let mut tx = BitcoinTx::with_lock_time(lock_time);
+                tx.input.push(input.clone());
+                final_txs.push(tx);
+            }
+            final_txs
+                .last_mut()
+                .unwrap()
+                .output
+                .push(Adapter::new(output));
+        }
+
+        if final_txs.is_empty() {
+            return Ok(());
+        }
+
+        let mut intermediate_tx = BitcoinTx::with_lock_time(lock_time);
+        let mut reserve_input = input;
+        reserve_input.amount = reserve_value;
+        intermediate_tx.input.push(reserve_input);
+        // One output per final transaction, followed by the change output.
+        for _ in 0..=final_txs.len() {
+            intermediate_tx.output.push(Adapter::new(TxOut {
+                value: 0,
+                script_pubkey: reserve_script.clone(),
+            }));
+        }
+        let intermediate_tx_fee = intermediate_tx.est_vsize()? * self.fee_rate;
+
+        // Reduce the outputs in proportion to their values if the reserve can
+        // not pay all of them.
+        let available = reserve_value.saturating_sub(intermediate_tx_fee);
+        let total = final_txs
+            .iter()
+            .try_fold(0, |sum, tx| Ok::<_, ContractError>(sum + tx.value()?))?;
+        if total > available {
+            for tx in final_txs.iter_mut() {
+                for output in tx.output.iter_mut() {
+                    output.value =
+                        (output.value as u128 * available as u128 / total as u128) as u64;
+                }
+            }
+        }
+
+        // Pay the miner fees of the final transactions from their own outputs.
+        // Outputs which fell below the minimum amount when reduced, or which
+        // can not pay their share of the fee, are dropped and their funds are
+        // left in the change output.
+        let mut kept_txs = vec![];
+        for mut tx in final_txs {
+            tx.output.retain(|output| {
+                output.value >= disbursal_config.min_tx_amt
+                    && output.value > output.script_pubkey.dust_value().to_sat()
+            });
+            if tx.output.is_empty() {
+                continue;
+            }
+
+            let fee = tx.est_vsize()? * self.fee_rate;
+            tx.deduct_fee(fee)?;
+            if tx.output.is_empty() {
+                continue;
+            }
+            tx.input[0].amount = tx.value()? + fee;
+            kept_txs.push(tx);
+        }
+        let mut final_txs = kept_txs;
+        if final_txs.is_empty() {
+            return Ok(());
+        }
+        intermediate_tx.output.truncate(final_txs.len() + 1);
+
+        // The fee of the intermediate transaction is smaller if final
+        // transactions were dropped.
+        let intermediate_tx_fee = intermediate_tx.est_vsize()? * self.fee_rate;
+        let mut change = reserve_value.saturating_sub(intermediate_tx_fee);
+        for (i, tx) in final_txs.iter().enumerate() {
+            let value = tx.input[0].amount;
+            intermediate_tx.output[i].value = value;
+            change = change.saturating_sub(value);
+        }
+        if change > reserve_script.dust_value().to_sat() {
+            intermediate_tx.output.last_mut().unwrap().value = change;
+        } else {
+            intermediate_tx.output.pop();
+        }
+        intermediate_tx.populate_input_sig_message(0)?;
+
+        // Link the final transactions to the outputs of the intermediate
+        // transaction.
+        let intermediate_txid = intermediate_tx.txid()?;
+        for (i, tx) in final_txs.iter_mut().enumerate() {
+            tx.input[0].prevout = Adapter::new(bitcoin::OutPoint {
+                txid: intermediate_txid,
+                vout: i as u32,
+            });
+            tx.populate_input_sig_message(0)?;
+        }
+
+        self.batches[BatchType::IntermediateTx].push(intermediate_tx);
+        self.batches[BatchType::Disbursal].extend(final_txs);
+
+        Ok(())
+    }
+
     /// Insert a transfer to the pending transfer queue.
     ///
     /// Transfers will be processed once the containing checkpoint is finished
@@ -920,6 +1339,63 @@ impl BuildingCheckpoint {
         self.pending.push((dest, coin));
         Ok(())
     }
+
+    /// Insert the transfer of a relayed deposit to the pending transfer queue,
+    /// keeping the outpoint of the deposit so the transfer can be removed with
+    /// `remove_deposit`.
+    pub fn insert_pending_deposit(
+        &mut self,
+        outpoint: bitcoin::OutPoint,
+        dest: Dest,
+        coin: Coin,
+    ) -> ContractResult<()> {
+        self.pending_outpoints.resize(self.pending.len(), None);
+        self.pending.push((dest, coin));
+        self.pending_outpoints.push(Some(Adapter::new(outpoint)));
+        Ok(())
+    }
+
+    /// Removes the input and the pending transfer which were added to the
+    /// checkpoint for a relayed deposit.
+    pub fn remove_deposit(&mut self, deposit: &RelayedDeposit) -> ContractResult<()> {
+        let checkpoint_tx = self.batches[BatchType::Checkpoint]
+            .get_mut(0)
+            .ok_or(ContractError::Checkpoint("Cannot get checkpoint tx".into()))?;
+        let input_index = checkpoint_tx
+            .input
+            .iter()
+            .position(|input| *input.prevout == *deposit.outpoint)
+            .ok_or(ContractError::Checkpoint("Deposit input not found".into()))?;
+        checkpoint_tx.input.remove(input_index);
+
+        let pending_index = self
+            .pending_outpoints
+            .iter()
+            .position(|outpoint| outpoint.as_deref() == Some(&*deposit.outpoint))
+            .or_else(|| {
+                // transfers inserted before their outpoints were kept
+                self.pending
+                    .iter()
+                    .enumerate()
+                    .position(|(i, (dest, coin))| {
+                        self.pending_outpoints
+                            .get(i)
+                            .and_then(Option::as_ref)
+                            .is_none()
+                            && *dest == deposit.dest
+                            && *coin == deposit.nbtc
+                    })
+            })
+            .ok_or(ContractError::Checkpoint(
+                "Deposit transfer not found".into(),
+            ))?;
+        self.pending.remove(pending_index);
+        if pending_index < self.pending_outpoints.len() {
+            self.pending_outpoints.remove(pending_index);
+        }
+
+        Ok(())
+    }
 }
 
 impl CheckpointQueue {
@@ -1119,6 +1595,27 @@ impl CheckpointQueue {
         }
     }
 
+    /// The emergency disbursal transactions of the last completed checkpoint,
+    /// the intermediate transaction followed by the final transactions.
+    ///
+    /// These can be broadcast once their locktime has passed, if the reserve
+    /// output of the checkpoint has not been spent by then.
+    pub fn emergency_disbursal_txs(
+        &self,
+        store: &dyn Storage,
+    ) -> ContractResult<Vec<Adapter<bitcoin::Transaction>>> {
+        let mut txs = vec![];
+        if let Some(completed) = self.completed(store, 1)?.last() {
+            let intermediate_tx_batch = &completed.batches[BatchType::IntermediateTx];
+            let disbursal_batch = &completed.batches[BatchType::Disbursal];
+            for tx in intermediate_tx_batch.iter().chain(disbursal_batch.iter()) {
+                txs.push(Adapter::new(tx.to_bitcoin_tx()?));
+            }
+        }
+
+        Ok(txs)
+    }
+
     /// A reference to the checkpoint in the `Signing` state, if there is one.
     pub fn signing(&self, store: &dyn Storage) -> ContractResult<Option<SigningCheckpoint>> {
         if self.len(store)? < 2 {
@@ -1187,8 +1684,16 @@ impl CheckpointQueue {
         timestamping_commitment: Vec<u8>,
         // fee_pool: &mut i64,
         parent_config: &BitcoinConfig,
+        querier: &QuerierWrapper,
+        minted: &[(Dest, Coin)],
     ) -> ContractResult<bool> {
-        let is_should_push = self.should_push(env, store, &timestamping_commitment, btc_height)?;
+        if self.config(store).emergency_disbursal.is_some() {
+            self.snapshot_recovery_balances(store, querier)?;
+        }
+
+        let ctx = ECMultContext::new_boxed();
+        let is_should_push =
+            self.should_push(env, store, &ctx, &timestamping_commitment, btc_height)?;
         if !is_should_push {
             return Ok(false);
         }
@@ -1204,7 +1709,8 @@ impl CheckpointQueue {
 
         if self.index(store) > 0 {
             let prev_index = self.index(store) - 1;
-            let cp_fees = self.calc_fee_checkpoint(store, prev_index, &timestamping_commitment)?;
+            let cp_fees =
+                self.calc_fee_checkpoint(store, &ctx, prev_index, &timestamping_commitment)?;
 
             let config = self.config(store);
             let prev = self.get(store, prev_index)?;
@@ -1212,7 +1718,26 @@ impl CheckpointQueue {
             let prev_fee_rate = prev.fee_rate;
             let mut building_checkpoint = BuildingCheckpoint(prev);
             let (reserve_outpoint, reserve_value, fees_paid, excess_inputs, excess_outputs) =
-                building_checkpoint.advance(timestamping_commitment, cp_fees, &config)?;
+                building_checkpoint.advance(&ctx, timestamping_commitment, cp_fees, &config)?;
+            if let Some(disbursal_config) = &config.emergency_disbursal {
+                let outputs = self.emergency_disbursal_outputs(
+                    store,
+                    &building_checkpoint,
+                    minted,
+                    parent_config.units_per_sat,
+                )?;
+                let lock_time =
+                    env.block.time.seconds() as u32 + disbursal_config.lock_time_interval;
+                building_checkpoint.generate_emergency_disbursal_txs(
+                    &ctx,
+                    lock_time,
+                    reserve_outpoint,
+                    reserve_value,
+                    outputs,
+                    &config,
+                    disbursal_config,
+                )?;
+            }
             // update checkpoint
             self.set(store, prev_index, &building_checkpoint)?;
 
@@ -1265,6 +1790,7 @@ impl CheckpointQueue {
             // The new checkpoint tx's first input is the reserve output from
             // the previous checkpoint.
             let input = Input::new(
+                &ctx,
                 reserve_outpoint,
                 &sigset,
                 &[0u8], // TODO: double-check safety
@@ -1279,6 +1805,12 @@ impl CheckpointQueue {
                 let shares = input.signatures.shares();
                 let mut data = input.clone();
                 data.signatures = ThresholdSig::from_shares(shares);
+                data.signatures.schnorr = input.signatures.schnorr;
+                data.signatures.musig = input
+                    .signatures
+                    .musig
+                    .as_ref()
+                    .map(|session| MusigSession::new(session.tweak(), session.nonces.len()));
                 checkpoint_tx.input.push(data);
             }
             for output in excess_outputs {
@@ -1292,6 +1824,89 @@ impl CheckpointQueue {
         Ok(true)
     }
 
+    /// The outputs of the emergency disbursal of the given checkpoint, paying
+    /// the nBTC balance of each account which registered a recovery script, as
+    /// of the snapshot kept by `snapshot_recovery_balances`.
+    ///
+    /// Balances include the pending transfers of the checkpoint and the
+    /// `minted` transfers being credited in the current block, since neither
+    /// is reflected in the account balances yet.
+    fn emergency_disbursal_outputs(
+        &self,
+        store: &dyn Storage,
+        checkpoint: &Checkpoint,
+        minted: &[(Dest, Coin)],
+        units_per_sat: u64,
+    ) -> ContractResult<Vec<TxOut>> {
+        let mut balances = RECOVERY_BALANCES
+            .range(store, None, None, Order::Ascending)
+            .collect::<StdResult<BTreeMap<Addr, Uint128>>>()?;
+
+        let denom = get_full_btc_denom(CONFIG.load(store)?.token_factory_addr.as_str());
+        for (dest, coin) in checkpoint.pending.iter().chain(minted) {
+            if let Dest::Address(addr) = dest {
+                if coin.denom == denom && RECOVERY_SCRIPTS.has(store, addr) {
+                    *balances.entry(addr.clone()).or_default() += coin.amount;
+                }
+            }
+        }
+
+        balances
+            .into_iter()
+            .map(|(addr, balance)| {
+                Ok(TxOut {
+                    value: (balance.u128() / units_per_sat as u128) as u64,
+                    script_pubkey: RECOVERY_SCRIPTS.load(store, &addr)?.into_inner(),
+                })
+            })
+            .collect()
+    }
+
+    /// Updates the snapshot of the nBTC balances of accounts with a recovery
+    /// script, which the emergency disbursal is created from, for up to
+    /// `RECOVERY_BALANCES_PER_BLOCK` accounts following the ones updated by the
+    /// previous call. Once the last account is updated, the next call starts
+    /// over from the first one.
+    ///
+    /// This bounds the number of balance queries made per block, so the
+    /// snapshot may lag behind the balance of an account by a full pass over
+    /// the recovery scripts. Accounts with a zero balance are left out.
+    pub fn snapshot_recovery_balances(
+        &self,
+        store: &mut dyn Storage,
+        querier: &QuerierWrapper,
+    ) -> ContractResult<()> {
+        let cursor = RECOVERY_BALANCES_CURSOR.may_load(store)?;
+        let addrs = RECOVERY_SCRIPTS
+            .keys(
+                store,
+                cursor.as_ref().map(Bound::exclusive),
+                None,
+                Order::Ascending,
+            )
+            .take(RECOVERY_BALANCES_PER_BLOCK)
+            .collect::<StdResult<Vec<_>>>()?;
+
+        let denom = get_full_btc_denom(CONFIG.load(store)?.token_factory_addr.as_str());
+        for addr in &addrs {
+            let balance = querier.query_balance(addr, &denom)?.amount;
+            if balance.is_zero() {
+                RECOVERY_BALANCES.remove(store, addr);
+            } else {
+                RECOVERY_BALANCES.save(store, addr, &balance)?;
+            }
+        }
+
+        match addrs.last() {
+            Some(last) if addrs.len() == RECOVERY_BALANCES_PER_BLOCK => {
+                RECOVERY_BALANCES_CURSOR.save(store, last)?
+            }
+            _ => RECOVERY_BALANCES_CURSOR.remove(store),
+        }
+
+        Ok(())
+    }
+
     /// Prunes old checkpoints from the queue.
     pub fn prune(&mut self, store: &mut dyn Storage) -> ContractResult<()> {
         let latest = self.building(store)?.create_time();
@@ -1317,6 +1932,7 @@ impl CheckpointQueue {
         &mut self,
         env: &Env,
         store: &dyn Storage,
+        ctx: &ECMultContext,
         timestamping_commitment: &[u8],
         btc_height: u32,
     ) -> ContractResult<bool> {
@@ -1347,7 +1963,7 @@ impl CheckpointQueue {
                 }
             }
             let cp_miner_fees =
-                self.calc_fee_checkpoint(store, self.index(store), timestamping_commitment)?;
+                self.calc_fee_checkpoint(store, ctx, self.index(store), timestamping_commitment)?;
             let building = self.building(store)?;
 
             // Don't push if there are no pending deposits, withdrawals, or
@@ -1439,12 +2055,13 @@ impl CheckpointQueue {
     pub fn calc_fee_checkpoint(
         &self,
         store: &dyn Storage,
+        ctx: &ECMultContext,
         cp_index: u32,
         timestamping_commitment: &[u8],
     ) -> ContractResult<u64> {
         let cp = self.get(store, cp_index)?;
-        let additional_fees = self.fee_adjustment(store, cp.fee_rate, &self.config(store))?;
-        let base_fee = cp.base_fee(&self.config(store), timestamping_commitment)?;
+        let additional_fees = self.fee_adjustment(store, ctx, cp.fee_rate, &self.config(store))?;
+        let base_fee = cp.base_fee(ctx, &self.config(store), timestamping_commitment)?;
         let total_fee = base_fee + additional_fees;
 
         Ok(total_fee)
@@ -1518,6 +2135,57 @@ impl CheckpointQueue {
         sigs: Vec<Signature>,
         index: u32,
         btc_height: u32,
+    ) -> ContractResult<()> {
+        let ctx = ECMultContext::new_boxed();
+        self.update_signing(store, index, |checkpoint| {
+            checkpoint.sign(api, &ctx, xpub, sigs, btc_height)
+        })
+    }
+
+    /// Adds a batch of MuSig2 public nonces to the checkpoint for the signatory
+    /// with the given extended public key (`xpub`).
+    ///
+    /// Nonces can not be verified, so the caller must ensure they were
+    /// submitted by the owner of the extended public key.
+    pub fn add_nonces(
+        &mut self,
+        store: &mut dyn Storage,
+        xpub: &Xpub,
+        nonces: Vec<Binary>,
+        index: u32,
+    ) -> ContractResult<()> {
+        self.update_signing(store, index, |checkpoint| {
+            checkpoint.add_nonces(xpub, nonces)
+        })
+    }
+
+    /// Adds a batch of MuSig2 partial signatures to the checkpoint for the
+    /// signatory with the given extended public key (`xpub`).
+    ///
+    /// If the batch of partial signatures causes the checkpoint to be fully
+    /// signed, it will be advanced to the `Complete` state.
+    pub fn add_partial_sigs(
+        &mut self,
+        store: &mut dyn Storage,
+        xpub: &Xpub,
+        partial_sigs: Vec<Binary>,
+        index: u32,
+        btc_height: u32,
+    ) -> ContractResult<()> {
+        let ctx = ECMultContext::new_boxed();
+        self.update_signing(store, index, |checkpoint| {
+            checkpoint.add_partial_sigs(&ctx, xpub, partial_sigs, btc_height)
+        })
+    }
+
+    /// Applies `op` to the checkpoint with the given index, which must not be
+    /// building, advancing it to the `Complete` state if it becomes fully
+    /// signed.
+    fn update_signing(
+        &mut self,
+        store: &mut dyn Storage,
+        index: u32,
+        op: impl FnOnce(&mut Checkpoint) -> ContractResult<()>,
     ) -> ContractResult<()> {
         let mut checkpoint = self.get(store, index)?;
         let status = checkpoint.status.clone();
@@ -1527,7 +2195,7 @@ impl CheckpointQueue {
             ));
         }
 
-        checkpoint.sign(api, xpub, sigs, btc_height)?;
+        op(&mut checkpoint)?;
 
         if matches!(status, CheckpointStatus::Signing) && checkpoint.signed() {
             #[cfg(debug_assertions)]
@@ -1544,6 +2212,145 @@ impl CheckpointQueue {
         Ok(())
     }
 
+    /// Makes the inputs of the `Signing` checkpoint which are spent through the
+    /// key path of an aggregated-key output, and are not yet fully signed, be
+    /// spent through the multisig leaf instead once `MUSIG_SIGNING_TIMEOUT` has
+    /// passed since the checkpoint advanced to `Signing`.
+    ///
+    /// MuSig2 signing requires every signatory, so this keeps a single offline
+    /// signatory from stalling the checkpoint. The multisig leaf needs a larger
+    /// witness, so the extra miner fee is deducted from the outputs of each
+    /// changed transaction: from the reserve output of the checkpoint
+    /// transaction, in proportion from the outputs of the intermediate
+    /// emergency disbursal transaction so they stay linked to the final
+    /// transactions, and with `deduct_fee` from the final emergency disbursal
+    /// transactions.
+    ///
+    /// This changes the txids, so the transactions spending them, including the
+    /// `Building` checkpoint, are relinked to the new outputs. All inputs of a
+    /// changed transaction are moved to the multisig leaf, their nonces and
+    /// signatures are dropped, and their sighashes are recalculated so they
+    /// can be signed by a threshold of the signatories.
+    pub fn maybe_fallback_to_script_path(
+        &mut self,
+        env: &Env,
+        store: &mut dyn Storage,
+        parent_config: &BitcoinConfig,
+    ) -> ContractResult<()> {
+        if self.signing(store)?.is_none() {
+            return Ok(());
+        }
+
+        // The `Building` checkpoint is created when the previous checkpoint
+        // advances to `Signing`.
+        let signing_since = self.building(store)?.create_time();
+        if env.block.time.seconds() < signing_since + MUSIG_SIGNING_TIMEOUT {
+            return Ok(());
+        }
+
+        let index = self.index(store) - 1;
+        let mut checkpoint = self.get(store, index)?;
+        let fee_rate = checkpoint.fee_rate;
+
+        // Transactions are visited before the transactions spending them, so
+        // the spenders can be relinked to their new txids and output values.
+        let mut replaced: Vec<ReplacedTx> = vec![];
+        let mut checkpoint_tx_fee = 0;
+        for batch_type in [
+            BatchType::Checkpoint,
+            BatchType::IntermediateTx,
+            BatchType::Disbursal,
+        ] {
+            let is_checkpoint = matches!(batch_type, BatchType::Checkpoint);
+            let is_intermediate = matches!(batch_type, BatchType::IntermediateTx);
+            let batch = &mut checkpoint.batches[batch_type];
+            for tx in batch.iter_mut() {
+                let txid = tx.txid()?;
+                let est_vsize = tx.est_vsize()?;
+                let in_amount: u64 = tx.input.iter().map(|input| input.amount).sum();
+
+                let relinked = relink_inputs(tx, &replaced);
+                let timed_out = tx
+                    .input
+                    .iter()
+                    .any(|input| input.signatures.musig.is_some() && !input.signatures.signed());
+                if !relinked && !timed_out {
+                    continue;
+                }
+
+                // The sighashes of all inputs change, so already complete
+                // MuSig2 signatures would have to be redone as well.
+                for input in tx.input.iter_mut() {
+                    if input.signatures.musig.is_some() {
+                        let sigset = self.sigset(store, input.sigset_index)?;
+                        *input = input.clone().spend_script_path(&sigset);
+                    }
+                    input.signatures.clear_sigs();
+                }
+                tx.signed_inputs = 0;
+
+                let new_in_amount: u64 = tx.input.iter().map(|input| input.amount).sum();
+                let fee = tx.est_vsize()?.saturating_sub(est_vsize) * fee_rate
+                    + in_amount.saturating_sub(new_in_amount);
+                if is_checkpoint {
+                    let reserve_out = &mut tx.output[0];
+                    reserve_out.value = reserve_out.value.checked_sub(fee).ok_or_else(|| {
+                        ContractError::Checkpoint(
+                            "Insufficient reserve value to cover miner fees".into(),
+                        )
+                    })?;
+                    checkpoint_tx_fee += fee;
+                } else if is_intermediate {
+                    let total = tx.value()?;
+                    if fee > total {
+                        return Err(ContractError::Checkpoint(
+                            "Insufficient emergency disbursal value to cover miner fees".into(),
+                        ));
+                    }
+                    for output in tx.output.iter_mut() {
+                        let share = (output.value as u128 * fee as u128).div_ceil(total as u128);
+                        output.value -= (share as u64).min(output.value);
+                    }
+                } else {
+                    tx.deduct_fee(fee)?;
+                }
+
+                for i in 0..tx.input.len() {
+                    tx.populate_input_sig_message(i)?;
+                }
+                replaced.push(ReplacedTx {
+                    txid,
+                    new_txid: tx.txid()?,
+                    values: tx.output.iter().map(|output| output.value).collect(),
+                });
+            }
+
+            // Final emergency disbursal transactions which could not pay their
+            // share of the fee are dropped.
+            batch.retain(|tx| !tx.output.is_empty());
+            batch.signed_txs = batch.iter().filter(|tx| tx.signed()).count() as u16;
+        }
+
+        if replaced.is_empty() {
+            return Ok(());
+        }
+        self.set(store, index, &checkpoint)?;
+
+        // The `Building` checkpoint spends the reserve output of the checkpoint
+        // transaction. Its sighashes are calculated when it advances.
+        let mut building = self.building(store)?;
+        for tx in building.batches[BatchType::Checkpoint].iter_mut() {
+            relink_inputs(tx, &replaced);
+        }
+        self.set(store, index + 1, &building)?;
+
+        let mut fee_pool = FEE_POOL.load(store)?;
+        fee_pool -= (checkpoint_tx_fee * parent_config.units_per_sat) as i64;
+        FEE_POOL.save(store, &fee_pool)?;
+
+        Ok(())
+    }
+
     /// The signatory set for the checkpoint with the given index.
     pub fn sigset(&self, store: &dyn Storage, index: u32) -> ContractResult<SignatorySet> {
         Ok(self.get(store, index)?.sigset.clone())
@@ -1556,7 +2363,8 @@ impl CheckpointQueue {
         cp_index: u32,
         timestamping_commitment: [u8; 32],
     ) -> ContractResult<u64> {
-        self.calc_fee_checkpoint(store, cp_index, &timestamping_commitment)
+        let ctx = ECMultContext::new_boxed();
+        self.calc_fee_checkpoint(store, &ctx, cp_index, &timestamping_commitment)
     }
 
     /// The number of completed checkpoints which have not yet been confirmed on
@@ -1639,11 +2447,12 @@ impl CheckpointQueue {
     pub fn unconfirmed_vbytes(
         &self,
         store: &dyn Storage,
+        ctx: &ECMultContext,
         config: &CheckpointConfig,
     ) -> ContractResult<u64> {
         self.unconfirmed(store)?
             .iter()
-            .map(|cp| cp.est_vsize(config, &[0; 32])) // TODO: shouldn't need to pass fixed length commitment to est_vsize
+            .map(|cp| cp.est_vsize(ctx, config, &[0; 32])) // TODO: shouldn't need to pass fixed length commitment to est_vsize
             .try_fold(0, |sum, result: ContractResult<_>| {
                 let vbytes = result?;
                 Ok::<_, ContractError>(sum + vbytes)
@@ -1653,15 +2462,44 @@ impl CheckpointQueue {
     fn fee_adjustment(
         &self,
         store: &dyn Storage,
+        ctx: &ECMultContext,
         fee_rate: u64,
         config: &CheckpointConfig,
     ) -> ContractResult<u64> {
         let unconf_fees_paid = self.unconfirmed_fees_paid(store)?;
-        let unconf_vbytes = self.unconfirmed_vbytes(store, config)?;
+        let unconf_vbytes = self.unconfirmed_vbytes(store, ctx, config)?;
         Ok((unconf_vbytes * fee_rate).saturating_sub(unconf_fees_paid))
     }
 }
 
+/// A transaction whose txid changed while falling back to the script path,
+/// with the values of its outputs after the change.
+struct ReplacedTx {
+    txid: bitcoin::Txid,
+    new_txid: bitcoin::Txid,
+    values: Vec<u64>,
+}
+
+/// Points the inputs of `tx` which spend a replaced transaction at its new
+/// txid and output values. Returns `true` if any input was relinked.
+fn relink_inputs(tx: &mut BitcoinTx, replaced: &[ReplacedTx]) -> bool {
+    let mut relinked = false;
+    for input in tx.input.iter_mut() {
+        let Some(replaced) = replaced
+            .iter()
+            .find(|replaced| replaced.txid == input.prevout.txid)
+        else {
+            continue;
+        };
+        if let Some(value) = replaced.values.get(input.prevout.vout as usize) {
+            input.amount = *value;
+        }
+        input.prevout.txid = replaced.new_txid;
+        relinked = true;
+    }
+    relinked
+}
+
 /// Takes a previous fee rate and returns a new fee rate, adjusted up or down by
 /// 25%. The new fee rate is capped at the maximum and minimum fee rates
 /// specified in the given config.
@@ -10,6 +10,12 @@ pub const TRANSFER_FEE: u64 = 0;
 pub const MAX_DEPOSIT_AGE: u64 = 60 * 60 * 24 * 7 * 2; // 2 weeks
 pub const MAX_CHECKPOINT_INTERVAL: u64 = 60 * 60 * 24 * 12; // 12 days. This value should be smaller than max_deposit_age & MAX_CHECKPOINT_AGE
 pub const MAX_CHECKPOINT_AGE: u64 = 60 * 60 * 24 * 7 * 3; // 3 weeks
+pub const EMERGENCY_DISBURSAL_LOCK_TIME_INTERVAL: u32 = 60 * 60 * 24 * 7 * 8; // 8 weeks. This value should be greater than MAX_CHECKPOINT_INTERVAL
+pub const EMERGENCY_DISBURSAL_MIN_TX_AMT: u64 = 1000; // in satoshis
+pub const EMERGENCY_DISBURSAL_MAX_TX_SIZE: u64 = 50_000; // in vbytes
+pub const MAX_RECOVERY_SCRIPTS: u32 = 5_000;
+pub const RECOVERY_BALANCES_PER_BLOCK: usize = 50; // balances of accounts with a recovery script queried per block
+pub const MUSIG_SIGNING_TIMEOUT: u64 = 60 * 60 * 2; // 2 hours. MuSig2 inputs not signed by then are spent through the multisig leaf
 
 // app constants
 pub const MIN_DEPOSIT_AMOUNT: u64 = 5000; // in satoshis
@@ -17,14 +23,24 @@ pub const MIN_WITHDRAWAL_AMOUNT: u64 = 5000; // in satoshis
 
 pub const MAX_LENGTH: u64 = 24_192; // ~6 months
 pub const MAX_HEADERS_RELAY_ONE_TIME: u64 = 1000;
+pub const DEFAULT_HEADERS_QUERY_LIMIT: u32 = 100;
+pub const MAX_HEADERS_QUERY_LIMIT: u32 = 1000;
 pub const MAX_TIME_INCREASE: u32 = 2 * 60 * 60;
 pub const RETARGET_INTERVAL: u32 = 2016;
 pub const TARGET_SPACING: u32 = 10 * 60;
 pub const TARGET_TIMESPAN: u32 = RETARGET_INTERVAL * TARGET_SPACING;
 pub const MAX_TARGET: u32 = 0x1d00ffff;
+pub const MAX_TARGET_SIGNET: u32 = 0x1e0377ae;
+pub const MAX_TARGET_REGTEST: u32 = 0x207fffff;
+pub const MAX_TIMEWARP: u32 = 10 * 60; // BIP94
+pub const MAX_FORKS: usize = 10;
+pub const MAX_FORK_AGE: u64 = 60 * 60 * 24; // 1 day
+pub const MAX_REORGS: usize = 100;
 
 // TODO: move to config
 pub const MAX_SIGNATORIES: u64 = 20;
+pub const MAX_AGGREGATED_SIGNATORIES: u64 = 100;
+pub const MAX_BACKUP_KEYS: usize = 20;
 pub const SIGSET_THRESHOLD: (u64, u64) = (2, 3);
 
 pub const BTC_NATIVE_TOKEN_DENOM: &str = "obtc";
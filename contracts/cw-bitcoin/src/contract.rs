@@ -2,13 +2,14 @@
 use cosmwasm_std::entry_point;
 
 use crate::{
+    checkpoint::Batch,
     entrypoints::*,
     error::ContractError,
     header::HeaderQueue,
     interface::{BitcoinConfig, CheckpointConfig, HeaderConfig},
     msg::{Config, ExecuteMsg, InstantiateMsg, MigrateMsg, QueryMsg, SudoMsg},
     state::{
-        BITCOIN_CONFIG, BUILDING_INDEX, CHECKPOINT_CONFIG, CONFIG, FEE_POOL,
+        BITCOIN_CONFIG, BUILDING_INDEX, CHECKPOINTS, CHECKPOINT_CONFIG, CONFIG, FEE_POOL,
         FIRST_UNHANDLED_CONFIRMED_INDEX,
     },
 };
@@ -44,7 +45,17 @@ pub fn instantiate(
     )?;
 
     // Set up header
-    let header_config = HeaderConfig::mainnet()?;
+    let header_config = match msg.preset {
+        Some(preset) => HeaderConfig::for_preset(preset)?,
+        None => HeaderConfig::for_network(msg.network.unwrap_or(bitcoin::Network::Bitcoin))?,
+    };
+    if let Some(network) = msg.network {
+        if network != header_config.network {
+            return Err(ContractError::App(
+                "Header preset does not match the network".to_string(),
+            ));
+        }
+    }
     let mut header_queue = HeaderQueue::default();
     header_queue.configure(deps.storage, header_config.clone())?;
 
@@ -92,32 +103,70 @@ pub fn execute(
         ExecuteMsg::RelayDeposit {
             btc_tx,
             btc_height,
+            btc_block_hash,
             btc_proof,
             btc_vout,
             sigset_index,
             dest,
+            btc_coinbase_proof,
         } => relay_deposit(
             env,
             deps.storage,
             btc_tx,
             btc_height,
+            btc_block_hash,
             btc_proof,
             btc_vout,
             sigset_index,
             dest,
+            btc_coinbase_proof,
         ),
         ExecuteMsg::RelayCheckpoint {
             btc_height,
+            btc_block_hash,
+            btc_proof,
+            cp_index,
+            btc_coinbase_proof,
+        } => relay_checkpoint(
+            deps.storage,
+            btc_height,
+            btc_block_hash,
             btc_proof,
             cp_index,
-        } => relay_checkpoint(deps.storage, btc_height, btc_proof, cp_index),
+            btc_coinbase_proof,
+        ),
         ExecuteMsg::WithdrawToBitcoin { btc_address } => {
             withdraw_to_bitcoin(deps.storage, info, env, btc_address)
         }
-        ExecuteMsg::RelayHeaders { headers } => relay_headers(deps.storage, headers),
+        ExecuteMsg::RelayHeaders { headers } => relay_headers(env, deps.storage, info, headers),
+        ExecuteMsg::RelayRawHeaders { start_height, data } => {
+            relay_raw_headers(env, deps.storage, info, start_height, data)
+        }
+        ExecuteMsg::ClaimHeaderRewards {} => claim_header_rewards(deps.storage, info),
+        ExecuteMsg::AddHeaderCheckpoint { height, hash } => {
+            add_header_checkpoint(deps.storage, info, height, hash)
+        }
+        ExecuteMsg::RelayHistoricalHeaders { headers } => {
+            relay_historical_headers(deps.storage, headers)
+        }
         ExecuteMsg::UpdateHeaderConfig { config } => {
             update_header_config(deps.storage, info, config)
         }
+        ExecuteMsg::ReanchorHeaders {
+            trusted,
+            retarget_anchor,
+            max_length,
+            max_time_increase,
+            max_history_length,
+        } => reanchor_headers(
+            deps.storage,
+            info,
+            trusted,
+            retarget_anchor,
+            max_length,
+            max_time_increase,
+            max_history_length,
+        ),
         ExecuteMsg::UpdateBitcoinConfig { config } => {
             update_bitcoin_config(deps.storage, info, config)
         }
@@ -137,10 +186,27 @@ pub fn execute(
             checkpoint_index,
             btc_height,
         ),
+        ExecuteMsg::SubmitCheckpointNonces {
+            nonces,
+            checkpoint_index,
+        } => submit_checkpoint_nonces(deps.storage, info, nonces, checkpoint_index),
+        ExecuteMsg::SubmitCheckpointPartialSignatures {
+            xpub,
+            partial_sigs,
+            checkpoint_index,
+            btc_height,
+        } => submit_checkpoint_partial_signatures(
+            deps.storage,
+            xpub,
+            partial_sigs,
+            checkpoint_index,
+            btc_height,
+        ),
         ExecuteMsg::SubmitRecoverySignature { xpub, sigs } => {
             submit_recovery_signature(deps.api, deps.storage, xpub, sigs)
         }
         ExecuteMsg::SetSignatoryKey { xpub } => set_signatory_key(deps.storage, info, xpub),
+        ExecuteMsg::SetRecoveryScript { script } => set_recovery_script(deps.storage, info, script),
         ExecuteMsg::AddValidators {
             addrs,
             voting_powers,
@@ -168,6 +234,9 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
         QueryMsg::SignatoryKey { addr } => {
             to_json_binary(&query_signatory_key(deps.storage, addr)?)
         }
+        QueryMsg::RecoveryScript { addr } => {
+            to_json_binary(&query_recovery_script(deps.storage, addr)?)
+        }
         QueryMsg::DepositFees { index } => {
             to_json_binary(&query_deposit_fees(deps.storage, index)?)
         }
@@ -184,7 +253,43 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
             to_json_binary(&query_checkpoint_tx(deps.storage, index)?)
         }
         QueryMsg::SignedRecoveryTxs {} => to_json_binary(&query_signed_recovery_txs(deps.storage)?),
+        QueryMsg::EmergencyDisbursalTxs {} => {
+            to_json_binary(&query_emergency_disbursal_txs(deps.storage)?)
+        }
         QueryMsg::HeaderHeight {} => to_json_binary(&query_header_height(deps.storage)?),
+        QueryMsg::ChainTip {} => to_json_binary(&query_chain_tip(deps.storage)?),
+        QueryMsg::HeaderRewards { address } => {
+            to_json_binary(&query_header_rewards(deps.storage, address)?)
+        }
+        QueryMsg::HeaderByHeight { height } => {
+            to_json_binary(&query_header_by_height(deps.storage, height)?)
+        }
+        QueryMsg::HeaderByHash { hash } => {
+            to_json_binary(&query_header_by_hash(deps.storage, hash)?)
+        }
+        QueryMsg::Headers {
+            start_height,
+            limit,
+        } => to_json_binary(&query_headers(deps.storage, start_height, limit)?),
+        QueryMsg::HeaderLocator {} => to_json_binary(&query_header_locator(deps.storage)?),
+        QueryMsg::FindForkPoint { hashes } => {
+            to_json_binary(&query_find_fork_point(deps.storage, hashes)?)
+        }
+        QueryMsg::RecentReorgs {} => to_json_binary(&query_recent_reorgs(deps.storage)?),
+        QueryMsg::VerifyTxInclusion {
+            tx,
+            proof,
+            height,
+            block_hash,
+            coinbase_proof,
+        } => to_json_binary(&query_verify_tx_inclusion(
+            deps.storage,
+            tx,
+            proof,
+            height,
+            block_hash,
+            coinbase_proof,
+        )?),
         QueryMsg::SidechainBlockHash {} => {
             to_json_binary(&query_sidechain_block_hash(deps.storage)?)
         }
@@ -207,9 +312,26 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
             xpub,
             checkpoint_index,
         )?),
+        QueryMsg::NoncesToSubmitAtCheckpointIndex {
+            xpub,
+            checkpoint_index,
+        } => to_json_binary(&query_nonces_to_submit_at_checkpoint_index(
+            deps.storage,
+            xpub,
+            checkpoint_index,
+        )?),
+        QueryMsg::PartialSigsToSubmitAtCheckpointIndex {
+            xpub,
+            checkpoint_index,
+        } => to_json_binary(&query_partial_sigs_to_submit_at_checkpoint_index(
+            deps.storage,
+            xpub,
+            checkpoint_index,
+        )?),
         QueryMsg::ProcessedOutpoint { key } => {
             to_json_binary(&query_process_outpoints(deps.storage, key)?)
         }
+        QueryMsg::ReorgedDeposits {} => to_json_binary(&query_reorged_deposits(deps.storage)?),
         QueryMsg::CompletedIndex {} => to_json_binary(&query_completed_index(deps.storage)?),
         QueryMsg::BuildingIndex {} => to_json_binary(&query_building_index(deps.storage)?),
         QueryMsg::ConfirmedIndex {} => to_json_binary(&query_comfirmed_index(deps.storage)?),
@@ -227,6 +349,30 @@ pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
 pub fn migrate(deps: DepsMut, _env: Env, _msg: MigrateMsg) -> Result<Response, ContractError> {
     let original_version =
         cw2::ensure_from_older_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    // older versions did not index headers by hash
+    let mut header_queue = HeaderQueue::default();
+    header_queue.index_hashes(deps.storage)?;
+
+    // older versions subtracted the work of pruned headers from the current
+    // work and built the chain work of newer headers on it, which can drift
+    // even where the last header matches the current work
+    header_queue.recompute_chain_work(deps.storage)?;
+
+    // older versions only had the checkpoint transaction batch, the emergency
+    // disbursal batches are signed before it. Pruned checkpoints are no longer
+    // in the queue, so only the retained ones are rewritten.
+    for pos in 0..CHECKPOINTS.len(deps.storage)? {
+        let mut checkpoint = CHECKPOINTS.get(deps.storage, pos)?.ok_or_else(|| {
+            ContractError::Checkpoint(format!("Missing checkpoint at queue position {}", pos))
+        })?;
+        if checkpoint.batches.len() == 1 {
+            checkpoint
+                .batches
+                .splice(0..0, [Batch::default(), Batch::default()]);
+            CHECKPOINTS.set(deps.storage, pos, &checkpoint)?;
+        }
+    }
     Ok(Response::new().add_attribute("new_version", original_version.to_string()))
 }
 
@@ -5,17 +5,20 @@ use crate::{
     app::{Bitcoin, ConsensusKey},
     error::ContractResult,
     header::{HeaderList, HeaderQueue, WrappedHeader},
-    interface::{BitcoinConfig, CheckpointConfig, Dest, HeaderConfig, Xpub},
+    interface::{
+        BitcoinConfig, CheckpointConfig, CoinbaseProof, Dest, HeaderConfig, RetargetAnchor, Xpub,
+    },
     state::{
-        get_full_btc_denom, Ratio, BITCOIN_CONFIG, CHECKPOINT_CONFIG, CONFIG, SIGNERS,
-        TOKEN_FEE_RATIO, VALIDATORS,
+        get_full_btc_denom, Ratio, BITCOIN_CONFIG, CHECKPOINT_CONFIG, CONFIG, HEADER_CONFIG,
+        SIGNERS, TOKEN_FEE_RATIO, VALIDATORS,
     },
     threshold_sig::Signature,
 };
-use bitcoin::{util::merkleblock::PartialMerkleTree, Transaction};
+use bitcoin::{util::merkleblock::PartialMerkleTree, BlockHash, Script, Transaction};
 
 use cosmwasm_std::{
-    to_json_binary, wasm_execute, Addr, Api, Env, MessageInfo, Response, Storage, Uint128, WasmMsg,
+    to_json_binary, wasm_execute, Addr, Api, BankMsg, Binary, CosmosMsg, Env, Event, MessageInfo,
+    Response, Storage, Uint128, WasmMsg,
 };
 use oraiswap::asset::AssetInfo;
 use token_bindings::Metadata;
@@ -77,6 +80,16 @@ pub fn update_checkpoint_config(
     config: CheckpointConfig,
 ) -> ContractResult<Response> {
     assert_eq!(info.sender, CONFIG.load(store)?.owner);
+    if let Some(backup) = &config.backup {
+        backup.validate()?;
+    }
+    if let Some(disbursal) = &config.emergency_disbursal {
+        if disbursal.lock_time_interval as u64 <= config.max_checkpoint_interval {
+            return Err(crate::error::ContractError::App(
+                "Emergency disbursal lock time interval must be greater than the maximum checkpoint interval".to_string(),
+            ));
+        }
+    }
     CHECKPOINT_CONFIG.save(store, &config)?;
     Ok(Response::new().add_attribute("action", "update_checkpoint_config"))
 }
@@ -86,7 +99,15 @@ pub fn update_bitcoin_config(
     info: MessageInfo,
     config: BitcoinConfig,
 ) -> ContractResult<Response> {
-    assert_eq!(info.sender, CONFIG.load(store)?.owner);
+    let contract_config = CONFIG.load(store)?;
+    assert_eq!(info.sender, contract_config.owner);
+    let nbtc_denom = get_full_btc_denom(contract_config.token_factory_addr.as_str());
+    if config.header_reward_denom.as_ref() == Some(&nbtc_denom) {
+        return Err(crate::error::ContractError::App(
+            "Header rewards in nBTC are paid from the fee pool, header reward denom must be unset"
+                .to_string(),
+        ));
+    }
     BITCOIN_CONFIG.save(store, &config)?;
     Ok(Response::new().add_attribute("action", "update_bitcoin_config"))
 }
@@ -102,14 +123,133 @@ pub fn update_header_config(
     Ok(Response::new().add_attribute("action", "update_header_config"))
 }
 
+pub fn reanchor_headers(
+    store: &mut dyn Storage,
+    info: MessageInfo,
+    trusted: Option<WrappedHeader>,
+    retarget_anchor: Option<RetargetAnchor>,
+    max_length: Option<u64>,
+    max_time_increase: Option<u32>,
+    max_history_length: Option<u64>,
+) -> ContractResult<Response> {
+    assert_eq!(info.sender, CONFIG.load(store)?.owner);
+    let mut header_queue = HeaderQueue::default();
+    if let Some(trusted) = trusted {
+        header_queue.reanchor(store, trusted, retarget_anchor)?;
+    }
+
+    let mut config = header_queue.config(store)?;
+    if let Some(max_length) = max_length {
+        config.max_length = max_length;
+    }
+    if let Some(max_time_increase) = max_time_increase {
+        config.max_time_increase = max_time_increase;
+    }
+    if let Some(max_history_length) = max_history_length {
+        config.max_history_length = max_history_length;
+        header_queue.trim_history(store, &config)?;
+    }
+    HEADER_CONFIG.save(store, &config)?;
+    Ok(Response::new().add_attribute("action", "reanchor_headers"))
+}
+
+pub fn add_header_checkpoint(
+    store: &mut dyn Storage,
+    info: MessageInfo,
+    height: u32,
+    hash: WrappedBinary<BlockHash>,
+) -> ContractResult<Response> {
+    assert_eq!(info.sender, CONFIG.load(store)?.owner);
+    let mut header_queue = HeaderQueue::default();
+    header_queue.add_checkpoint(store, height, hash.0)?;
+    Ok(Response::new().add_attribute("action", "add_header_checkpoint"))
+}
+
 pub fn relay_headers(
+    env: Env,
     store: &mut dyn Storage,
+    info: MessageInfo,
     headers: Vec<WrappedHeader>,
 ) -> ContractResult<Response> {
     // let header_config = HEADER_CONFIG.load(store)?;
+    let mut btc = Bitcoin::default();
+    let mut response = Response::new().add_attribute("action", "add_headers");
+    let reorg = btc
+        .headers
+        .add(&env, store, HeaderList::from(headers.clone()))?;
+    btc.reward_headers(store, &info.sender, &headers)?;
+    btc.prune_deposit_blocks(store)?;
+    if let Some(reorg) = reorg {
+        btc.invalidate_deposits(store, &reorg)?;
+        response = response.add_event(
+            Event::new("header_reorg")
+                .add_attribute("old_tip", reorg.old_tip.to_string())
+                .add_attribute("new_tip", reorg.new_tip.to_string())
+                .add_attribute("fork_height", reorg.fork_height.to_string())
+                .add_attribute("depth", reorg.depth.to_string())
+                .add_attribute("work_replaced", reorg.work_replaced.to_string()),
+        );
+    }
+    Ok(response)
+}
+
+pub fn relay_raw_headers(
+    env: Env,
+    store: &mut dyn Storage,
+    info: MessageInfo,
+    start_height: u32,
+    data: Binary,
+) -> ContractResult<Response> {
+    let headers = HeaderList::from_raw(start_height, &data)?;
+    relay_headers(env, store, info, headers.into())
+}
+
+pub fn claim_header_rewards(
+    store: &mut dyn Storage,
+    info: MessageInfo,
+) -> ContractResult<Response> {
+    let mut btc = Bitcoin::default();
+    let token_factory = CONFIG.load(store)?.token_factory_addr;
+    let nbtc_denom = get_full_btc_denom(token_factory.as_str());
+
+    let mut cosmos_msgs: Vec<CosmosMsg> = vec![];
+    for reward in btc.take_header_rewards(store, &info.sender)? {
+        if reward.denom == nbtc_denom {
+            cosmos_msgs.push(
+                wasm_execute(
+                    token_factory.clone(),
+                    &tokenfactory::msg::ExecuteMsg::MintTokens {
+                        denom: reward.denom,
+                        amount: reward.amount,
+                        mint_to_address: info.sender.to_string(),
+                    },
+                    vec![],
+                )?
+                .into(),
+            );
+        } else {
+            cosmos_msgs.push(
+                BankMsg::Send {
+                    to_address: info.sender.to_string(),
+                    amount: vec![reward],
+                }
+                .into(),
+            );
+        }
+    }
+
+    Ok(Response::new()
+        .add_messages(cosmos_msgs)
+        .add_attribute("action", "claim_header_rewards"))
+}
+
+pub fn relay_historical_headers(
+    store: &mut dyn Storage,
+    headers: Vec<WrappedHeader>,
+) -> ContractResult<Response> {
     let mut header_queue = HeaderQueue::default();
-    header_queue.add(store, HeaderList::from(headers))?;
-    Ok(Response::new().add_attribute("action", "add_headers"))
+    header_queue.add_historical(store, HeaderList::from(headers))?;
+    Ok(Response::new().add_attribute("action", "add_historical_headers"))
 }
 
 pub fn relay_deposit(
@@ -117,10 +257,12 @@ pub fn relay_deposit(
     store: &mut dyn Storage,
     btc_tx: Adapter<Transaction>,
     btc_height: u32,
+    btc_block_hash: Option<WrappedBinary<BlockHash>>,
     btc_proof: Adapter<PartialMerkleTree>,
     btc_vout: u32,
     sigset_index: u32,
     dest: Dest,
+    btc_coinbase_proof: Option<CoinbaseProof>,
 ) -> ContractResult<Response> {
     // dest validation?
     let mut btc = Bitcoin::default();
@@ -130,10 +272,12 @@ pub fn relay_deposit(
         store,
         btc_tx,
         btc_height,
+        btc_block_hash.map(|hash| hash.0),
         btc_proof,
         btc_vout,
         sigset_index,
         dest,
+        btc_coinbase_proof,
     )?;
 
     Ok(response)
@@ -151,9 +295,14 @@ pub fn withdraw_to_bitcoin(
 
     let config = CONFIG.load(store)?;
     let denom = get_full_btc_denom(config.token_factory_addr.as_str());
-    let script_pubkey = bitcoin::Address::from_str(btc_address.as_str())
-        .map_err(|err| crate::error::ContractError::App(err.to_string()))?
-        .script_pubkey();
+    let address = bitcoin::Address::from_str(btc_address.as_str())
+        .map_err(|err| crate::error::ContractError::App(err.to_string()))?;
+    if !address.is_valid_for_network(btc.network(store)?) {
+        return Err(crate::error::ContractError::App(
+            "Withdrawal address network does not match network".to_string(),
+        ));
+    }
+    let script_pubkey = address.script_pubkey();
     for fund in info.funds {
         if fund.denom == denom {
             let amount = fund.amount;
@@ -179,12 +328,21 @@ pub fn withdraw_to_bitcoin(
 pub fn relay_checkpoint(
     store: &mut dyn Storage,
     btc_height: u32,
+    btc_block_hash: Option<WrappedBinary<BlockHash>>,
     btc_proof: Adapter<PartialMerkleTree>,
     cp_index: u32,
+    btc_coinbase_proof: Option<CoinbaseProof>,
 ) -> ContractResult<Response> {
     let mut btc = Bitcoin::default();
     let response = Response::new().add_attribute("action", "relay_checkpoint");
-    btc.relay_checkpoint(store, btc_height, btc_proof, cp_index)?;
+    btc.relay_checkpoint(
+        store,
+        btc_height,
+        btc_block_hash.map(|hash| hash.0),
+        btc_proof,
+        cp_index,
+        btc_coinbase_proof,
+    )?;
     Ok(response)
 }
 
@@ -203,6 +361,32 @@ pub fn submit_checkpoint_signature(
     Ok(response)
 }
 
+pub fn submit_checkpoint_nonces(
+    store: &mut dyn Storage,
+    info: MessageInfo,
+    nonces: Vec<Binary>,
+    cp_index: u32,
+) -> ContractResult<Response> {
+    let mut btc = Bitcoin::default();
+    btc.submit_checkpoint_nonces(store, info.sender, nonces, cp_index)?;
+    let response = Response::new().add_attribute("action", "submit_checkpoint_nonces");
+    Ok(response)
+}
+
+pub fn submit_checkpoint_partial_signatures(
+    store: &mut dyn Storage,
+    xpub: WrappedBinary<Xpub>,
+    partial_sigs: Vec<Binary>,
+    cp_index: u32,
+    btc_height: u32,
+) -> ContractResult<Response> {
+    let btc = Bitcoin::default();
+    let mut checkpoints = btc.checkpoints;
+    checkpoints.add_partial_sigs(store, &xpub.0, partial_sigs, cp_index, btc_height)?;
+    let response = Response::new().add_attribute("action", "submit_checkpoint_partial_signatures");
+    Ok(response)
+}
+
 pub fn submit_recovery_signature(
     api: &dyn Api,
     store: &mut dyn Storage,
@@ -227,6 +411,17 @@ pub fn set_signatory_key(
     Ok(response)
 }
 
+pub fn set_recovery_script(
+    store: &mut dyn Storage,
+    info: MessageInfo,
+    script: Adapter<Script>,
+) -> ContractResult<Response> {
+    let mut btc = Bitcoin::default();
+    btc.set_recovery_script(store, info.sender, script)?;
+    let response = Response::new().add_attribute("action", "set_recovery_script");
+    Ok(response)
+}
+
 // TODO: Add check only owners of this contract can call
 pub fn add_validators(
     store: &mut dyn Storage,
@@ -1,20 +1,22 @@
-use bitcoin::{BlockHash, Transaction};
-use cosmwasm_std::{Addr, Env, QuerierWrapper, Storage};
+use bitcoin::{util::merkleblock::PartialMerkleTree, BlockHash, Script, Transaction};
+use cosmwasm_std::{Addr, Coin, Env, Order, QuerierWrapper, Storage};
+use libsecp256k1_core::curve::ECMultContext;
 use std::str::FromStr;
 
 use crate::{
     adapter::{Adapter, WrappedBinary},
-    app::{Bitcoin, ConsensusKey},
-    checkpoint::{Checkpoint, CheckpointQueue, CheckpointStatus},
+    app::{verify_tx_proof, Bitcoin, ConsensusKey, RelayedDeposit},
+    checkpoint::{Checkpoint, CheckpointQueue, CheckpointStatus, MusigSignRequest},
+    constants::{DEFAULT_HEADERS_QUERY_LIMIT, MAX_HEADERS_QUERY_LIMIT},
     error::{ContractError, ContractResult},
-    header::HeaderQueue,
-    interface::{BitcoinConfig, ChangeRates, CheckpointConfig, HeaderConfig, Xpub},
-    msg::ConfigResponse,
+    header::{HeaderQueue, Reorg, WorkHeader},
+    interface::{BitcoinConfig, ChangeRates, CheckpointConfig, CoinbaseProof, HeaderConfig, Xpub},
+    msg::{ChainTipResponse, ConfigResponse, HeaderResponse, TxInclusionResponse},
     recovery::{RecoveryTxs, SignedRecoveryTx},
     signatory::SignatorySet,
     state::{
-        header_height, BITCOIN_CONFIG, BUILDING_INDEX, CHECKPOINT_CONFIG, CONFIG, HEADER_CONFIG,
-        OUTPOINTS, SIGNERS, SIG_KEYS, TOKEN_FEE_RATIO,
+        header_height, BITCOIN_CONFIG, BUILDING_INDEX, CHECKPOINT_CONFIG, CONFIG, HEADERS,
+        HEADER_CONFIG, OUTPOINTS, REORGED_DEPOSITS, SIGNERS, SIG_KEYS, TOKEN_FEE_RATIO,
     },
 };
 
@@ -49,6 +51,15 @@ pub fn query_header_config(store: &dyn Storage) -> ContractResult<HeaderConfig>
     Ok(header_config)
 }
 
+pub fn query_recovery_script(
+    store: &dyn Storage,
+    addr: Addr,
+) -> ContractResult<Option<Adapter<Script>>> {
+    let btc = Bitcoin::default();
+    let recovery_script = btc.recovery_script(store, &addr)?;
+    Ok(recovery_script)
+}
+
 pub fn query_signatory_key(
     store: &dyn Storage,
     addr: Addr,
@@ -66,6 +77,167 @@ pub fn query_header_height(store: &dyn Storage) -> ContractResult<u32> {
     header_height(store)
 }
 
+pub fn query_chain_tip(store: &dyn Storage) -> ContractResult<ChainTipResponse> {
+    let headers = HeaderQueue::default();
+    let tip = HEADERS
+        .back(store)?
+        .ok_or_else(|| ContractError::Header("HeaderQueue is empty".into()))?;
+    Ok(ChainTipResponse {
+        height: tip.height(),
+        hash: WrappedBinary(tip.block_hash()),
+        median_time_past: headers.median_time_past(store)?,
+        time: tip.time(),
+        chain_work: tip.chain_work.clone(),
+        bits: tip.header.bits(),
+        target: Adapter::new(tip.header.target()),
+    })
+}
+
+pub fn query_header_rewards(store: &dyn Storage, address: Addr) -> ContractResult<Vec<Coin>> {
+    let btc = Bitcoin::default();
+    btc.header_rewards(store, &address)
+}
+
+/// Historical headers are returned without their chain work, see
+/// `HeaderResponse::chain_work`.
+fn header_response(header: WorkHeader, tip_height: u32, initial_height: u32) -> HeaderResponse {
+    let chain_work = (header.height() >= initial_height).then_some(header.chain_work);
+    HeaderResponse {
+        height: header.height(),
+        hash: WrappedBinary(header.block_hash()),
+        prev_hash: WrappedBinary(header.header.prev_blockhash()),
+        merkle_root: WrappedBinary(header.merkle_root()),
+        time: header.time(),
+        bits: header.header.bits(),
+        chain_work,
+        confirmations: tip_height - header.height() + 1,
+    }
+}
+
+pub fn query_header_by_height(
+    store: &dyn Storage,
+    height: u32,
+) -> ContractResult<Option<HeaderResponse>> {
+    let headers = HeaderQueue::default();
+    if height < headers.first_height(store)? {
+        return Ok(None);
+    }
+    let initial_height = headers.get_initial_height(store)?;
+    let tip_height = headers.height(store)?;
+    let header = headers.get_by_height(store, height, Some(initial_height))?;
+    Ok(header.map(|header| header_response(header, tip_height, initial_height)))
+}
+
+pub fn query_header_by_hash(
+    store: &dyn Storage,
+    hash: WrappedBinary<BlockHash>,
+) -> ContractResult<Option<HeaderResponse>> {
+    let headers = HeaderQueue::default();
+    let initial_height = headers.get_initial_height(store)?;
+    let tip_height = headers.height(store)?;
+    let header = headers.get_by_hash(store, hash.0)?;
+    Ok(header.map(|header| header_response(header, tip_height, initial_height)))
+}
+
+pub fn query_headers(
+    store: &dyn Storage,
+    start_height: Option<u32>,
+    limit: Option<u32>,
+) -> ContractResult<Vec<HeaderResponse>> {
+    let headers = HeaderQueue::default();
+    let initial_height = headers.get_initial_height(store)?;
+    let tip_height = headers.height(store)?;
+    let first_height = headers.first_height(store)?;
+    let start_height = start_height.unwrap_or(first_height).max(first_height);
+    let limit = limit
+        .unwrap_or(DEFAULT_HEADERS_QUERY_LIMIT)
+        .min(MAX_HEADERS_QUERY_LIMIT);
+
+    let mut result = vec![];
+    for height in (start_height..=tip_height).take(limit as usize) {
+        if let Some(header) = headers.get_by_height(store, height, Some(initial_height))? {
+            result.push(header_response(header, tip_height, initial_height));
+        }
+    }
+    Ok(result)
+}
+
+pub fn query_header_locator(store: &dyn Storage) -> ContractResult<Vec<WrappedBinary<BlockHash>>> {
+    let headers = HeaderQueue::default();
+    let locator = headers.locator(store)?;
+    Ok(locator.into_iter().map(WrappedBinary).collect())
+}
+
+pub fn query_find_fork_point(
+    store: &dyn Storage,
+    hashes: Vec<WrappedBinary<BlockHash>>,
+) -> ContractResult<Option<u32>> {
+    let headers = HeaderQueue::default();
+    let hashes: Vec<BlockHash> = hashes.into_iter().map(|hash| hash.0).collect();
+    headers.find_fork_point(store, &hashes)
+}
+
+pub fn query_verify_tx_inclusion(
+    store: &dyn Storage,
+    tx: Adapter<Transaction>,
+    proof: Adapter<PartialMerkleTree>,
+    height: Option<u32>,
+    block_hash: Option<WrappedBinary<BlockHash>>,
+    coinbase_proof: Option<CoinbaseProof>,
+) -> ContractResult<TxInclusionResponse> {
+    let headers = HeaderQueue::default();
+    let (header, on_best_chain) = match (block_hash, height) {
+        (Some(hash), _) => match headers.get_by_hash(store, hash.0)? {
+            Some(header) => (header, true),
+            None => (
+                headers
+                    .get_fork_header_by_hash(store, hash.0)?
+                    .ok_or_else(|| ContractError::App(format!("Block {} is unknown", hash.0)))?,
+                false,
+            ),
+        },
+        (None, Some(height)) => (
+            headers
+                .get_by_height(store, height, None)?
+                .ok_or_else(|| ContractError::App("Invalid bitcoin block height".to_string()))?,
+            true,
+        ),
+        (None, None) => {
+            return Err(ContractError::App(
+                "Either a block hash or a height is required".to_string(),
+            ))
+        }
+    };
+    if let Some(height) = height {
+        if header.height() != height {
+            return Err(ContractError::App(format!(
+                "Block {} is at height {}, not {}",
+                header.block_hash(),
+                header.height(),
+                height
+            )));
+        }
+    }
+
+    verify_tx_proof(&tx, &proof, &header, coinbase_proof.as_ref())?;
+
+    let confirmations = if on_best_chain {
+        headers.height(store)? - header.height() + 1
+    } else {
+        0
+    };
+    Ok(TxInclusionResponse {
+        txid: WrappedBinary(tx.txid()),
+        confirmations,
+        on_best_chain,
+    })
+}
+
+pub fn query_recent_reorgs(store: &dyn Storage) -> ContractResult<Vec<Reorg>> {
+    let headers = HeaderQueue::default();
+    headers.recent_reorgs(store)
+}
+
 pub fn query_deposit_fees(store: &dyn Storage, index: Option<u32>) -> ContractResult<u64> {
     let btc = Bitcoin::default();
 
@@ -93,9 +265,10 @@ pub fn query_withdrawal_fees(
 pub fn query_checkpoint_fees(store: &dyn Storage, index: Option<u32>) -> ContractResult<u64> {
     let btc = Bitcoin::default();
     let building_index = BUILDING_INDEX.load(store)?;
+    let ctx = ECMultContext::new_boxed();
     let checkpoint_fees = btc
         .checkpoints
-        .calc_fee_checkpoint(store, index.unwrap_or(building_index), &[0])
+        .calc_fee_checkpoint(store, &ctx, index.unwrap_or(building_index), &[0])
         .unwrap();
     Ok(checkpoint_fees)
 }
@@ -159,6 +332,14 @@ pub fn query_complete_checkpoint_txs(
     Ok(complete_txs)
 }
 
+pub fn query_emergency_disbursal_txs(
+    store: &dyn Storage,
+) -> ContractResult<Vec<Adapter<Transaction>>> {
+    let checkpoints = CheckpointQueue::default();
+    let disbursal_txs = checkpoints.emergency_disbursal_txs(store)?;
+    Ok(disbursal_txs)
+}
+
 pub fn query_signed_recovery_txs(store: &dyn Storage) -> ContractResult<Vec<SignedRecoveryTx>> {
     let recovery_txs = RecoveryTxs::default();
     let signed_recovery_txs = recovery_txs.signed(store)?;
@@ -204,6 +385,13 @@ pub fn query_process_outpoints(store: &dyn Storage, key: String) -> ContractResu
     Ok(process_outpoints)
 }
 
+pub fn query_reorged_deposits(store: &dyn Storage) -> ContractResult<Vec<RelayedDeposit>> {
+    REORGED_DEPOSITS
+        .range(store, None, None, Order::Ascending)
+        .map(|item| Ok(item?.1))
+        .collect()
+}
+
 pub fn query_signatory_keys(
     store: &dyn Storage,
     cons_key: ConsensusKey,
@@ -231,6 +419,32 @@ pub fn query_signing_txs_at_checkpoint_index(
     checkpoint.to_sign(&xpub.0)
 }
 
+pub fn query_nonces_to_submit_at_checkpoint_index(
+    store: &dyn Storage,
+    xpub: WrappedBinary<Xpub>,
+    cp_index: u32,
+) -> ContractResult<Vec<([u8; 32], u32)>> {
+    let checkpoints = CheckpointQueue::default();
+    let checkpoint = checkpoints.get(store, cp_index)?;
+    if checkpoint.status != CheckpointStatus::Signing {
+        return Err(ContractError::App("checkpoint is not signing".to_string()));
+    }
+    checkpoint.nonces_to_submit(&xpub.0)
+}
+
+pub fn query_partial_sigs_to_submit_at_checkpoint_index(
+    store: &dyn Storage,
+    xpub: WrappedBinary<Xpub>,
+    cp_index: u32,
+) -> ContractResult<Vec<MusigSignRequest>> {
+    let checkpoints = CheckpointQueue::default();
+    let checkpoint = checkpoints.get(store, cp_index)?;
+    if checkpoint.status != CheckpointStatus::Signing {
+        return Err(ContractError::App("checkpoint is not signing".to_string()));
+    }
+    checkpoint.partial_sigs_to_submit(&xpub.0)
+}
+
 pub fn query_change_rates(
     store: &dyn Storage,
     env: Env,
@@ -29,22 +29,25 @@ pub fn clock_end_block(
     let osor_entry_point_contract = config.osor_entry_point_contract;
 
     let mut msgs = vec![];
+    let mut minted = vec![];
     for pending in pending_nbtc_transfers {
         for (dest, coin) in pending {
             let fee_data = process_deduct_fee(storage, querier, api, coin.clone())?;
             let denom = coin.denom.to_owned();
+            let deducted_coin = Coin {
+                denom: denom.clone(),
+                amount: fee_data.deducted_amount,
+            };
 
             dest.build_cosmos_msg(
                 env,
                 &mut msgs,
-                Coin {
-                    denom: denom.clone(),
-                    amount: fee_data.deducted_amount,
-                },
+                deducted_coin.clone(),
                 env.contract.address.clone(),
                 token_factory.clone(),
                 osor_entry_point_contract.clone(),
             );
+            minted.push((dest, deducted_coin));
 
             if fee_data.relayer_fee.amount.gt(&Uint128::zero()) {
                 msgs.push(CosmosMsg::Wasm(WasmMsg::Execute {
@@ -71,7 +74,7 @@ pub fn clock_end_block(
             }
         }
     }
-    let offline_signers = btc.begin_block_step(env, storage, hash.to_vec())?;
+    let offline_signers = btc.begin_block_step(env, storage, querier, hash.to_vec(), &minted)?;
     for cons_key in &offline_signers {
         let (_, address) = VALIDATORS.load(storage, cons_key)?;
         btc.punish_validator(storage, cons_key, address)?;
@@ -1,14 +1,24 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 
-use crate::adapter::Adapter;
-use crate::constants::MAX_HEADERS_RELAY_ONE_TIME;
+use crate::adapter::{Adapter, WrappedBinary};
+use crate::constants::{
+    MAX_FORKS, MAX_FORK_AGE, MAX_HEADERS_RELAY_ONE_TIME, MAX_REORGS, MAX_TIMEWARP,
+};
 use crate::error::ContractError;
 use crate::error::ContractResult;
-use crate::interface::HeaderConfig;
+use crate::interface::{HeaderConfig, RetargetAnchor};
 use crate::state::header_height;
 use crate::state::CURRENT_WORK;
+use crate::state::FORKS;
+use crate::state::FORK_HEADERS;
+use crate::state::FORK_HEADER_HASHES;
+use crate::state::FORK_TIPS;
 use crate::state::HEADERS;
 use crate::state::HEADER_CONFIG;
+use crate::state::HEADER_HASHES;
+use crate::state::HISTORICAL_HEADERS;
+use crate::state::NEXT_FORK_ID;
+use crate::state::REORGS;
 use bitcoin::blockdata::block::BlockHeader;
 
 use bitcoin::util::uint::Uint256;
@@ -17,7 +27,7 @@ use bitcoin::BlockHash;
 use bitcoin::TxMerkleNode;
 use cosmwasm_schema::schemars::JsonSchema;
 use cosmwasm_schema::serde::{Deserialize, Serialize};
-use cosmwasm_std::Storage;
+use cosmwasm_std::{Env, Order, Storage};
 // use ed::Terminated;
 
 /// A wrapper around a bitcoin::BlockHeader that implements the core orga
@@ -131,6 +141,26 @@ impl FromIterator<WrappedHeader> for HeaderList {
     }
 }
 
+impl HeaderList {
+    /// Decodes concatenated 80-byte consensus-encoded headers, the first of
+    /// which is at `start_height` and each following one at the next height.
+    pub fn from_raw(start_height: u32, data: &[u8]) -> ContractResult<Self> {
+        if data.len() % 80 != 0 {
+            return Err(ContractError::App(
+                "Raw headers must be a multiple of 80 bytes".to_string(),
+            ));
+        }
+
+        data.chunks(80)
+            .zip(start_height..)
+            .map(|(bytes, height)| {
+                let header: BlockHeader = bitcoin::consensus::deserialize(bytes)?;
+                Ok(WrappedHeader::new(Adapter::new(header), height))
+            })
+            .collect()
+    }
+}
+
 // impl Terminated for HeaderList {}
 
 /// A `WrappedHeader`, along with a total estimated amount of work (measured in
@@ -180,6 +210,64 @@ impl WorkHeader {
     }
 }
 
+/// A candidate chain which branches off the best chain but does not (yet)
+/// include more work than it. Its headers are stored in `FORK_HEADERS` so it
+/// can be extended across several relays until it becomes the best chain.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "cosmwasm_schema::serde")]
+#[schemars(crate = "cosmwasm_schema::schemars")]
+pub struct Fork {
+    /// The height of the best chain header the fork builds on.
+    pub base_height: u32,
+    /// The hash of the best chain header the fork builds on.
+    pub base_hash: Adapter<BlockHash>,
+    pub tip_height: u32,
+    pub tip_hash: Adapter<BlockHash>,
+    /// The chain work of the fork's tip.
+    pub chain_work: Adapter<Uint256>,
+    /// The block time at which the fork was last extended, in seconds.
+    pub updated_at: u64,
+}
+
+/// A reorg of the best chain, kept in the bounded `REORGS` log.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "cosmwasm_schema::serde")]
+#[schemars(crate = "cosmwasm_schema::schemars")]
+pub struct Reorg {
+    /// The height of the last header shared by the old and the new best chain.
+    pub fork_height: u32,
+    /// The hash of the tip of the old best chain.
+    pub old_tip: Adapter<BlockHash>,
+    /// The hash of the tip of the new best chain.
+    pub new_tip: Adapter<BlockHash>,
+    /// The number of headers removed from the old best chain.
+    pub depth: u32,
+    /// The total work of the removed headers.
+    pub work_replaced: Adapter<Uint256>,
+    /// The block time at which the reorg happened, in seconds.
+    pub time: u64,
+}
+
+/// Headers being verified on top of the best chain header at `base_height`,
+/// optionally through a stored candidate fork.
+struct Branch {
+    initial_height: u32,
+    base_height: u32,
+    fork: Option<(u64, Fork)>,
+    headers: Vec<WorkHeader>,
+}
+
+impl Branch {
+    /// The height of the last header of the branch.
+    fn tip_height(&self) -> u32 {
+        let stored_height = match self.fork.as_ref() {
+            Some((_, fork)) => fork.tip_height,
+            None => self.base_height,
+        };
+        stored_height + self.headers.len() as u32
+    }
+}
+
 /// A queue of Bitcoin block headers, along with the total estimated amount of
 /// work (measured in hashes) done in the headers included in the queue.
 ///
@@ -191,6 +279,8 @@ impl WorkHeader {
 /// contains more work than the current chain, however it can not process reorgs
 /// that are deeper than the length of the queue (the length will be at the
 /// configured pruning level based on the `max_length` config parameter).
+/// Competing chains with less work are kept as candidate forks, so a reorg
+/// can be relayed across several messages.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(crate = "cosmwasm_schema::serde")]
 #[derive(Default)]
@@ -209,18 +299,25 @@ impl HeaderQueue {
 
     /// Verify and add a list of headers to the header queue.
     ///
-    /// The headers must be consecutive and must bring the chain to a final
-    /// state that has more work than the current chain.
+    /// The headers must be consecutive and must either extend the best chain,
+    /// branch off a header of the best chain, or extend a stored candidate
+    /// fork.
     ///
-    /// If the headers are valid, they will be added to the header queue. If the
-    /// headers start from a height lower than the current height, the header
-    /// queue will be reorged to the new chain.
+    /// If the headers are valid and bring their chain to a state that has more
+    /// work than the current best chain, they will be added to the header
+    /// queue, reorging it if needed. Otherwise they are stored as a candidate
+    /// fork which can be extended by later calls.
     ///
     /// If the headers are invalid (e.g. by not including a valid proof-of-work,
     /// using a difficulty other than what was expected, using invalid
     /// timestamps, etc.), an error will be returned and the header queue will
     /// not be modified.    
-    pub fn add(&mut self, store: &mut dyn Storage, headers: HeaderList) -> ContractResult<()> {
+    pub fn add(
+        &mut self,
+        env: &Env,
+        store: &mut dyn Storage,
+        headers: HeaderList,
+    ) -> ContractResult<Option<Reorg>> {
         let headers: Vec<_> = headers.into();
 
         if headers.len() as u64 > MAX_HEADERS_RELAY_ONE_TIME {
@@ -229,58 +326,86 @@ impl HeaderQueue {
             ));
         }
 
-        self.add_into_iter(store, headers)
+        self.add_into_iter(env, store, headers)
             .map_err(|err| ContractError::App(err.to_string()))
     }
 
     /// Verify and add an iterator of headers to the header queue.
     ///
-    /// The headers must be consecutive and must bring the chain to a final
-    /// state that has more work than the current chain.
+    /// The headers must be consecutive and must either extend the best chain,
+    /// branch off a header of the best chain, or extend a stored candidate
+    /// fork.
     ///
-    /// If the headers are valid, they will be added to the header queue. If the
-    /// headers start from a height lower than the current height, the header
-    /// queue will be reorged to the new chain.
+    /// If the headers are valid and bring their chain to a state that has more
+    /// work than the current best chain, they will be added to the header
+    /// queue, reorging it if needed. Otherwise they are stored as a candidate
+    /// fork which can be extended by later calls.
     ///
     /// If the headers are invalid (e.g. by not including a valid proof-of-work,
     /// using a difficulty other than what was expected, using invalid
     /// timestamps, etc.), an error will be returned and the header queue will
     /// not be modified.
-    pub fn add_into_iter<T>(&mut self, store: &mut dyn Storage, headers: T) -> ContractResult<()>
+    ///
+    /// Returns the reorg of the best chain if one happened, which is also
+    /// pushed to the `REORGS` log.
+    pub fn add_into_iter<T>(
+        &mut self,
+        env: &Env,
+        store: &mut dyn Storage,
+        headers: T,
+    ) -> ContractResult<Option<Reorg>>
     where
         T: IntoIterator<Item = WrappedHeader>,
     {
         let headers: Vec<WrappedHeader> = headers.into_iter().collect();
-        let current_height = self.height(store)?;
         let config = self.config(store)?;
 
         let first = headers
             .first()
             .ok_or_else(|| ContractError::Header("Passed header list empty".into()))?;
 
-        let mut removed_work = Uint256::default();
-        if first.height <= current_height {
-            let first_replaced = self
-                .get_by_height(store, first.height, None)?
-                .ok_or_else(|| ContractError::Header("Header not found".into()))?;
+        if first.height == 0 {
+            return Err(ContractError::Header(
+                "Headers must start after height 0".into(),
+            ));
+        }
 
-            if first_replaced.block_hash() == first.block_hash() {
-                return Err(ContractError::Header("Provided redundant header.".into()));
-            }
+        self.prune_forks(env, store)?;
 
-            removed_work = self.pop_back_to(store, first.height)?;
+        let mut branch = self.branch_for(store, first)?;
+        self.verify_headers(env, store, &config, &mut branch, &headers)?;
+
+        let tip = branch
+            .headers
+            .last()
+            .ok_or_else(|| ContractError::Header("Passed header list empty".into()))?;
+        let best = HEADERS
+            .back(store)?
+            .ok_or_else(|| ContractError::Header("HeaderQueue is empty".into()))?;
+        if *tip.chain_work <= *best.chain_work {
+            self.save_fork(env, store, branch)?;
+            return Ok(None);
         }
 
-        let added_work = self.verify_and_add_headers(store, &headers)?;
-        if added_work <= removed_work {
-            return Err(ContractError::Header(
-                "New best chain must include more work than old best chain.".into(),
-            ));
+        let current_work = tip.chain_work.clone();
+        let removed = self.pop_back_to(store, branch.base_height + 1)?;
+
+        if let Some((id, fork)) = branch.fork.as_ref() {
+            for height in fork.base_height + 1..=fork.tip_height {
+                let header = FORK_HEADERS.load(store, (*id, height))?;
+                HEADERS.push_back(store, &header)?;
+                HEADER_HASHES.save(store, &header.block_hash()[..], &height)?;
+            }
+            self.remove_fork(store, *id, fork)?;
+        }
+        for header in branch.headers.iter() {
+            HEADERS.push_back(store, header)?;
+            HEADER_HASHES.save(store, &header.block_hash()[..], &header.height())?;
         }
 
-        // Prune the header queue if it has grown too large.
+        // Prune the header queue if it has grown too large. The chain work of
+        // the remaining headers still counts the pruned ones.
         let mut queue_len = self.len(store)?;
-        let mut current_work = *CURRENT_WORK.load(store)?;
         while queue_len > config.max_length {
             let header = match HEADERS.pop_front(store)? {
                 Some(inner) => inner,
@@ -289,47 +414,104 @@ impl HeaderQueue {
                 }
             };
             queue_len -= 1;
+            self.move_to_history(store, &config, header)?;
+        }
+        self.trim_history(store, &config)?;
+        CURRENT_WORK.save(store, &current_work)?;
 
-            // TODO: do we really want to subtract work when pruning?
-            current_work = current_work - header.work();
+        if removed.is_empty() {
+            return Ok(None);
         }
-        CURRENT_WORK.save(store, &Adapter::new(current_work))?;
-        Ok(())
+        let reorg = Reorg {
+            fork_height: branch.base_height,
+            old_tip: Adapter::new(best.block_hash()),
+            new_tip: Adapter::new(tip.block_hash()),
+            depth: removed.len() as u32,
+            work_replaced: Adapter::new(
+                removed
+                    .iter()
+                    .fold(Uint256::zero(), |work, header| work + header.work()),
+            ),
+            time: env.block.time.seconds(),
+        };
+        REORGS.push_back(store, &reorg)?;
+        while REORGS.len(store)? as usize > MAX_REORGS {
+            REORGS.pop_front(store)?;
+        }
+
+        Ok(Some(reorg))
     }
 
-    /// Verify and add a list of headers to the header queue, returning the
-    /// amount of additional estimated work added to the header queue.
-    fn verify_and_add_headers(
-        &mut self,
-        store: &mut dyn Storage,
-        headers: &[WrappedHeader],
-    ) -> ContractResult<Uint256> {
-        let first_height = headers
-            .first()
-            .ok_or_else(|| ContractError::Header("Passed header list is empty".into()))?
-            .height;
+    /// The reorgs kept in the `REORGS` log, oldest first.
+    pub fn recent_reorgs(&self, store: &dyn Storage) -> ContractResult<Vec<Reorg>> {
+        Ok(REORGS.iter(store)?.collect::<Result<Vec<_>, _>>()?)
+    }
 
-        if first_height == 0 {
-            return Err(ContractError::Header(
-                "Headers must start after height 0".into(),
-            ));
+    /// Finds where the passed header connects: either to a header of the best
+    /// chain, or to the tip of a stored candidate fork.
+    fn branch_for(&self, store: &dyn Storage, first: &WrappedHeader) -> ContractResult<Branch> {
+        let initial_height = self.get_initial_height(store)?;
+        let prev_hash = first.prev_blockhash();
+
+        if let Some(base_height) = HEADER_HASHES.may_load(store, &prev_hash[..])? {
+            // historical headers can not be reorged
+            if base_height < initial_height {
+                return Err(ContractError::Header("Headers not connect to chain".into()));
+            }
+
+            if let Some(existing) =
+                self.get_by_height(store, base_height + 1, Some(initial_height))?
+            {
+                if existing.block_hash() == first.block_hash() {
+                    return Err(ContractError::Header("Provided redundant header.".into()));
+                }
+            }
+
+            return Ok(Branch {
+                initial_height,
+                base_height,
+                fork: None,
+                headers: vec![],
+            });
         }
 
-        // get header right before first header of headers (which are going to be relayed)
-        let prev_header = [self
-            .get_by_height(store, first_height - 1, None)?
-            .ok_or_else(|| ContractError::Header("Headers not connect to chain".into()))?
-            .header];
+        if let Some(id) = FORK_TIPS.may_load(store, &prev_hash[..])? {
+            let fork = FORKS.load(store, id)?;
+            return Ok(Branch {
+                initial_height,
+                base_height: fork.base_height,
+                fork: Some((id, fork)),
+                headers: vec![],
+            });
+        }
 
-        // create tupple of headers
-        // [prev_header, headers[0], headers[1], ...]
-        // [headers[0], headers[1], headers[2]...]
-        let headers = prev_header.iter().chain(headers.iter()).zip(headers.iter());
+        Err(ContractError::Header("Headers not connect to chain".into()))
+    }
 
-        let mut work = Uint256::zero();
+    /// Verify a list of headers on top of the passed branch, appending them to
+    /// the branch's headers.
+    ///
+    /// Everything needed to verify the batch (the config, the timestamps of
+    /// the last 11 headers and the bits of the last non-minimum difficulty
+    /// header) is read once, then kept up to date in memory as headers are
+    /// verified.
+    fn verify_headers(
+        &self,
+        env: &Env,
+        store: &dyn Storage,
+        config: &HeaderConfig,
+        branch: &mut Branch,
+        headers: &[WrappedHeader],
+    ) -> ContractResult<()> {
+        let mut prev_header = self
+            .branch_header(store, branch, branch.tip_height())?
+            .ok_or_else(|| ContractError::Header("Headers not connect to chain".into()))?;
 
-        let mut cache_headers_map = HashMap::new();
-        for (prev_header, header) in headers {
+        let mut timestamps = self.timestamp_window(store, branch, prev_header.height())?;
+        let mut walk_bits = self.walk_bits(store, config, branch, &prev_header.header)?;
+        let checkpoints = checkpoint_map(config);
+
+        for header in headers {
             // prove: prev_header and header are adjacent
             if header.height() != prev_header.height() + 1 {
                 return Err(ContractError::Header(
@@ -349,49 +531,145 @@ impl HeaderQueue {
                 ));
             }
 
-            // make sure header is <= median timestamp of last 11 headers
-            if HEADERS.len(store)? >= 11 {
-                self.validate_time(store, header)?;
-            }
+            validate_checkpoint(&checkpoints, header)?;
+            self.validate_time(env, config, &timestamps, header)?;
 
-            let initial_height = self.get_initial_height(store)?;
+            // BIP94: the first block of a retargeting period must not be
+            // timestamped too far before its parent
+            if config.enforce_bip94
+                && header.height() % config.retarget_interval == 0
+                && header.time() < prev_header.time().saturating_sub(MAX_TIMEWARP)
+            {
+                return Err(ContractError::Header(
+                    "Header timestamp violates the time warp rule".into(),
+                ));
+            }
 
             let target = self.get_next_target(
                 store,
+                config,
+                branch,
                 header,
-                prev_header,
-                initial_height,
-                &mut cache_headers_map,
+                &prev_header.header,
+                walk_bits,
             )?;
             header.validate_pow(&target)?;
 
-            let header_work = header.work();
-            work = work + header_work;
+            if timestamps.len() == 11 {
+                timestamps.pop_front();
+            }
+            timestamps.push_back(header.time());
+            if header.height() % config.retarget_interval == 0 || header.bits() != config.max_target
+            {
+                walk_bits = header.bits();
+            }
 
-            let chain_work = *self.current_work(store)? + header_work;
+            let chain_work = *prev_header.chain_work + header.work();
             let work_header = WorkHeader::new(header.clone(), chain_work);
-            HEADERS.push_back(store, &work_header)?;
-            // self.current_work = Adapter::new(chain_work);
-            CURRENT_WORK.save(store, &Adapter::new(chain_work))?;
+            branch.headers.push(work_header.clone());
+            prev_header = work_header;
         }
 
-        Ok(work)
+        Ok(())
+    }
+
+    /// The timestamps of the (up to) 11 headers of the branch ending at the
+    /// passed height, oldest first.
+    fn timestamp_window(
+        &self,
+        store: &dyn Storage,
+        branch: &Branch,
+        height: u32,
+    ) -> ContractResult<VecDeque<u32>> {
+        let start = height.saturating_sub(10).max(branch.initial_height);
+        let mut timestamps = VecDeque::with_capacity(11);
+        for prev_height in start..=height {
+            let header = self
+                .branch_header(store, branch, prev_height)?
+                .ok_or_else(|| {
+                    ContractError::Header("Deque does not contain any elements".into())
+                })?;
+            timestamps.push_back(header.time());
+        }
+        Ok(timestamps)
+    }
+
+    /// The bits of the last header of the branch, up to and including the
+    /// passed header, which is either the first of a retargeting period or not
+    /// a minimum difficulty block.
+    fn walk_bits(
+        &self,
+        store: &dyn Storage,
+        config: &HeaderConfig,
+        branch: &Branch,
+        header: &WrappedHeader,
+    ) -> ContractResult<u32> {
+        let mut current_header_height = header.height();
+        let mut current_bits = header.bits();
+        if !config.min_difficulty_blocks {
+            return Ok(current_bits);
+        }
+
+        while current_header_height > branch.initial_height
+            && current_header_height % config.retarget_interval != 0
+            && current_bits == config.max_target
+        {
+            current_header_height -= 1;
+            current_bits = self
+                .branch_header(store, branch, current_header_height)?
+                .ok_or_else(|| ContractError::Header("No previous header exists".into()))?
+                .header
+                .bits();
+        }
+        Ok(current_bits)
+    }
+
+    /// Get a header by its height along the passed branch.
+    fn branch_header(
+        &self,
+        store: &dyn Storage,
+        branch: &Branch,
+        height: u32,
+    ) -> ContractResult<Option<WorkHeader>> {
+        if height <= branch.base_height {
+            return self.get_by_height(store, height, Some(branch.initial_height));
+        }
+
+        let mut stored_height = branch.base_height;
+        if let Some((id, fork)) = branch.fork.as_ref() {
+            if height <= fork.tip_height {
+                return Ok(FORK_HEADERS.may_load(store, (*id, height))?);
+            }
+            stored_height = fork.tip_height;
+        }
+
+        Ok(branch
+            .headers
+            .get((height - stored_height - 1) as usize)
+            .cloned())
     }
 
     /// Calculate the expected next target based on the passed header and the
-    /// previous header.
+    /// previous header. `walk_bits` are the bits of the last header before
+    /// the passed one which was not a minimum difficulty block.
     fn get_next_target(
         &self,
         store: &dyn Storage,
+        config: &HeaderConfig,
+        branch: &Branch,
         header: &WrappedHeader,
         previous_header: &WrappedHeader,
-        initial_height: u32,
-        cache_headers_map: &mut HashMap<u32, u32>,
+        walk_bits: u32,
     ) -> ContractResult<Uint256> {
-        let config = self.config(store)?;
         if header.height() % config.retarget_interval == 0 {
             let first_reorg_height = header.height() - config.retarget_interval;
-            return self.calculate_next_target(store, previous_header, first_reorg_height);
+            return self.calculate_next_target(
+                store,
+                config,
+                branch,
+                previous_header,
+                first_reorg_height,
+            );
         }
 
         if !config.min_difficulty_blocks {
@@ -402,28 +680,7 @@ impl HeaderQueue {
             return Ok(WrappedHeader::u256_from_compact(config.max_target));
         }
 
-        let mut current_header_height = previous_header.height();
-        let mut current_bits = previous_header.bits();
-
-        while current_header_height > 0
-            && current_header_height % config.retarget_interval != 0
-            && current_bits == config.max_target
-        {
-            current_header_height -= 1;
-
-            current_bits = match cache_headers_map.get(&current_header_height) {
-                Some(val) => *val,
-                None => {
-                    cache_headers_map.insert(current_header_height, current_bits);
-                    HEADERS
-                        .get(store, current_header_height - initial_height)?
-                        .ok_or_else(|| ContractError::Header("No previous header exists".into()))?
-                        .header
-                        .bits()
-                }
-            }
-        }
-        Ok(WrappedHeader::u256_from_compact(current_bits))
+        Ok(WrappedHeader::u256_from_compact(walk_bits))
     }
 
     /// Calculate the expected next target based on the passed header and the
@@ -431,11 +688,11 @@ impl HeaderQueue {
     fn calculate_next_target(
         &self,
         store: &dyn Storage,
+        config: &HeaderConfig,
+        branch: &Branch,
         header: &WrappedHeader,
         first_reorg_height: u32,
     ) -> ContractResult<Uint256> {
-        let config = self.config(store)?;
-
         if !config.retargeting {
             return Ok(WrappedHeader::u256_from_compact(header.bits()));
         }
@@ -444,12 +701,31 @@ impl HeaderQueue {
             return Err(ContractError::Header("Invalid trusted header. Trusted header have height which is a multiple of the retarget interval".into()));
         }
 
-        let prev_retarget = match self.get_by_height(store, first_reorg_height, None)? {
-            Some(inner) => inner.time(),
-            None => {
-                return Err(ContractError::Header(
-                    "No previous retargeting header exists".into(),
-                ));
+        // the first header of the period may come before the trusted header,
+        // in which case the config carries its timestamp and bits
+        let (prev_retarget, first_bits) = if first_reorg_height < branch.initial_height {
+            match config.retarget_anchor.as_ref() {
+                Some(anchor)
+                    if first_reorg_height
+                        == config.trusted_height
+                            - config.trusted_height % config.retarget_interval =>
+                {
+                    (anchor.time, anchor.bits)
+                }
+                _ => {
+                    return Err(ContractError::Header(
+                        "No previous retargeting header exists".into(),
+                    ))
+                }
+            }
+        } else {
+            match self.branch_header(store, branch, first_reorg_height)? {
+                Some(inner) => (inner.time(), inner.header.bits()),
+                None => {
+                    return Err(ContractError::Header(
+                        "No previous retargeting header exists".into(),
+                    ));
+                }
             }
         };
 
@@ -459,7 +735,14 @@ impl HeaderQueue {
         let target_timespan = WrappedHeader::u32_to_u256(config.target_timespan);
         let timespan = WrappedHeader::u32_to_u256(timespan);
 
-        let target = header.target() * timespan / target_timespan;
+        // BIP94 retargets from the first block of the period, so a
+        // minimum difficulty block at the end of the period has no effect
+        let base_target = if config.enforce_bip94 {
+            WrappedHeader::u256_from_compact(first_bits)
+        } else {
+            header.target()
+        };
+        let target = base_target * timespan / target_timespan;
         let target_u32 = BlockHeader::compact_target_from_u256(&target);
         let target = WrappedHeader::u256_from_compact(target_u32);
 
@@ -467,45 +750,47 @@ impl HeaderQueue {
     }
 
     /// Remove headers from the header queue until the height of the last header
-    /// in the queue is equal to the passed height.
-    fn pop_back_to(&mut self, store: &mut dyn Storage, height: u32) -> ContractResult<Uint256> {
-        let mut work = Uint256::default();
-
+    /// in the queue is lower than the passed height, returning the removed
+    /// headers from the tip down.
+    fn pop_back_to(
+        &mut self,
+        store: &mut dyn Storage,
+        height: u32,
+    ) -> ContractResult<Vec<WorkHeader>> {
+        let mut removed = vec![];
         while self.height(store)? >= height {
             let header = HEADERS
                 .pop_back(store)?
                 .ok_or_else(|| ContractError::Header("Removed all headers".into()))?;
-
-            work = work + header.work();
+            HEADER_HASHES.remove(store, &header.block_hash()[..]);
+            removed.push(header);
         }
 
-        Ok(work)
+        Ok(removed)
     }
 
-    /// Validate the timestamp of the passed header.
+    /// Validate the timestamp of the passed header against the current time
+    /// and the timestamps of the previous headers.
     fn validate_time(
         &self,
-        store: &dyn Storage,
+        env: &Env,
+        config: &HeaderConfig,
+        prev_stamps: &VecDeque<u32>,
         current_header: &WrappedHeader,
     ) -> ContractResult<()> {
-        let mut prev_stamps: Vec<u32> = Vec::with_capacity(11);
-        let initial_height = self.get_initial_height(store)?;
-        let height = self.height(store)?;
-        for prev_height in height - 10..=height {
-            let current_item = match self.get_by_height(store, prev_height, Some(initial_height))? {
-                Some(inner) => inner.time(),
-                None => {
-                    return Err(ContractError::Header(
-                        "Deque does not contain any elements".into(),
-                    ))
-                }
-            };
-            prev_stamps.push(current_item);
+        let max_time = env.block.time.seconds() + config.max_time_increase as u64;
+        if current_header.time() as u64 > max_time {
+            return Err(ContractError::Header(
+                "Header timestamp is too far ahead of the current time".into(),
+            ));
         }
 
-        prev_stamps.sort_unstable();
+        // make sure header is > median timestamp of last 11 headers
+        if prev_stamps.len() < 11 {
+            return Ok(());
+        }
 
-        let median_stamp = match prev_stamps.get(5) {
+        let median_stamp = match median_timestamp(prev_stamps.iter().copied()) {
             Some(inner) => inner,
             None => {
                 return Err(ContractError::Header(
@@ -514,31 +799,148 @@ impl HeaderQueue {
             }
         };
 
-        if current_header.time() <= *median_stamp {
+        if current_header.time() <= median_stamp {
             return Err(ContractError::Header(
                 "Header contains an invalid timestamp".into(),
             ));
         }
 
-        // TODO: compare timestamps with max_time_increase over the current
-        // clock time (not the previous header's time)
-        // if max(current_header.time(), previous_header.time())
-        //     - min(current_header.time(), previous_header.time())
-        //     > self.config.max_time_increase
-        // {
-        //     return Err(ContractError::Header(
-        //         "Timestamp is too far ahead of previous timestamp".into(),
-        //     ));
-        // }
+        Ok(())
+    }
+
+    /// Store the headers of a branch which does not have more work than the
+    /// best chain as a candidate fork, so later relays can extend it. When
+    /// `MAX_FORKS` forks are stored, a new fork replaces the one with the
+    /// least work, and is rejected if it does not have more work than it.
+    fn save_fork(&self, env: &Env, store: &mut dyn Storage, branch: Branch) -> ContractResult<()> {
+        let tip = branch
+            .headers
+            .last()
+            .ok_or_else(|| ContractError::Header("Passed header list empty".into()))?;
+        if FORK_TIPS.has(store, &tip.block_hash()[..]) {
+            return Err(ContractError::Header("Provided redundant header.".into()));
+        }
 
+        let (id, mut fork) = match branch.fork {
+            Some((id, fork)) => {
+                FORK_TIPS.remove(store, &fork.tip_hash[..]);
+                (id, fork)
+            }
+            None => {
+                let forks = self.forks(store)?;
+                if forks.len() >= MAX_FORKS {
+                    // make room by evicting the fork with the least work (the
+                    // stalest one among equals), if the new fork has more
+                    let (weakest_id, weakest) = forks
+                        .into_iter()
+                        .min_by_key(|(_, fork)| (*fork.chain_work, fork.updated_at))
+                        .ok_or_else(|| ContractError::Header("Fork not found".into()))?;
+                    if *tip.chain_work <= *weakest.chain_work {
+                        return Err(ContractError::Header("Too many candidate forks".into()));
+                    }
+                    self.remove_fork(store, weakest_id, &weakest)?;
+                }
+
+                let base = self
+                    .get_by_height(store, branch.base_height, Some(branch.initial_height))?
+                    .ok_or_else(|| ContractError::Header("Header not found".into()))?;
+                let id = NEXT_FORK_ID.may_load(store)?.unwrap_or_default();
+                NEXT_FORK_ID.save(store, &(id + 1))?;
+
+                let fork = Fork {
+                    base_height: base.height(),
+                    base_hash: Adapter::new(base.block_hash()),
+                    tip_height: base.height(),
+                    tip_hash: Adapter::new(base.block_hash()),
+                    chain_work: base.chain_work,
+                    updated_at: 0,
+                };
+                (id, fork)
+            }
+        };
+
+        for header in branch.headers.iter() {
+            FORK_HEADERS.save(store, (id, header.height()), header)?;
+            FORK_HEADER_HASHES.save(store, &header.block_hash()[..], &(id, header.height()))?;
+        }
+        fork.tip_height = tip.height();
+        fork.tip_hash = Adapter::new(tip.block_hash());
+        fork.chain_work = tip.chain_work.clone();
+        fork.updated_at = env.block.time.seconds();
+
+        FORKS.save(store, id, &fork)?;
+        FORK_TIPS.save(store, &fork.tip_hash[..], &id)?;
+
+        Ok(())
+    }
+
+    /// Delete a candidate fork and its headers.
+    fn remove_fork(&self, store: &mut dyn Storage, id: u64, fork: &Fork) -> ContractResult<()> {
+        for height in fork.base_height + 1..=fork.tip_height {
+            let hash = FORK_HEADERS.load(store, (id, height))?.block_hash();
+            // another fork may have stored the same header since
+            if FORK_HEADER_HASHES.may_load(store, &hash[..])? == Some((id, height)) {
+                FORK_HEADER_HASHES.remove(store, &hash[..]);
+            }
+            FORK_HEADERS.remove(store, (id, height));
+        }
+        FORK_TIPS.remove(store, &fork.tip_hash[..]);
+        FORKS.remove(store, id);
         Ok(())
     }
 
+    /// Delete candidate forks which have not been extended for
+    /// `MAX_FORK_AGE` seconds, or which no longer branch off the best chain.
+    fn prune_forks(&self, env: &Env, store: &mut dyn Storage) -> ContractResult<()> {
+        let forks = FORKS
+            .range(store, None, None, Order::Ascending)
+            .collect::<Result<Vec<_>, _>>()?;
+        let initial_height = self.get_initial_height(store)?;
+
+        for (id, fork) in forks {
+            let expired = env.block.time.seconds() > fork.updated_at + MAX_FORK_AGE;
+            let detached = fork.base_height < initial_height
+                || HEADER_HASHES.may_load(store, &fork.base_hash[..])? != Some(fork.base_height);
+            if expired || detached {
+                self.remove_fork(store, id, &fork)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The candidate forks which are currently stored, by id.
+    pub fn forks(&self, store: &dyn Storage) -> ContractResult<Vec<(u64, Fork)>> {
+        let forks = FORKS
+            .range(store, None, None, Order::Ascending)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(forks)
+    }
+
     /// The height of the last header in the header queue.    
     pub fn height(&self, store: &dyn Storage) -> ContractResult<u32> {
         header_height(store)
     }
 
+    /// The median timestamp of the last 11 headers of the best chain (or of
+    /// all of them, if there are fewer), which a new header must exceed.
+    pub fn median_time_past(&self, store: &dyn Storage) -> ContractResult<u32> {
+        let height = self.height(store)?;
+        let start = height.saturating_sub(10).max(self.first_height(store)?);
+        let mut timestamps = vec![];
+        for prev_height in start..=height {
+            let header = self
+                .get_by_height(store, prev_height, None)?
+                .ok_or_else(|| {
+                    ContractError::Header("Deque does not contain any elements".into())
+                })?;
+            timestamps.push(header.time());
+        }
+
+        median_timestamp(timestamps.into_iter())
+            .ok_or_else(|| ContractError::Header("Median timestamp does not exist".into()))
+    }
+
     /// The hash of the last header in the header queue.    
     pub fn hash(&self, store: &dyn Storage) -> ContractResult<BlockHash> {
         match HEADERS.back(store)? {
@@ -578,8 +980,8 @@ impl HeaderQueue {
     /// If the header queue does not contain a header at the passed height,
     /// `None` will be returned.
     ///
-    /// If the passed height is less than the initial height of the header queue,
-    /// an error will be returned.    
+    /// If the passed height is less than the initial height of the header queue
+    /// and is not a historical header, an error will be returned.    
     pub fn get_by_height(
         &self,
         store: &dyn Storage,
@@ -592,6 +994,10 @@ impl HeaderQueue {
         };
 
         if height < initial_height {
+            if let Some(header) = HISTORICAL_HEADERS.may_load(store, height)? {
+                return Ok(Some(header));
+            }
+
             // TODO: error message is wrong
             // TODO: shouldn't this just return None?
             return Err(ContractError::Header(
@@ -603,6 +1009,104 @@ impl HeaderQueue {
         Ok(header)
     }
 
+    /// Gets the header with the given block hash, if it is part of the best
+    /// chain in the header queue.
+    pub fn get_by_hash(
+        &self,
+        store: &dyn Storage,
+        hash: BlockHash,
+    ) -> ContractResult<Option<WorkHeader>> {
+        match HEADER_HASHES.may_load(store, &hash[..])? {
+            Some(height) => self.get_by_height(store, height, None),
+            None => Ok(None),
+        }
+    }
+
+    /// Get a header of a stored candidate fork by its hash.
+    pub fn get_fork_header_by_hash(
+        &self,
+        store: &dyn Storage,
+        hash: BlockHash,
+    ) -> ContractResult<Option<WorkHeader>> {
+        match FORK_HEADER_HASHES.may_load(store, &hash[..])? {
+            Some(key) => Ok(FORK_HEADERS.may_load(store, key)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Gets the header for a relayed proof, which names its block by height
+    /// and optionally by hash. When a hash is given, the block must still be
+    /// on the best chain at the given height.
+    pub fn get_for_proof(
+        &self,
+        store: &dyn Storage,
+        height: u32,
+        hash: Option<BlockHash>,
+    ) -> ContractResult<WorkHeader> {
+        let header = match hash {
+            Some(hash) => self.get_by_hash(store, hash)?.ok_or_else(|| {
+                ContractError::App(format!("Block {} is not on the best chain", hash))
+            })?,
+            None => self
+                .get_by_height(store, height, None)?
+                .ok_or_else(|| ContractError::App("Invalid bitcoin block height".to_string()))?,
+        };
+
+        if header.height() != height {
+            return Err(ContractError::App(format!(
+                "Block {} is at height {}, not {}",
+                header.block_hash(),
+                header.height(),
+                height
+            )));
+        }
+
+        Ok(header)
+    }
+
+    /// Builds a block locator: hashes of the best chain starting at the tip,
+    /// one per block for the most recent blocks and then exponentially spaced,
+    /// always ending with the first header in the queue.
+    pub fn locator(&self, store: &dyn Storage) -> ContractResult<Vec<BlockHash>> {
+        let initial_height = self.get_initial_height(store)?;
+        let mut height = self.height(store)?;
+        let mut step = 1;
+        let mut hashes = vec![];
+
+        loop {
+            let header = self
+                .get_by_height(store, height, Some(initial_height))?
+                .ok_or_else(|| ContractError::Header("Header not found".into()))?;
+            hashes.push(header.block_hash());
+
+            if height == initial_height {
+                break;
+            }
+            if hashes.len() > 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step).max(initial_height);
+        }
+
+        Ok(hashes)
+    }
+
+    /// Returns the highest height of the given hashes which is part of the
+    /// best chain, if any.
+    pub fn find_fork_point(
+        &self,
+        store: &dyn Storage,
+        hashes: &[BlockHash],
+    ) -> ContractResult<Option<u32>> {
+        let mut fork_point = None;
+        for hash in hashes {
+            if let Some(height) = HEADER_HASHES.may_load(store, &hash[..])? {
+                fork_point = fork_point.max(Some(height));
+            }
+        }
+        Ok(fork_point)
+    }
+
     /// The height of the configured trusted header.    
     pub fn trusted_height(&self, store: &dyn Storage) -> ContractResult<u32> {
         let config = HEADER_CONFIG.load(store)?;
@@ -616,7 +1120,21 @@ impl HeaderQueue {
         store: &mut dyn Storage,
         config: HeaderConfig,
     ) -> ContractResult<()> {
+        let headers = HEADERS.iter(store)?.collect::<Result<Vec<_>, _>>()?;
+        for header in headers {
+            HEADER_HASHES.remove(store, &header.block_hash()[..]);
+        }
         HEADERS.clear(store)?;
+        let history = HISTORICAL_HEADERS
+            .range(store, None, None, Order::Ascending)
+            .collect::<Result<Vec<_>, _>>()?;
+        for (height, header) in history {
+            HISTORICAL_HEADERS.remove(store, height);
+            HEADER_HASHES.remove(store, &header.block_hash()[..]);
+        }
+        for (id, fork) in self.forks(store)? {
+            self.remove_fork(store, id, &fork)?;
+        }
         let wrapped_header = WrappedHeader::new(config.trusted_header, config.trusted_height);
         let work = wrapped_header.work();
         let work_header = WorkHeader::new(wrapped_header, work);
@@ -625,6 +1143,7 @@ impl HeaderQueue {
         CURRENT_WORK.save(store, &work_header.chain_work)?;
 
         HEADERS.push_front(store, &work_header)?;
+        HEADER_HASHES.save(store, &work_header.block_hash()[..], &work_header.height())?;
 
         // self.config = config;
         HEADER_CONFIG.save(store, &config)?;
@@ -632,13 +1151,356 @@ impl HeaderQueue {
         Ok(())
     }
 
+    /// Verify and add a list of headers which precede the oldest stored
+    /// header, extending the history of the header queue into the past.
+    ///
+    /// The headers must be consecutive and the last one must be the parent of
+    /// the oldest stored header. Since each header is committed to by the hash
+    /// of the next one, the linkage alone proves they are part of the chain;
+    /// their proof-of-work and difficulty are still checked against the next
+    /// header.
+    pub fn add_historical(
+        &mut self,
+        store: &mut dyn Storage,
+        headers: HeaderList,
+    ) -> ContractResult<()> {
+        let headers: Vec<_> = headers.into();
+        if headers.len() as u64 > MAX_HEADERS_RELAY_ONE_TIME {
+            return Err(ContractError::App(
+                "Exceeded maximum amount of relayed headers".to_string(),
+            ));
+        }
+        if headers.is_empty() {
+            return Err(ContractError::App("Passed header list empty".to_string()));
+        }
+
+        let config = self.config(store)?;
+        let initial_height = self.get_initial_height(store)?;
+        let first_height = self.first_height(store)?;
+        let history_len = (initial_height - first_height) as u64 + headers.len() as u64;
+        if history_len > config.max_history_length {
+            return Err(ContractError::App(
+                "Historical headers exceed the maximum history length".to_string(),
+            ));
+        }
+
+        let checkpoints = checkpoint_map(&config);
+        let mut next = self
+            .get_by_height(store, first_height, Some(initial_height))?
+            .ok_or_else(|| ContractError::App("Header not found".to_string()))?;
+        for header in headers.iter().rev() {
+            if header.height() + 1 != next.height() {
+                return Err(ContractError::App(
+                    "Non-consecutive headers passed".to_string(),
+                ));
+            }
+            if header.block_hash() != next.header.prev_blockhash() {
+                return Err(ContractError::App(
+                    "Passed header is not the previous block of the next header".to_string(),
+                ));
+            }
+            validate_checkpoint(&checkpoints, header)?;
+            self.validate_historical_target(&config, header, &next.header)?;
+            header.validate_pow(&header.target())?;
+
+            // The work before the trusted header is not known, so the chain
+            // work of historical headers is floored at zero. It is not
+            // returned by the header queries.
+            let chain_work = if *next.chain_work > next.work() {
+                *next.chain_work - next.work()
+            } else {
+                Uint256::zero()
+            };
+            let work_header = WorkHeader::new(header.clone(), chain_work);
+            HISTORICAL_HEADERS.save(store, header.height(), &work_header)?;
+            HEADER_HASHES.save(store, &header.block_hash()[..], &header.height())?;
+            next = work_header;
+        }
+
+        Ok(())
+    }
+
+    /// Check the difficulty of a historical header against the next header.
+    /// Within a retargeting period both must use the same target, and a
+    /// retarget can change the target by at most a factor of 4. Networks with
+    /// minimum difficulty blocks are only checked against the maximum target.
+    fn validate_historical_target(
+        &self,
+        config: &HeaderConfig,
+        header: &WrappedHeader,
+        next: &WrappedHeader,
+    ) -> ContractResult<()> {
+        if header.target() > WrappedHeader::u256_from_compact(config.max_target) {
+            return Err(ContractError::App(
+                "Header target is above the maximum target".to_string(),
+            ));
+        }
+        if config.min_difficulty_blocks {
+            return Ok(());
+        }
+
+        if config.retargeting && next.height() % config.retarget_interval == 0 {
+            let four = WrappedHeader::u32_to_u256(4);
+            let target = header.target();
+            let next_target = next.target();
+            if next_target > target * four || next_target * four < target {
+                return Err(ContractError::App(
+                    "Header target is not within the retarget range of the next header".to_string(),
+                ));
+            }
+        } else if header.bits() != next.bits() {
+            return Err(ContractError::App(
+                "Header target does not match the next header".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// The height of the oldest stored header, including historical headers.
+    pub fn first_height(&self, store: &dyn Storage) -> ContractResult<u32> {
+        let history_start = HISTORICAL_HEADERS
+            .keys(store, None, None, Order::Ascending)
+            .next()
+            .transpose()?;
+        match history_start {
+            Some(height) => Ok(height),
+            None => self.get_initial_height(store),
+        }
+    }
+
+    /// Keep a header removed from the front of the queue as a historical
+    /// header, if history is kept.
+    fn move_to_history(
+        &self,
+        store: &mut dyn Storage,
+        config: &HeaderConfig,
+        header: WorkHeader,
+    ) -> ContractResult<()> {
+        if config.max_history_length == 0 {
+            HEADER_HASHES.remove(store, &header.block_hash()[..]);
+            return Ok(());
+        }
+        HISTORICAL_HEADERS.save(store, header.height(), &header)?;
+        Ok(())
+    }
+
+    /// Drop the oldest historical headers until at most `max_history_length`
+    /// of them are kept.
+    pub fn trim_history(
+        &self,
+        store: &mut dyn Storage,
+        config: &HeaderConfig,
+    ) -> ContractResult<()> {
+        let initial_height = self.get_initial_height(store)?;
+        let mut height = self.first_height(store)?;
+        while ((initial_height - height) as u64) > config.max_history_length {
+            let header = HISTORICAL_HEADERS.load(store, height)?;
+            HISTORICAL_HEADERS.remove(store, height);
+            HEADER_HASHES.remove(store, &header.block_hash()[..]);
+            height += 1;
+        }
+        Ok(())
+    }
+
+    /// Appends a known block to the checkpoints of the header config. The
+    /// checkpoint must be above the existing ones and must not conflict with
+    /// the stored headers. Candidate forks which conflict with it are dropped.
+    pub fn add_checkpoint(
+        &mut self,
+        store: &mut dyn Storage,
+        height: u32,
+        hash: BlockHash,
+    ) -> ContractResult<()> {
+        let mut config = self.config(store)?;
+        if let Some((last_height, _)) = config.checkpoints.last() {
+            if height <= *last_height {
+                return Err(ContractError::Header(
+                    "Checkpoints must be added in ascending order of height".into(),
+                ));
+            }
+        }
+
+        if height >= self.first_height(store)? {
+            if let Some(header) = self.get_by_height(store, height, None)? {
+                if header.block_hash() != hash {
+                    return Err(ContractError::Header(
+                        "Checkpoint conflicts with the best chain".into(),
+                    ));
+                }
+            }
+        }
+
+        for (id, fork) in self.forks(store)? {
+            if height <= fork.base_height || height > fork.tip_height {
+                continue;
+            }
+            if FORK_HEADERS.load(store, (id, height))?.block_hash() != hash {
+                self.remove_fork(store, id, &fork)?;
+            }
+        }
+
+        config.checkpoints.push((height, WrappedBinary(hash)));
+        HEADER_CONFIG.save(store, &config)?;
+        Ok(())
+    }
+
+    /// Recomputes the chain work of the stored headers so it is cumulative
+    /// from the first header of the queue, and the current work so it equals
+    /// the chain work of the last header. Queues which subtracted the work of
+    /// pruned headers from the current work are migrated with this. Candidate
+    /// forks are dropped, they can be relayed again.
+    pub fn recompute_chain_work(&mut self, store: &mut dyn Storage) -> ContractResult<()> {
+        for (id, fork) in self.forks(store)? {
+            self.remove_fork(store, id, &fork)?;
+        }
+
+        let front = HEADERS
+            .front(store)?
+            .ok_or_else(|| ContractError::Header("Queue does not contain any headers".into()))?;
+
+        // rotate the queue once, rewriting each header
+        let mut chain_work = *front.chain_work - front.work();
+        for _ in 0..self.len(store)? {
+            let mut header = HEADERS
+                .pop_front(store)?
+                .ok_or_else(|| ContractError::Header("Removed all headers".into()))?;
+            chain_work = chain_work + header.work();
+            header.chain_work = Adapter::new(chain_work);
+            HEADERS.push_back(store, &header)?;
+        }
+        CURRENT_WORK.save(store, &Adapter::new(chain_work))?;
+
+        let mut next = front;
+        for height in (self.first_height(store)?..next.height()).rev() {
+            let mut header = HISTORICAL_HEADERS.load(store, height)?;
+            header.chain_work = Adapter::new(if *next.chain_work > next.work() {
+                *next.chain_work - next.work()
+            } else {
+                Uint256::zero()
+            });
+            HISTORICAL_HEADERS.save(store, height, &header)?;
+            next = header;
+        }
+
+        Ok(())
+    }
+
+    /// Saves the hash of every header of the best chain, including the
+    /// historical headers, in `HEADER_HASHES`. Queues stored before the hash
+    /// index existed are migrated with this.
+    pub fn index_hashes(&self, store: &mut dyn Storage) -> ContractResult<()> {
+        let mut headers = HISTORICAL_HEADERS
+            .range(store, None, None, Order::Ascending)
+            .map(|entry| entry.map(|(_, header)| header))
+            .collect::<Result<Vec<_>, _>>()?;
+        headers.extend(HEADERS.iter(store)?.collect::<Result<Vec<_>, _>>()?);
+        for header in headers {
+            HEADER_HASHES.save(store, &header.block_hash()[..], &header.height())?;
+        }
+        Ok(())
+    }
+
+    /// Moves the trusted header of the header queue.
+    ///
+    /// If the new trusted header is part of the best chain, the headers before
+    /// it are dropped and the rest of the queue is kept, so proofs against
+    /// those headers stay valid. Otherwise the queue is cleared and restarts
+    /// from the new trusted header. Historical headers can not become the
+    /// trusted header, since they are below the front of the queue.
+    ///
+    /// A trusted header which is not the first header of its retargeting
+    /// period needs a retarget anchor, which is taken from the queue if the
+    /// first header of the period is still part of it.
+    pub fn reanchor(
+        &mut self,
+        store: &mut dyn Storage,
+        trusted: WrappedHeader,
+        retarget_anchor: Option<RetargetAnchor>,
+    ) -> ContractResult<()> {
+        let mut config = self.config(store)?;
+        let initial_height = self.get_initial_height(store)?;
+        let on_best_chain =
+            HEADER_HASHES.may_load(store, &trusted.block_hash()[..])? == Some(trusted.height);
+
+        // historical headers are only kept below the front of the queue, so
+        // the queue can not be moved back onto one of them
+        if on_best_chain && trusted.height < initial_height {
+            return Err(ContractError::Header(
+                "Trusted header is below the front of the header queue".into(),
+            ));
+        }
+
+        let period_start = trusted.height - trusted.height % config.retarget_interval;
+        config.retarget_anchor = None;
+        if config.retargeting && period_start != trusted.height {
+            let anchor = match retarget_anchor {
+                Some(anchor) => Some(anchor),
+                None if on_best_chain && period_start >= initial_height => self
+                    .get_by_height(store, period_start, Some(initial_height))?
+                    .map(|header| RetargetAnchor {
+                        time: header.time(),
+                        bits: header.header.bits(),
+                    }),
+                None => None,
+            };
+            config.retarget_anchor = Some(anchor.ok_or_else(|| {
+                ContractError::Header(
+                    "Trusted header is not the first header of its retargeting period and no retarget anchor was given".into(),
+                )
+            })?);
+        }
+        config.trusted_height = trusted.height;
+        config.trusted_header = trusted.header.clone();
+
+        if !on_best_chain {
+            return self.configure(store, config);
+        }
+
+        for _ in initial_height..trusted.height {
+            let header = HEADERS
+                .pop_front(store)?
+                .ok_or_else(|| ContractError::Header("Removed all headers".into()))?;
+            self.move_to_history(store, &config, header)?;
+        }
+        self.trim_history(store, &config)?;
+        HEADER_CONFIG.save(store, &config)?;
+
+        Ok(())
+    }
+
     /// The network the header queue is configured for.
-    pub fn network(&self) -> bitcoin::Network {
-        // TODO: should be dynamic, from config
-        #[cfg(feature = "mainnet")]
-        return bitcoin::Network::Bitcoin;
+    pub fn network(&self, store: &dyn Storage) -> ContractResult<bitcoin::Network> {
+        Ok(self.config(store)?.network)
+    }
+}
+
+/// The checkpoints of the passed config, by height.
+fn checkpoint_map(config: &HeaderConfig) -> HashMap<u32, BlockHash> {
+    config
+        .checkpoints
+        .iter()
+        .map(|(height, hash)| (*height, hash.0))
+        .collect()
+}
 
-        #[cfg(not(feature = "mainnet"))]
-        return bitcoin::Network::Testnet;
+/// Reject a header which conflicts with a checkpoint.
+fn validate_checkpoint(
+    checkpoints: &HashMap<u32, BlockHash>,
+    header: &WrappedHeader,
+) -> ContractResult<()> {
+    match checkpoints.get(&header.height()) {
+        Some(hash) if *hash != header.block_hash() => Err(ContractError::Header(format!(
+            "Header conflicts with checkpoint at height {}",
+            header.height()
+        ))),
+        _ => Ok(()),
     }
 }
+
+/// The median of the passed timestamps, or `None` if there are none.
+fn median_timestamp(timestamps: impl Iterator<Item = u32>) -> Option<u32> {
+    let mut timestamps: Vec<u32> = timestamps.collect();
+    timestamps.sort_unstable();
+    timestamps.get(timestamps.len() / 2).copied()
+}
@@ -1,5 +1,6 @@
 use super::utils::{
     get_wrapped_header_from_block_hash, populate_bitcoin_block, retry, test_bitcoin_client,
+    MOCK_TIME, MOCK_TIME_ARG,
 };
 use crate::adapter::Adapter;
 use crate::adapter::WrappedBinary;
@@ -22,6 +23,7 @@ use bitcoind::{BitcoinD, Conf};
 use cosmwasm_std::coins;
 use cosmwasm_std::{Addr, Binary, Coin, Uint128};
 use cosmwasm_testing_util::MockResult;
+use libsecp256k1_core::curve::ECMultContext;
 use std::str::FromStr;
 
 use oraiswap::asset::AssetInfo;
@@ -109,8 +111,10 @@ async fn relay_checkpoint(
                     bitcoin_bridge_addr.clone(),
                     &msg::ExecuteMsg::RelayCheckpoint {
                         btc_height: block_height,
+                        btc_block_hash: Some(WrappedBinary(block)),
                         btc_proof: Adapter::from(proof),
                         cp_index: checkpoint_index,
+                        btc_coinbase_proof: None,
                     },
                     &[],
                 )
@@ -140,6 +144,9 @@ async fn relay_recovery(
         let txid = btc_client
             .send_raw_transaction(&transaction.into_inner())
             .await;
+        if recovery_tx.paid_to_recovery_script {
+            continue;
+        }
         let headers = mine_and_relay_headers(
             btc_client,
             app,
@@ -164,10 +171,12 @@ async fn relay_recovery(
             &msg::ExecuteMsg::RelayDeposit {
                 btc_tx: recovery_tx.tx.clone(),
                 btc_height: header.height(),
+                btc_block_hash: Some(WrappedBinary(header.block_hash())),
                 btc_proof: Adapter::from(proof),
                 btc_vout: 0, // always is zero for sure
                 sigset_index: recovery_tx.sigset_index,
                 dest: recovery_tx.dest,
+                btc_coinbase_proof: None,
             },
             &[],
         )
@@ -175,7 +184,6 @@ async fn relay_recovery(
     }
 }
 
-#[cfg(feature = "mainnet")]
 #[tokio::test]
 async fn test_full_flow_happy_case_bitcoin() {
     // Set up app
@@ -190,6 +198,7 @@ async fn test_full_flow_happy_case_bitcoin() {
         ("token_fee_receiver", &coins(100_000_000_000, "orai")),
         ("receiver", &coins(100_000_000_000, "orai")),
     ]);
+    app.advance_past(MOCK_TIME);
     let owner = Addr::unchecked(&accounts[0]);
     let validator_1 = Addr::unchecked(&accounts[1]);
     let validator_2 = Addr::unchecked(&accounts[2]);
@@ -218,6 +227,8 @@ async fn test_full_flow_happy_case_bitcoin() {
                 token_fee_receiver: token_fee_receiver.clone(),
                 swap_router_contract: None,
                 osor_entry_point_contract: None,
+                network: None,
+                preset: None,
             },
         )
         .unwrap();
@@ -225,6 +236,7 @@ async fn test_full_flow_happy_case_bitcoin() {
     // Set up bitcoin
     let mut conf = Conf::default();
     conf.args.push("-txindex");
+    conf.args.push(MOCK_TIME_ARG);
     let bitcoind = BitcoinD::with_conf(bitcoind::downloaded_exe_path().unwrap(), &conf).unwrap();
     let rpc_url = bitcoind.rpc_url();
     let cookie_file = bitcoind.params.cookie_file.clone();
@@ -296,6 +308,11 @@ async fn test_full_flow_happy_case_bitcoin() {
                 max_target: 0x1d00ffff,
                 retargeting: true,
                 min_difficulty_blocks: false,
+                enforce_bip94: false,
+                retarget_anchor: None,
+                max_history_length: 0,
+                checkpoints: vec![],
+                network: bitcoin::Network::Regtest,
                 trusted_header: Adapter::from(trusted_header),
             };
             app.execute(
@@ -323,10 +340,12 @@ async fn test_full_flow_happy_case_bitcoin() {
             &msg::ExecuteMsg::RelayDeposit {
                 btc_tx,
                 btc_height,
+                btc_block_hash: None,
                 btc_proof,
                 btc_vout,
                 sigset_index,
                 dest,
+                btc_coinbase_proof: None,
             },
             &[],
         )
@@ -463,7 +482,7 @@ async fn test_full_flow_happy_case_bitcoin() {
     assert_eq!(header_height, 1020);
 
     // Set up 2 validators here
-    let network = bitcoin::Network::Bitcoin; // This is actually hard-coded
+    let network = bitcoin::Network::Regtest;
     let secp = Secp256k1::new();
     let xprivs = vec![
         ExtendedPrivKey::new_master(network, &[0]).unwrap(),
@@ -524,8 +543,9 @@ async fn test_full_flow_happy_case_bitcoin() {
 
     // [TESTCASE] Bridge one transaction and try to submit tx with proof when not enough confirmations
     let dest = Dest::Address(receiver.clone());
+    let ctx = ECMultContext::new_boxed();
     let script = sigset
-        .output_script(&dest.commitment_bytes().unwrap(), threshold)
+        .output_script(&ctx, &dest.commitment_bytes().unwrap(), threshold)
         .unwrap();
     let deposit_addr = bitcoin::Address::from_script(&script, bitcoin::Network::Regtest).unwrap();
     let deposit_amount = bitcoin::Amount::from_btc(1.2).unwrap();
@@ -710,7 +730,7 @@ async fn test_full_flow_happy_case_bitcoin() {
     let withdraw_address = wallet.get_new_address(None, None).unwrap();
     let script = checkpoint
         .sigset
-        .output_script(&dest.commitment_bytes().unwrap(), threshold)
+        .output_script(&ctx, &dest.commitment_bytes().unwrap(), threshold)
         .unwrap();
     withdraw_to_bitcoin(
         &mut app,
@@ -987,7 +1007,6 @@ async fn test_full_flow_happy_case_bitcoin() {
     println!("[BRAVOOO] All testcases passed!");
 }
 
-#[cfg(feature = "mainnet")]
 #[tokio::test]
 #[serial_test::serial]
 async fn test_deposit_with_token_fee() {
@@ -1001,6 +1020,7 @@ async fn test_deposit_with_token_fee() {
         ("token_fee_receiver", &coins(100_000_000_000, "orai")),
         ("receiver", &coins(100_000_000_000, "orai")),
     ]);
+    app.advance_past(MOCK_TIME);
     let owner = Addr::unchecked(&accounts[0]);
     let validator_1 = Addr::unchecked(&accounts[1]);
     let validator_2 = Addr::unchecked(&accounts[2]);
@@ -1027,6 +1047,8 @@ async fn test_deposit_with_token_fee() {
                 token_fee_receiver: token_fee_receiver.clone(),
                 swap_router_contract: None,
                 osor_entry_point_contract: None,
+                network: None,
+                preset: None,
             },
         )
         .unwrap();
@@ -1034,6 +1056,7 @@ async fn test_deposit_with_token_fee() {
     // Set up bitcoin
     let mut conf = Conf::default();
     conf.args.push("-txindex");
+    conf.args.push(MOCK_TIME_ARG);
     let bitcoind = BitcoinD::with_conf(bitcoind::downloaded_exe_path().unwrap(), &conf).unwrap();
     let rpc_url = bitcoind.rpc_url();
     let cookie_file = bitcoind.params.cookie_file.clone();
@@ -1105,6 +1128,11 @@ async fn test_deposit_with_token_fee() {
                 max_target: 0x1d00ffff,
                 retargeting: true,
                 min_difficulty_blocks: false,
+                enforce_bip94: false,
+                retarget_anchor: None,
+                max_history_length: 0,
+                checkpoints: vec![],
+                network: bitcoin::Network::Regtest,
                 trusted_header: Adapter::from(trusted_header),
             };
             app.execute(
@@ -1132,10 +1160,12 @@ async fn test_deposit_with_token_fee() {
             &msg::ExecuteMsg::RelayDeposit {
                 btc_tx,
                 btc_height,
+                btc_block_hash: None,
                 btc_proof,
                 btc_vout,
                 sigset_index,
                 dest,
+                btc_coinbase_proof: None,
             },
             &[],
         )
@@ -1253,7 +1283,7 @@ async fn test_deposit_with_token_fee() {
     assert_eq!(header_height, 1020);
 
     // Set up 2 validators here
-    let network = bitcoin::Network::Bitcoin; // This is actually hard-coded
+    let network = bitcoin::Network::Regtest;
     let secp = Secp256k1::new();
     let xprivs = vec![
         ExtendedPrivKey::new_master(network, &[0]).unwrap(),
@@ -1293,8 +1323,9 @@ async fn test_deposit_with_token_fee() {
 
     // [TESTCASE] Bridge one transaction
     let dest = Dest::Address(receiver.clone());
+    let ctx = ECMultContext::new_boxed();
     let script = sigset
-        .output_script(&dest.commitment_bytes().unwrap(), threshold)
+        .output_script(&ctx, &dest.commitment_bytes().unwrap(), threshold)
         .unwrap();
     let deposit_addr = bitcoin::Address::from_script(&script, bitcoin::Network::Regtest).unwrap();
     let deposit_amount = bitcoin::Amount::from_btc(1.2).unwrap();
@@ -1,3 +1,4 @@
+use super::utils::{MOCK_TIME, MOCK_TIME_ARG};
 use crate::{
     adapter::Adapter, header::WrappedHeader, interface::HeaderConfig, msg, tests::helper::MockApp,
 };
@@ -14,7 +15,6 @@ where
     Ok(serde_json::to_value(val).unwrap())
 }
 
-#[cfg(feature = "mainnet")]
 #[tokio::test]
 #[serial_test::serial]
 async fn test_relay_bulk_headers() {
@@ -49,6 +49,8 @@ async fn test_relay_bulk_headers() {
                 token_fee_receiver: token_fee_receiver.clone(),
                 swap_router_contract: None,
                 osor_entry_point_contract: None,
+                network: None,
+                preset: None,
             },
         )
         .unwrap();
@@ -64,6 +66,11 @@ async fn test_relay_bulk_headers() {
         max_target: 0x1d00ffff,
         retargeting: true,
         min_difficulty_blocks: true,
+        enforce_bip94: false,
+        retarget_anchor: None,
+        max_history_length: 0,
+        checkpoints: vec![],
+        network: bitcoin::Network::Testnet,
         trusted_header: Adapter::from(BlockHeader {
             bits: 420466436,
             nonce: 732839121,
@@ -93,6 +100,7 @@ async fn test_relay_bulk_headers() {
     static JSON: &[u8] = include_bytes!("testdata/headers.json");
 
     let headers: Vec<WrappedHeader> = serde_json::from_slice(JSON).unwrap();
+    app.advance_past(headers.last().unwrap().time());
 
     let num = 12;
     for i in 0..num {
@@ -115,6 +123,7 @@ fn reorg() {
     // Set up app
 
     let (mut app, accounts) = MockApp::new(&[("perfogic", &coins(100_000_000_000, "orai"))]);
+    app.advance_past(MOCK_TIME);
     let owner = Addr::unchecked(&accounts[0]);
     let token_factory_addr = app.create_tokenfactory(owner.clone()).unwrap();
     let bitcoin_bridge_addr = app
@@ -130,14 +139,18 @@ fn reorg() {
                 token_fee_receiver: Addr::unchecked("token_fee_receiver"),
                 swap_router_contract: None,
                 osor_entry_point_contract: None,
+                network: None,
+                preset: None,
             },
         )
         .unwrap();
 
     let mut conf = Conf::default();
+    conf.args.push(MOCK_TIME_ARG);
     conf.p2p = P2P::Yes;
     let node_1 = BitcoinD::with_conf(bitcoind::downloaded_exe_path().unwrap(), &conf).unwrap();
     let mut conf = Conf::default();
+    conf.args.push(MOCK_TIME_ARG);
     conf.p2p = node_1.p2p_connect(true).unwrap();
     let node_2 = BitcoinD::with_conf(bitcoind::downloaded_exe_path().unwrap(), &conf).unwrap();
     let alice_address = node_1.client.get_new_address(Some("alice"), None).unwrap();
@@ -167,6 +180,11 @@ fn reorg() {
         max_target: 0x1d00ffff,
         retargeting: true,
         min_difficulty_blocks: false,
+        enforce_bip94: false,
+        retarget_anchor: None,
+        max_history_length: 0,
+        checkpoints: vec![],
+        network: bitcoin::Network::Regtest,
         trusted_header: Adapter::from(tip_header),
     };
     app.execute(
@@ -262,6 +280,7 @@ fn reorg() {
 fn reorg_competing_chain_similar() {
     // Set up app
     let (mut app, accounts) = MockApp::new(&[("perfogic", &coins(100_000_000_000, "orai"))]);
+    app.advance_past(MOCK_TIME);
     let owner = Addr::unchecked(&accounts[0]);
     let token_factory_addr = app.create_tokenfactory(owner.clone()).unwrap();
     let bitcoin_bridge_addr = app
@@ -277,15 +296,19 @@ fn reorg_competing_chain_similar() {
                 token_fee_receiver: Addr::unchecked("token_fee_receiver"),
                 swap_router_contract: None,
                 osor_entry_point_contract: None,
+                network: None,
+                preset: None,
             },
         )
         .unwrap();
 
     let mut conf = Conf::default();
+    conf.args.push(MOCK_TIME_ARG);
     conf.p2p = P2P::Yes;
     let node_1 = BitcoinD::with_conf(bitcoind::downloaded_exe_path().unwrap(), &conf).unwrap();
 
     let mut conf = Conf::default();
+    conf.args.push(MOCK_TIME_ARG);
     conf.p2p = node_1.p2p_connect(true).unwrap();
     let node_2 = BitcoinD::with_conf(bitcoind::downloaded_exe_path().unwrap(), &conf).unwrap();
     let alice_address = node_1.client.get_new_address(Some("alice"), None).unwrap();
@@ -315,6 +338,11 @@ fn reorg_competing_chain_similar() {
         max_target: 0x1d00ffff,
         retargeting: true,
         min_difficulty_blocks: false,
+        enforce_bip94: false,
+        retarget_anchor: None,
+        max_history_length: 0,
+        checkpoints: vec![],
+        network: bitcoin::Network::Regtest,
         trusted_header: Adapter::from(tip_header),
     };
     app.execute(
@@ -411,6 +439,7 @@ fn reorg_competing_chain_similar() {
 fn reorg_deep() {
     // Set up app
     let (mut app, accounts) = MockApp::new(&[("perfogic", &coins(100_000_000_000, "orai"))]);
+    app.advance_past(MOCK_TIME);
     let owner = Addr::unchecked(&accounts[0]);
     let token_factory_addr = app.create_tokenfactory(owner.clone()).unwrap();
     let bitcoin_bridge_addr = app
@@ -426,15 +455,19 @@ fn reorg_deep() {
                 token_fee_receiver: Addr::unchecked("token_fee_receiver"),
                 swap_router_contract: None,
                 osor_entry_point_contract: None,
+                network: None,
+                preset: None,
             },
         )
         .unwrap();
 
     let mut conf = Conf::default();
+    conf.args.push(MOCK_TIME_ARG);
     conf.p2p = P2P::Yes;
     let node_1 = BitcoinD::with_conf(bitcoind::downloaded_exe_path().unwrap(), &conf).unwrap();
 
     let mut conf = Conf::default();
+    conf.args.push(MOCK_TIME_ARG);
     conf.p2p = node_1.p2p_connect(true).unwrap();
     let node_2 = BitcoinD::with_conf(bitcoind::downloaded_exe_path().unwrap(), &conf).unwrap();
     let alice_address = node_1.client.get_new_address(Some("alice"), None).unwrap();
@@ -464,6 +497,11 @@ fn reorg_deep() {
         max_target: 0x1d00ffff,
         retargeting: true,
         min_difficulty_blocks: false,
+        enforce_bip94: false,
+        retarget_anchor: None,
+        max_history_length: 0,
+        checkpoints: vec![],
+        network: bitcoin::Network::Regtest,
         trusted_header: Adapter::from(tip_header),
     };
     app.execute(
@@ -4,6 +4,12 @@ use crate::{adapter::Adapter, header::WrappedHeader};
 use bitcoin::{BlockHash, BlockHeader};
 use bitcoincore_rpc_async::{Auth, Client as BitcoinRpcClient, RpcApi};
 
+/// The clock of the test nodes, so mined headers have fixed timestamps which
+/// do not depend on when the tests run. Blocks are stamped at this time, or
+/// just after the median time of the previous blocks.
+pub const MOCK_TIME: u32 = 1_720_000_000;
+pub const MOCK_TIME_ARG: &str = "-mocktime=1720000000";
+
 pub fn retry<F, T, E>(f: F, max_retries: u32) -> std::result::Result<T, E>
 where
     F: Fn() -> std::result::Result<T, E>,
@@ -1,6 +1,10 @@
+use bitcoin::blockdata::constants::genesis_block;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::hashes::Hash;
 use bitcoin::secp256k1;
 use bitcoin::util::bip32::ExtendedPubKey;
-use bitcoin::BlockHeader;
+use bitcoin::util::merkleblock::PartialMerkleTree;
+use bitcoin::{BlockHash, BlockHeader, Transaction, TxMerkleNode};
 use cosmwasm_schema::{
     cw_serde,
     schemars::JsonSchema,
@@ -18,18 +22,19 @@ use oraiswap::universal_swap_memo::{
 };
 use sha2::{Digest, Sha256};
 
-use crate::adapter::Adapter;
+use crate::adapter::{Adapter, WrappedBinary};
 use crate::app::ConsensusKey;
-use crate::app::NETWORK;
 use crate::constants::{
-    MAX_CHECKPOINT_AGE, MAX_CHECKPOINT_INTERVAL, MAX_DEPOSIT_AGE, MAX_FEE_RATE, MAX_LENGTH,
-    MAX_TARGET, MAX_TIME_INCREASE, MIN_DEPOSIT_AMOUNT, MIN_FEE_RATE, MIN_WITHDRAWAL_AMOUNT,
-    RETARGET_INTERVAL, SIGSET_THRESHOLD, TARGET_SPACING, TARGET_TIMESPAN, TRANSFER_FEE,
-    USER_FEE_FACTOR,
+    EMERGENCY_DISBURSAL_LOCK_TIME_INTERVAL, EMERGENCY_DISBURSAL_MAX_TX_SIZE,
+    EMERGENCY_DISBURSAL_MIN_TX_AMT, MAX_CHECKPOINT_AGE, MAX_CHECKPOINT_INTERVAL, MAX_DEPOSIT_AGE,
+    MAX_FEE_RATE, MAX_LENGTH, MAX_TARGET, MAX_TARGET_REGTEST, MAX_TARGET_SIGNET, MAX_TIME_INCREASE,
+    MIN_DEPOSIT_AMOUNT, MIN_FEE_RATE, MIN_WITHDRAWAL_AMOUNT, RETARGET_INTERVAL, SIGSET_THRESHOLD,
+    TARGET_SPACING, TARGET_TIMESPAN, TRANSFER_FEE, USER_FEE_FACTOR,
 };
 use crate::error::ContractResult;
 use crate::header::WorkHeader;
 use crate::header::WrappedHeader;
+use crate::signatory::{BackupConfig, ScriptType};
 use libsecp256k1_core::curve::{Affine, ECMultContext, Field, Scalar};
 use libsecp256k1_core::util::{TAG_PUBKEY_EVEN, TAG_PUBKEY_ODD};
 use prost::Message;
@@ -65,7 +70,10 @@ impl<'a, T: Serialize + de::DeserializeOwned> DequeExtension<'a, T> {
     }
 
     pub fn get_key(&self, pos: u32) -> Vec<u8> {
-        let key = &pos.to_be_bytes();
+        self.prefixed_key(&pos.to_be_bytes())
+    }
+
+    fn prefixed_key(&self, key: &[u8]) -> Vec<u8> {
         let size = self.namespace.len() + 2 + key.len();
         let mut out = Vec::with_capacity(size);
         out.extend_from_slice(&self.key_prefix);
@@ -74,9 +82,28 @@ impl<'a, T: Serialize + de::DeserializeOwned> DequeExtension<'a, T> {
         out
     }
 
+    /// The storage position of the front of the queue, which moves forward as
+    /// items are popped from the front.
+    fn head(&self, storage: &dyn Storage) -> ContractResult<u32> {
+        match storage.get(&self.prefixed_key(b"h")) {
+            Some(bytes) => {
+                let bytes: [u8; 4] = bytes
+                    .as_slice()
+                    .try_into()
+                    .map_err(|_| StdError::parse_err("u32", "Invalid deque head"))?;
+                Ok(u32::from_be_bytes(bytes))
+            }
+            None => Ok(0),
+        }
+    }
+
     /// Sets the value at the given position in the queue. Returns [`StdError::NotFound`] if index is out of bounds
     pub fn set(&self, storage: &mut dyn Storage, pos: u32, value: &T) -> ContractResult<()> {
-        let prefixed_key = self.get_key(pos);
+        if pos >= self.len(storage)? {
+            return Err(StdError::not_found(format!("Deque position {}", pos)).into());
+        }
+        // Positions are relative to the front of the queue, as in `get`.
+        let prefixed_key = self.get_key(self.head(storage)?.wrapping_add(pos));
         storage.set(&prefixed_key, &to_json_vec(value)?);
         Ok(())
     }
@@ -259,6 +286,17 @@ pub struct BitcoinConfig {
     pub fee_pool_target_balance: u64,
 
     pub fee_pool_reward_split: (u64, u64),
+
+    /// The reward paid to the relayer of each header which stays on the best
+    /// chain for `header_reward_confirmations` confirmations. It is paid in
+    /// `header_reward_denom` from the contract's balance, or in nBTC units
+    /// from the fee pool if no denom is set. Zero disables the rewards.
+    #[serde(default)]
+    pub header_reward: u64,
+    #[serde(default)]
+    pub header_reward_confirmations: u32,
+    #[serde(default)]
+    pub header_reward_denom: Option<String>,
 }
 
 impl BitcoinConfig {
@@ -278,16 +316,16 @@ impl BitcoinConfig {
             max_deposit_age: MAX_DEPOSIT_AGE, // 2 weeks. Initially there may not be many deposits & withdraws
             fee_pool_target_balance: 100_000_000, // 1 BTC
             fee_pool_reward_split: (1, 10),
+            header_reward: 0,
+            header_reward_confirmations: 6,
+            header_reward_denom: None,
         }
     }
 }
 
 impl Default for BitcoinConfig {
     fn default() -> Self {
-        match NETWORK {
-            bitcoin::Network::Testnet | bitcoin::Network::Bitcoin => Self::bitcoin(),
-            _ => unimplemented!(),
-        }
+        Self::bitcoin()
     }
 }
 
@@ -388,6 +426,64 @@ pub struct CheckpointConfig {
     /// issue is simply with relayers failing to report the confirmation of the
     /// checkpoint transactions.    
     pub max_unconfirmed_checkpoints: u32,
+
+    /// The type of output used by newly created signatory sets for deposits
+    /// and the reserve output.
+    ///
+    /// Changing this migrates the reserve to the new output type once the next
+    /// checkpoint is created, since the reserve output is always paid to the
+    /// latest signatory set. Deposits to older signatory sets keep being spent
+    /// with the output type of their own signatory set.
+    #[serde(default)]
+    pub script_type: ScriptType,
+
+    /// The backup multisig included in the scripts of newly created signatory
+    /// sets, which can spend deposits and the reserve once they have been
+    /// unspent for the configured number of blocks, or `None` to not include
+    /// a backup branch.
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
+
+    /// The parameters of the emergency disbursal transactions created for
+    /// each checkpoint, or `None` to not create an emergency disbursal. This
+    /// is disabled by default, so operators must opt in explicitly.
+    #[serde(default)]
+    pub emergency_disbursal: Option<EmergencyDisbursalConfig>,
+}
+
+/// Configuration parameters used in creating the emergency disbursal
+/// transactions of checkpoints.
+#[cw_serde]
+pub struct EmergencyDisbursalConfig {
+    /// The amount of time after a checkpoint advances to `Signing` at which its
+    /// emergency disbursal transactions become valid, in seconds.
+    ///
+    /// If the reserve output of the checkpoint has not been spent by then
+    /// (e.g. because the signatories stopped signing), the emergency
+    /// disbursal can be broadcast to pay out the reserve to the recovery
+    /// scripts of nBTC holders. This should be greater than
+    /// `max_checkpoint_interval` so that the disbursal of a healthy network
+    /// never becomes valid.
+    pub lock_time_interval: u32,
+
+    /// The minimum amount to pay to a recovery script, in satoshis. Balances
+    /// smaller than this are left out of the emergency disbursal.
+    pub min_tx_amt: u64,
+
+    /// The maximum size of each final emergency disbursal transaction, in
+    /// virtual bytes. Outputs which do not fit are moved to additional
+    /// transactions.
+    pub max_tx_size: u64,
+}
+
+impl Default for EmergencyDisbursalConfig {
+    fn default() -> Self {
+        Self {
+            lock_time_interval: EMERGENCY_DISBURSAL_LOCK_TIME_INTERVAL,
+            min_tx_amt: EMERGENCY_DISBURSAL_MIN_TX_AMT,
+            max_tx_size: EMERGENCY_DISBURSAL_MAX_TX_SIZE,
+        }
+    }
 }
 
 impl Default for CheckpointConfig {
@@ -405,6 +501,9 @@ impl Default for CheckpointConfig {
             sigset_threshold: SIGSET_THRESHOLD,
             max_unconfirmed_checkpoints: 15,
             fee_rate: 0,
+            script_type: ScriptType::P2wsh,
+            backup: None,
+            emergency_disbursal: None,
         }
     }
 }
@@ -507,8 +606,26 @@ impl<'de> Deserialize<'de> for Xpub {
     }
 }
 
+/// A proof that a coinbase transaction is the first transaction of a block.
+/// Since the coinbase can not be forged as an inner node of the merkle tree,
+/// it reveals the real depth of the tree, which other proofs against the same
+/// block must match.
+#[cw_serde]
+pub struct CoinbaseProof {
+    pub tx: Adapter<Transaction>,
+    pub proof: Adapter<PartialMerkleTree>,
+}
+
+/// The timestamp and bits of the first header of a retargeting period.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(crate = "cosmwasm_schema::serde")]
+#[schemars(crate = "cosmwasm_schema::schemars")]
+pub struct RetargetAnchor {
+    pub time: u32,
+    pub bits: u32,
+}
+
 ///  HeaderConfiguration parameters for Bitcoin header processing.
-// TODO: implement trait that returns constants for bitcoin::Network variants
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(crate = "cosmwasm_schema::serde")]
 #[schemars(crate = "cosmwasm_schema::schemars")]
@@ -539,9 +656,61 @@ pub struct HeaderConfig {
     /// difficulty after a certain amount of time has passed (used in Bitcoin
     /// testnet).
     pub min_difficulty_blocks: bool,
+    /// Whether or not to enforce the BIP94 rules (used in Bitcoin testnet4):
+    /// the first block of a retargeting period may not be more than
+    /// `MAX_TIMEWARP` seconds older than its parent, and retargeting is based
+    /// on the bits of the first block of the period instead of the last.
+    #[serde(default)]
+    pub enforce_bip94: bool,
+    /// The first header of the retargeting period containing the trusted
+    /// header. Only needed when the trusted header is not the first header of
+    /// its period, so the next difficulty adjustment can be validated.
+    #[serde(default)]
+    pub retarget_anchor: Option<RetargetAnchor>,
+    /// The maximum number of headers kept below the front of the header
+    /// queue, either pruned from the queue or relayed as historical headers,
+    /// so older deposits can still be proven. No history is kept if zero.
+    #[serde(default)]
+    pub max_history_length: u64,
+    /// Known blocks as `(height, hash)` pairs, in ascending order of height.
+    /// Any chain which contains a different block at one of these heights is
+    /// rejected.
+    #[serde(default)]
+    pub checkpoints: Vec<(u32, WrappedBinary<BlockHash>)>,
     /// The trusted header (the header which populates the queue when it is
     /// newly created), as encoded bytes.
     pub trusted_header: Adapter<BlockHeader>,
+    /// The Bitcoin network the headers belong to. Signatory keys and
+    /// withdrawal addresses are checked against this network.
+    #[serde(default = "default_network")]
+    #[schemars(with = "String")]
+    pub network: bitcoin::Network,
+}
+
+/// The header consensus presets a header queue can be configured with. Unlike
+/// the network, a preset tells testnet3 and testnet4 apart.
+#[cw_serde]
+pub enum HeaderPreset {
+    Bitcoin,
+    Testnet3,
+    Testnet4,
+    Signet,
+    Regtest,
+}
+
+impl From<bitcoin::Network> for HeaderPreset {
+    fn from(network: bitcoin::Network) -> Self {
+        match network {
+            bitcoin::Network::Bitcoin => HeaderPreset::Bitcoin,
+            bitcoin::Network::Testnet => HeaderPreset::Testnet3,
+            bitcoin::Network::Signet => HeaderPreset::Signet,
+            bitcoin::Network::Regtest => HeaderPreset::Regtest,
+        }
+    }
+}
+
+fn default_network() -> bitcoin::Network {
+    bitcoin::Network::Bitcoin
 }
 
 impl HeaderConfig {
@@ -553,20 +722,111 @@ impl HeaderConfig {
         let checkpoint: (u32, BlockHeader) = from_json(checkpoint_json)?;
         let (height, header) = checkpoint;
 
-        Ok(Self {
+        Ok(Self::bitcoin(height, header))
+    }
+
+    /// Consensus parameters of Bitcoin mainnet.
+    pub fn bitcoin(trusted_height: u32, trusted_header: BlockHeader) -> Self {
+        Self {
             max_length: MAX_LENGTH,
             max_time_increase: MAX_TIME_INCREASE,
-            trusted_height: height,
+            trusted_height,
             retarget_interval: RETARGET_INTERVAL,
             target_spacing: TARGET_SPACING,
             target_timespan: TARGET_TIMESPAN,
             max_target: MAX_TARGET,
-            trusted_header: header.into(),
+            trusted_header: trusted_header.into(),
             retargeting: true,
             min_difficulty_blocks: false,
+            enforce_bip94: false,
+            retarget_anchor: None,
+            max_history_length: 0,
+            checkpoints: vec![],
+            network: bitcoin::Network::Bitcoin,
+        }
+    }
+
+    /// Consensus parameters of Bitcoin testnet3, where a block may use the
+    /// minimum difficulty if it comes more than 20 minutes after its parent.
+    pub fn testnet3(trusted_height: u32, trusted_header: BlockHeader) -> Self {
+        Self {
+            min_difficulty_blocks: true,
+            network: bitcoin::Network::Testnet,
+            ..Self::bitcoin(trusted_height, trusted_header)
+        }
+    }
+
+    /// Consensus parameters of Bitcoin testnet4, which keeps the testnet3
+    /// minimum difficulty rule and adds the BIP94 rules.
+    pub fn testnet4(trusted_height: u32, trusted_header: BlockHeader) -> Self {
+        Self {
+            enforce_bip94: true,
+            ..Self::testnet3(trusted_height, trusted_header)
+        }
+    }
+
+    /// Consensus parameters of the default Bitcoin signet. Headers do not
+    /// carry the block signature, so only proof-of-work is checked.
+    pub fn signet(trusted_height: u32, trusted_header: BlockHeader) -> Self {
+        Self {
+            max_target: MAX_TARGET_SIGNET,
+            network: bitcoin::Network::Signet,
+            ..Self::bitcoin(trusted_height, trusted_header)
+        }
+    }
+
+    /// Consensus parameters of Bitcoin regtest, which never retargets.
+    pub fn regtest(trusted_height: u32, trusted_header: BlockHeader) -> Self {
+        Self {
+            max_target: MAX_TARGET_REGTEST,
+            retargeting: false,
+            min_difficulty_blocks: true,
+            network: bitcoin::Network::Regtest,
+            ..Self::bitcoin(trusted_height, trusted_header)
+        }
+    }
+
+    /// The default header configuration for the given network. Mainnet starts
+    /// from the bundled checkpoint, other networks start from their genesis
+    /// block.
+    pub fn for_network(network: bitcoin::Network) -> ContractResult<Self> {
+        Self::for_preset(network.into())
+    }
+
+    /// The default header configuration for the given preset. Mainnet starts
+    /// from the bundled checkpoint, other presets start from their genesis
+    /// block.
+    pub fn for_preset(preset: HeaderPreset) -> ContractResult<Self> {
+        Ok(match preset {
+            HeaderPreset::Bitcoin => Self::mainnet()?,
+            HeaderPreset::Testnet3 => {
+                Self::testnet3(0, genesis_block(bitcoin::Network::Testnet).header)
+            }
+            HeaderPreset::Testnet4 => Self::testnet4_genesis(),
+            HeaderPreset::Signet => Self::signet(0, genesis_block(bitcoin::Network::Signet).header),
+            HeaderPreset::Regtest => {
+                Self::regtest(0, genesis_block(bitcoin::Network::Regtest).header)
+            }
         })
     }
 
+    /// Bitcoin testnet4 starting from its genesis block.
+    pub fn testnet4_genesis() -> Self {
+        let genesis = BlockHeader {
+            version: 1,
+            prev_blockhash: BlockHash::all_zeros(),
+            merkle_root: TxMerkleNode::from_hex(
+                "7aa0a7ae1e223414cb807e40cd57e667b718e42aaf9306db9102fe28912b7b4e",
+            )
+            .unwrap(),
+            time: 1714777860,
+            bits: 0x1d00ffff,
+            nonce: 393743547,
+        };
+
+        Self::testnet4(0, genesis)
+    }
+
     pub fn work_header(&self) -> WorkHeader {
         let decoded_adapter: Adapter<BlockHeader> = self.trusted_header.into();
         let wrapped_header = WrappedHeader::new(decoded_adapter, self.trusted_height);
@@ -6,6 +6,7 @@ pub mod error;
 mod header;
 mod interface;
 pub mod msg;
+mod musig;
 
 mod adapter;
 pub mod contract;
@@ -1,4 +1,7 @@
-use bitcoin::{util::merkleblock::PartialMerkleTree, Transaction};
+use bitcoin::util::uint::Uint256;
+use bitcoin::{
+    util::merkleblock::PartialMerkleTree, BlockHash, Script, Transaction, TxMerkleNode, Txid,
+};
 use cosmwasm_schema::{cw_serde, QueryResponses};
 use cosmwasm_std::{Addr, Binary, Coin, Uint128};
 use oraiswap::asset::AssetInfo;
@@ -8,7 +11,10 @@ use crate::{
     adapter::{Adapter, WrappedBinary},
     app::ConsensusKey,
     header::WrappedHeader,
-    interface::{BitcoinConfig, CheckpointConfig, Dest, HeaderConfig, Xpub},
+    interface::{
+        BitcoinConfig, CheckpointConfig, CoinbaseProof, Dest, HeaderConfig, HeaderPreset,
+        RetargetAnchor, Xpub,
+    },
     state::Ratio,
     threshold_sig::Signature,
 };
@@ -29,6 +35,13 @@ pub struct InstantiateMsg {
     pub relayer_fee_receiver: Addr,
     pub swap_router_contract: Option<Addr>,
     pub osor_entry_point_contract: Option<Addr>,
+    // Bitcoin network to operate on, defaults to mainnet
+    #[schemars(with = "Option<String>")]
+    pub network: Option<bitcoin::Network>,
+    // Header consensus preset, which must match `network` if both are set.
+    // Needed to select testnet4, which shares its network with testnet3.
+    #[serde(default)]
+    pub preset: Option<HeaderPreset>,
 }
 
 #[cw_serde]
@@ -56,6 +69,39 @@ pub struct ConfigResponse {
     pub osor_entry_point_contract: Option<Addr>,
 }
 
+#[cw_serde]
+pub struct HeaderResponse {
+    pub height: u32,
+    pub hash: WrappedBinary<BlockHash>,
+    pub prev_hash: WrappedBinary<BlockHash>,
+    pub merkle_root: WrappedBinary<TxMerkleNode>,
+    pub time: u32,
+    pub bits: u32,
+    /// The cumulative work of the header. `None` for historical headers,
+    /// which are below the front of the header queue, since the work of the
+    /// headers before them is not known.
+    pub chain_work: Option<Adapter<Uint256>>,
+    pub confirmations: u32,
+}
+
+#[cw_serde]
+pub struct ChainTipResponse {
+    pub height: u32,
+    pub hash: WrappedBinary<BlockHash>,
+    pub median_time_past: u32,
+    pub time: u32,
+    pub chain_work: Adapter<Uint256>,
+    pub bits: u32,
+    pub target: Adapter<Uint256>,
+}
+
+#[cw_serde]
+pub struct TxInclusionResponse {
+    pub txid: WrappedBinary<Txid>,
+    pub confirmations: u32,
+    pub on_best_chain: bool,
+}
+
 #[cw_serde]
 pub enum ExecuteMsg {
     UpdateConfig {
@@ -77,21 +123,52 @@ pub enum ExecuteMsg {
     UpdateHeaderConfig {
         config: HeaderConfig,
     },
+    /// Moves the trusted header while keeping the headers which are still
+    /// valid, and updates the header queue parameters which do not affect
+    /// header validation.
+    ReanchorHeaders {
+        trusted: Option<WrappedHeader>,
+        retarget_anchor: Option<RetargetAnchor>,
+        max_length: Option<u64>,
+        max_time_increase: Option<u32>,
+        max_history_length: Option<u64>,
+    },
     RelayHeaders {
         headers: Vec<WrappedHeader>,
     },
+    /// Relays concatenated 80-byte consensus-encoded headers, the first of
+    /// which is at `start_height`.
+    RelayRawHeaders {
+        start_height: u32,
+        data: Binary,
+    },
+    /// Pays out the rewards earned by the sender for relaying headers.
+    ClaimHeaderRewards {},
+    /// Appends a known block to the header checkpoints.
+    AddHeaderCheckpoint {
+        height: u32,
+        hash: WrappedBinary<BlockHash>,
+    },
+    /// Relays headers preceding the oldest stored header, in ascending order.
+    RelayHistoricalHeaders {
+        headers: Vec<WrappedHeader>,
+    },
     RelayDeposit {
         btc_tx: Adapter<Transaction>,
         btc_height: u32,
+        btc_block_hash: Option<WrappedBinary<BlockHash>>,
         btc_proof: Adapter<PartialMerkleTree>,
         btc_vout: u32,
         sigset_index: u32,
         dest: Dest,
+        btc_coinbase_proof: Option<CoinbaseProof>,
     },
     RelayCheckpoint {
         btc_height: u32,
+        btc_block_hash: Option<WrappedBinary<BlockHash>>,
         btc_proof: Adapter<PartialMerkleTree>,
         cp_index: u32,
+        btc_coinbase_proof: Option<CoinbaseProof>,
     },
     WithdrawToBitcoin {
         btc_address: String,
@@ -102,6 +179,21 @@ pub enum ExecuteMsg {
         checkpoint_index: u32,
         btc_height: u32,
     },
+    /// Submits the sender's MuSig2 public nonces for the key-path inputs of
+    /// the checkpoint with the given index, for the signatory key registered
+    /// by the sender.
+    SubmitCheckpointNonces {
+        nonces: Vec<Binary>,
+        checkpoint_index: u32,
+    },
+    /// Submits MuSig2 partial signatures for the key-path inputs of the
+    /// checkpoint with the given index, once all nonces have been submitted.
+    SubmitCheckpointPartialSignatures {
+        xpub: WrappedBinary<Xpub>,
+        partial_sigs: Vec<Binary>,
+        checkpoint_index: u32,
+        btc_height: u32,
+    },
     SubmitRecoverySignature {
         xpub: WrappedBinary<Xpub>,
         sigs: Vec<Signature>,
@@ -109,6 +201,9 @@ pub enum ExecuteMsg {
     SetSignatoryKey {
         xpub: WrappedBinary<Xpub>,
     },
+    SetRecoveryScript {
+        script: Adapter<Script>,
+    },
     AddValidators {
         addrs: Vec<String>,
         voting_powers: Vec<u64>,
@@ -139,8 +234,39 @@ pub enum QueryMsg {
     HeaderConfig {},
     #[returns(Option<WrappedBinary<Xpub>>)]
     SignatoryKey { addr: Addr },
+    #[returns(Option<Adapter<Script>>)]
+    RecoveryScript { addr: Addr },
     #[returns(u32)]
     HeaderHeight {},
+    #[returns(ChainTipResponse)]
+    ChainTip {},
+    #[returns(Vec<Coin>)]
+    HeaderRewards { address: Addr },
+    #[returns(Option<HeaderResponse>)]
+    HeaderByHeight { height: u32 },
+    #[returns(Option<HeaderResponse>)]
+    HeaderByHash { hash: WrappedBinary<BlockHash> },
+    #[returns(Vec<HeaderResponse>)]
+    Headers {
+        start_height: Option<u32>,
+        limit: Option<u32>,
+    },
+    #[returns(Vec<WrappedBinary<BlockHash>>)]
+    HeaderLocator {},
+    #[returns(Option<u32>)]
+    FindForkPoint {
+        hashes: Vec<WrappedBinary<BlockHash>>,
+    },
+    #[returns(Vec<crate::header::Reorg>)]
+    RecentReorgs {},
+    #[returns(TxInclusionResponse)]
+    VerifyTxInclusion {
+        tx: Adapter<Transaction>,
+        proof: Adapter<PartialMerkleTree>,
+        height: Option<u32>,
+        block_hash: Option<WrappedBinary<BlockHash>>,
+        coinbase_proof: Option<CoinbaseProof>,
+    },
     #[returns(u64)]
     DepositFees { index: Option<u32> },
     #[returns(u64)]
@@ -151,6 +277,8 @@ pub enum QueryMsg {
     CompletedCheckpointTxs { limit: u32 },
     #[returns(Vec<Adapter<Transaction>>)]
     SignedRecoveryTxs {},
+    #[returns(Vec<Adapter<Transaction>>)]
+    EmergencyDisbursalTxs {},
     #[returns(Adapter<Transaction>)]
     CheckpointTx { index: Option<u32> },
     #[returns(WrappedBinary<bitcoin::BlockHash>)]
@@ -166,8 +294,20 @@ pub enum QueryMsg {
         xpub: WrappedBinary<Xpub>,
         checkpoint_index: u32,
     },
+    #[returns(Vec<([u8; 32], u32)>)]
+    NoncesToSubmitAtCheckpointIndex {
+        xpub: WrappedBinary<Xpub>,
+        checkpoint_index: u32,
+    },
+    #[returns(Vec<crate::checkpoint::MusigSignRequest>)]
+    PartialSigsToSubmitAtCheckpointIndex {
+        xpub: WrappedBinary<Xpub>,
+        checkpoint_index: u32,
+    },
     #[returns(bool)]
     ProcessedOutpoint { key: String },
+    #[returns(Vec<crate::app::RelayedDeposit>)]
+    ReorgedDeposits {},
     // Query index
     #[returns(Option<u32>)]
     ConfirmedIndex {},
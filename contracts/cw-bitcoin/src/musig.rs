@@ -0,0 +1,360 @@
+use crate::error::{ContractError, ContractResult};
+use crate::threshold_sig::{schnorr_verify_xonly, tagged_hash, Pubkey};
+use cosmwasm_schema::cw_serde;
+use cosmwasm_std::Binary;
+use libsecp256k1_core::curve::{Affine, ECMultContext, Field, Jacobian, Scalar};
+use libsecp256k1_core::util::{TAG_PUBKEY_EVEN, TAG_PUBKEY_ODD};
+
+/// The size of a MuSig2 public nonce, made of two compressed points.
+pub const PUBNONCE_SIZE: usize = 66;
+
+/// The size of a MuSig2 partial signature.
+pub const PARTIAL_SIG_SIZE: usize = 32;
+
+/// The state of a MuSig2 signing session for a key-path spend of a taproot
+/// output owned by an aggregated-key signatory set.
+///
+/// Signers first each submit a public nonce, then once all nonces are known,
+/// each submit a partial signature. Once all partial signatures are known they
+/// are aggregated into a single Schnorr signature for the output key. Signers
+/// are in the same order as in the signatory set, which is also the order
+/// their keys are aggregated in.
+#[cw_serde]
+pub struct MusigSession {
+    /// The x-only taproot tweak which is applied to the aggregate key to get
+    /// the output key of the output being spent.
+    pub tweak: Binary,
+
+    /// The public nonce of each signer, once submitted.
+    pub nonces: Vec<Option<Binary>>,
+
+    /// The partial signature of each signer, once submitted.
+    pub partial_sigs: Vec<Option<Binary>>,
+
+    /// The aggregated signature, once all partial signatures are submitted.
+    pub sig: Option<Binary>,
+}
+
+impl MusigSession {
+    /// Creates an empty session for the given number of signers.
+    pub fn new(tweak: [u8; 32], len: usize) -> Self {
+        MusigSession {
+            tweak: Binary::from(tweak.as_slice()),
+            nonces: vec![None; len],
+            partial_sigs: vec![None; len],
+            sig: None,
+        }
+    }
+
+    /// The x-only taproot tweak of the session.
+    pub fn tweak(&self) -> [u8; 32] {
+        let mut tweak = [0; 32];
+        tweak.copy_from_slice(self.tweak.as_slice());
+        tweak
+    }
+
+    /// Whether all signers have submitted their public nonce.
+    pub fn has_all_nonces(&self) -> bool {
+        self.nonces.iter().all(Option::is_some)
+    }
+}
+
+/// Computes the x-only MuSig2 aggregate of the given public keys, in the given
+/// order, as specified by `KeyAgg` in BIP327.
+pub fn aggregate_key(ctx: &ECMultContext, pubkeys: &[Pubkey]) -> ContractResult<[u8; 32]> {
+    Ok(xonly(&KeyAggContext::new(ctx, pubkeys)?.q))
+}
+
+/// Checks that a public nonce consists of two valid compressed points.
+pub fn validate_nonce(nonce: &[u8]) -> ContractResult<()> {
+    parse_nonce(nonce).map(|_| ())
+}
+
+/// Verifies the partial signature of the signer at `index`, as specified by
+/// `PartialSigVerify` in BIP327.
+pub fn verify_partial_sig(
+    ctx: &ECMultContext,
+    pubkeys: &[Pubkey],
+    session: &MusigSession,
+    msg: &[u8],
+    index: usize,
+    partial_sig: &[u8],
+) -> ContractResult<()> {
+    let values = SessionValues::new(ctx, pubkeys, session, msg)?;
+    let invalid = || ContractError::App("Invalid partial signature".to_string());
+
+    let s = parse_partial_sig(partial_sig)?;
+    let nonce = session.nonces[index].as_ref().ok_or_else(invalid)?;
+    let (r1, r2) = parse_nonce(nonce)?;
+    let pubkey = parse_point(pubkeys[index].as_slice())?;
+
+    // The signer's effective nonce, R1 + b⋅R2, negated if the aggregate nonce
+    // has an odd y coordinate.
+    let effective_nonce = to_affine(&mul(ctx, &r2, &values.b).add_ge_var(&r1, None))?;
+    let negate_nonce = values.r.y.is_odd();
+
+    // s⋅G - e⋅a⋅g⋅P must equal the signer's effective nonce.
+    let g = values.key_agg.parity_factor() * values.key_agg.gacc;
+    let k = -(values.e * values.key_agg.coefficients[index] * g);
+    let mut lhs = Jacobian::default();
+    ctx.ecmult(&mut lhs, &Jacobian::from_ge(&pubkey), &k, &s);
+    let lhs = to_affine(&lhs)?;
+
+    if xonly(&lhs) != xonly(&effective_nonce)
+        || lhs.y.is_odd() != (effective_nonce.y.is_odd() != negate_nonce)
+    {
+        return Err(invalid());
+    }
+
+    Ok(())
+}
+
+/// Aggregates the partial signatures of all signers into a Schnorr signature
+/// for the tweaked aggregate key, as specified by `PartialSigAgg` in BIP327,
+/// and verifies it.
+pub fn aggregate_partial_sigs(
+    ctx: &ECMultContext,
+    pubkeys: &[Pubkey],
+    session: &MusigSession,
+    msg: &[u8],
+) -> ContractResult<Vec<u8>> {
+    let values = SessionValues::new(ctx, pubkeys, session, msg)?;
+
+    let mut s = values.e * values.key_agg.parity_factor() * values.key_agg.tacc;
+    for partial_sig in &session.partial_sigs {
+        let partial_sig = partial_sig
+            .as_ref()
+            .ok_or_else(|| ContractError::App("Missing partial signature".to_string()))?;
+        s = s + parse_partial_sig(partial_sig)?;
+    }
+
+    let mut sig = xonly(&values.r).to_vec();
+    sig.extend_from_slice(&s.b32());
+    schnorr_verify_xonly(ctx, msg, &xonly(&values.key_agg.q), &sig)?;
+
+    Ok(sig)
+}
+
+/// Creates the partial signature of the signer at `index` from their secret
+/// key and the secret nonces behind their public nonce, as specified by `Sign`
+/// in BIP327. Signing is done off-chain by signatories, this is only used to
+/// test the contract's verification.
+#[cfg(test)]
+pub fn partial_sign(
+    ctx: &ECMultContext,
+    pubkeys: &[Pubkey],
+    session: &MusigSession,
+    msg: &[u8],
+    index: usize,
+    secret_key: &Scalar,
+    secret_nonces: (Scalar, Scalar),
+) -> ContractResult<[u8; 32]> {
+    let values = SessionValues::new(ctx, pubkeys, session, msg)?;
+
+    let (mut k1, mut k2) = secret_nonces;
+    if values.r.y.is_odd() {
+        k1 = -k1;
+        k2 = -k2;
+    }
+    let d = values.key_agg.parity_factor() * values.key_agg.gacc * *secret_key;
+    let s = k1 + values.b * k2 + values.e * values.key_agg.coefficients[index] * d;
+
+    Ok(s.b32())
+}
+
+/// The aggregate of a list of public keys, along with the accumulated values
+/// of the tweaks applied to it.
+struct KeyAggContext {
+    q: Affine,
+    coefficients: Vec<Scalar>,
+    gacc: Scalar,
+    tacc: Scalar,
+}
+
+impl KeyAggContext {
+    fn new(ctx: &ECMultContext, pubkeys: &[Pubkey]) -> ContractResult<Self> {
+        let first = pubkeys
+            .first()
+            .ok_or_else(|| ContractError::App("Cannot aggregate an empty key list".to_string()))?;
+        let list: Vec<_> = pubkeys.iter().map(Pubkey::as_slice).collect();
+        let list_hash = tagged_hash("KeyAgg list", &list);
+        let second = pubkeys.iter().find(|pubkey| *pubkey != first);
+
+        let mut coefficients = Vec::with_capacity(pubkeys.len());
+        let mut q: Option<Jacobian> = None;
+        for pubkey in pubkeys {
+            let coefficient = if Some(pubkey) == second {
+                Scalar::from_int(1)
+            } else {
+                reduce(tagged_hash(
+                    "KeyAgg coefficient",
+                    &[&list_hash, pubkey.as_slice()],
+                ))
+            };
+            let term = mul(ctx, &parse_point(pubkey.as_slice())?, &coefficient);
+            q = Some(match q {
+                Some(q) => q.add_var(&term, None),
+                None => term,
+            });
+            coefficients.push(coefficient);
+        }
+
+        Ok(KeyAggContext {
+            q: to_affine(&q.unwrap())?,
+            coefficients,
+            gacc: Scalar::from_int(1),
+            tacc: Scalar::from_int(0),
+        })
+    }
+
+    /// Applies an x-only tweak, as done for the taproot tweak.
+    fn apply_xonly_tweak(&mut self, ctx: &ECMultContext, tweak: &[u8]) -> ContractResult<()> {
+        if tweak.len() != 32 {
+            return Err(ContractError::App("Invalid tweak".to_string()));
+        }
+        let mut t = Scalar::default();
+        if bool::from(t.set_b32(arrayref::array_ref!(tweak, 0, 32))) {
+            return Err(ContractError::App("Invalid tweak".to_string()));
+        }
+
+        let g = self.parity_factor();
+        let mut q = Jacobian::default();
+        ctx.ecmult(&mut q, &Jacobian::from_ge(&self.q), &g, &t);
+        self.q = to_affine(&q)?;
+        self.gacc = g * self.gacc;
+        self.tacc = t + g * self.tacc;
+
+        Ok(())
+    }
+
+    /// 1 if the aggregate key has an even y coordinate, otherwise -1.
+    fn parity_factor(&self) -> Scalar {
+        if self.q.y.is_odd() {
+            -Scalar::from_int(1)
+        } else {
+            Scalar::from_int(1)
+        }
+    }
+}
+
+/// The values shared by all signers of a session, derived from the tweaked
+/// aggregate key, the aggregate nonce and the message.
+struct SessionValues {
+    key_agg: KeyAggContext,
+    r: Affine,
+    b: Scalar,
+    e: Scalar,
+}
+
+impl SessionValues {
+    fn new(
+        ctx: &ECMultContext,
+        pubkeys: &[Pubkey],
+        session: &MusigSession,
+        msg: &[u8],
+    ) -> ContractResult<Self> {
+        let mut key_agg = KeyAggContext::new(ctx, pubkeys)?;
+        key_agg.apply_xonly_tweak(ctx, &session.tweak)?;
+
+        let mut aggnonce: Option<(Jacobian, Jacobian)> = None;
+        for nonce in &session.nonces {
+            let nonce = nonce
+                .as_ref()
+                .ok_or_else(|| ContractError::App("Missing public nonce".to_string()))?;
+            let (r1, r2) = parse_nonce(nonce)?;
+            aggnonce = Some(match aggnonce {
+                Some((agg1, agg2)) => (agg1.add_ge_var(&r1, None), agg2.add_ge_var(&r2, None)),
+                None => (Jacobian::from_ge(&r1), Jacobian::from_ge(&r2)),
+            });
+        }
+        let (r1, r2) =
+            aggnonce.ok_or_else(|| ContractError::App("Missing public nonce".to_string()))?;
+        let (r1, r2) = (to_affine(&r1)?, to_affine(&r2)?);
+
+        let q = xonly(&key_agg.q);
+        let b = reduce(tagged_hash(
+            "MuSig/noncecoef",
+            &[&serialize_point(&r1), &serialize_point(&r2), &q, msg],
+        ));
+        let r = to_affine(&mul(ctx, &r2, &b).add_ge_var(&r1, None))?;
+        let e = reduce(tagged_hash("BIP0340/challenge", &[&xonly(&r), &q, msg]));
+
+        Ok(SessionValues { key_agg, r, b, e })
+    }
+}
+
+fn parse_point(bytes: &[u8]) -> ContractResult<Affine> {
+    let invalid = || ContractError::App("Invalid point".to_string());
+    if bytes.len() != 33 || (bytes[0] != TAG_PUBKEY_EVEN && bytes[0] != TAG_PUBKEY_ODD) {
+        return Err(invalid());
+    }
+
+    let mut x = Field::default();
+    let mut point = Affine::default();
+    if !x.set_b32(arrayref::array_ref!(bytes, 1, 32))
+        || !point.set_xo_var(&x, bytes[0] == TAG_PUBKEY_ODD)
+    {
+        return Err(invalid());
+    }
+    Ok(point)
+}
+
+fn parse_nonce(nonce: &[u8]) -> ContractResult<(Affine, Affine)> {
+    if nonce.len() != PUBNONCE_SIZE {
+        return Err(ContractError::App("Invalid public nonce".to_string()));
+    }
+    Ok((parse_point(&nonce[..33])?, parse_point(&nonce[33..])?))
+}
+
+fn parse_partial_sig(partial_sig: &[u8]) -> ContractResult<Scalar> {
+    let invalid = || ContractError::App("Invalid partial signature".to_string());
+    if partial_sig.len() != PARTIAL_SIG_SIZE {
+        return Err(invalid());
+    }
+    let mut s = Scalar::default();
+    if bool::from(s.set_b32(arrayref::array_ref!(partial_sig, 0, 32))) {
+        return Err(invalid());
+    }
+    Ok(s)
+}
+
+/// Interprets a hash as an integer modulo the curve order.
+fn reduce(hash: [u8; 32]) -> Scalar {
+    let mut scalar = Scalar::default();
+    let _ = scalar.set_b32(&hash);
+    scalar
+}
+
+fn mul(ctx: &ECMultContext, point: &Affine, k: &Scalar) -> Jacobian {
+    let mut r = Jacobian::default();
+    ctx.ecmult(&mut r, &Jacobian::from_ge(point), k, &Scalar::from_int(0));
+    r
+}
+
+fn to_affine(point: &Jacobian) -> ContractResult<Affine> {
+    if point.is_infinity() {
+        return Err(ContractError::App("Point at infinity".to_string()));
+    }
+    let mut affine = Affine::default();
+    affine.set_gej(point);
+    affine.x.normalize_var();
+    affine.y.normalize_var();
+    Ok(affine)
+}
+
+fn xonly(point: &Affine) -> [u8; 32] {
+    let mut x = [0; 32];
+    point.x.fill_b32(&mut x);
+    x
+}
+
+fn serialize_point(point: &Affine) -> [u8; 33] {
+    let mut bytes = [0; 33];
+    bytes[0] = if point.y.is_odd() {
+        TAG_PUBKEY_ODD
+    } else {
+        TAG_PUBKEY_EVEN
+    };
+    point.x.fill_b32(arrayref::array_mut_ref!(bytes, 1, 32));
+    bytes
+}
@@ -9,9 +9,10 @@ use crate::{
     interface::{Dest, Xpub},
     state::RECOVERY_TXS,
 };
-use bitcoin::{OutPoint, Transaction, TxOut};
+use bitcoin::{OutPoint, Script, Transaction, TxOut};
 use cosmwasm_schema::serde::{Deserialize, Serialize};
 use cosmwasm_std::{Api, Storage};
+use libsecp256k1_core::curve::ECMultContext;
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(crate = "cosmwasm_schema::serde")]
@@ -20,6 +21,8 @@ pub struct RecoveryTx {
     old_sigset_index: u32,
     new_sigset_index: u32,
     dest: Dest,
+    #[serde(default)]
+    paid_to_recovery_script: bool,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -28,6 +31,10 @@ pub struct SignedRecoveryTx {
     pub tx: Adapter<Transaction>,
     pub sigset_index: u32,
     pub dest: Dest,
+    /// Whether the transaction refunds the deposit to the recovery script of
+    /// `dest`, in which case its output is not to be relayed as a deposit.
+    #[serde(default)]
+    pub paid_to_recovery_script: bool,
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -42,12 +49,14 @@ pub struct RecoveryTxInput<'a> {
     pub threshold: (u64, u64),
     pub fee_rate: u64,
     pub dest: Dest,
+    pub recovery_script: Option<Adapter<Script>>,
 }
 
 impl RecoveryTxs {
     pub fn create_recovery_tx(
         &mut self,
         store: &mut dyn Storage,
+        ctx: &ECMultContext,
         args: RecoveryTxInput,
     ) -> ContractResult<()> {
         let expired_output = args
@@ -56,16 +65,30 @@ impl RecoveryTxs {
             .get(args.vout as usize)
             .ok_or_else(|| ContractError::Signer("Invalid recovery tx vout".to_string()))?;
 
+        // Recovery txs are signed by a threshold of the old signatories, since
+        // some of them may no longer be available to produce an aggregated
+        // signature.
         let input = Input::new(
+            ctx,
             OutPoint::new(args.expired_tx.txid(), args.vout),
             args.old_sigset,
             &args.dest.commitment_bytes()?,
             expired_output.value,
             args.threshold,
-        )?;
-        let script_pubkey = args
-            .new_sigset
-            .output_script(args.dest.commitment_bytes()?.as_slice(), args.threshold)?;
+        )?
+        .spend_script_path(args.old_sigset);
+        // Deposits are refunded to the recovery script of their destination if
+        // it has one, otherwise they are moved to the new signatory set to be
+        // relayed again.
+        let paid_to_recovery_script = args.recovery_script.is_some();
+        let script_pubkey = match args.recovery_script {
+            Some(script) => script.into_inner(),
+            None => args.new_sigset.output_script(
+                ctx,
+                args.dest.commitment_bytes()?.as_slice(),
+                args.threshold,
+            )?,
+        };
         let output = TxOut {
             value: expired_output.value,
             script_pubkey,
@@ -86,6 +109,7 @@ impl RecoveryTxs {
                 old_sigset_index: args.old_sigset.index,
                 new_sigset_index: args.new_sigset.index,
                 dest: args.dest,
+                paid_to_recovery_script,
             },
         )?;
 
@@ -119,6 +143,7 @@ impl RecoveryTxs {
         xpub: &Xpub,
         sigs: Vec<Signature>,
     ) -> ContractResult<()> {
+        let ctx = ECMultContext::new_boxed();
         let mut sig_index = 0;
 
         if sigs.is_empty() {
@@ -149,7 +174,7 @@ impl RecoveryTxs {
                 sig_index += 1;
 
                 let input_was_signed = input.signatures.signed();
-                input.signatures.sign(api, pubkey.into(), sig)?;
+                input.signatures.sign(api, &ctx, pubkey.into(), sig)?;
 
                 if !input_was_signed && input.signatures.signed() {
                     tx.tx.signed_inputs += 1;
@@ -179,6 +204,7 @@ impl RecoveryTxs {
                     tx: Adapter::new(tx.tx.to_bitcoin_tx()?),
                     sigset_index: tx.new_sigset_index,
                     dest: tx.dest.clone(),
+                    paid_to_recovery_script: tx.paid_to_recovery_script,
                 });
             }
         }
@@ -1,26 +1,35 @@
 use std::cmp::Ordering;
 
 use crate::app::ConsensusKey;
-use crate::constants::MAX_SIGNATORIES;
+use crate::constants::{MAX_AGGREGATED_SIGNATORIES, MAX_BACKUP_KEYS, MAX_SIGNATORIES};
 use crate::interface::Xpub;
+use crate::musig;
 use crate::state::get_validators;
+use crate::state::CHECKPOINT_CONFIG;
 use crate::state::SIG_KEYS;
 use crate::state::XPUBS;
 
 use super::error::ContractError;
 use super::error::ContractResult;
-use super::threshold_sig::Pubkey;
+use super::threshold_sig::{lift_x, Pubkey};
 use bitcoin::blockdata::opcodes::all::{
-    OP_ADD, OP_CHECKSIG, OP_DROP, OP_ELSE, OP_ENDIF, OP_GREATERTHAN, OP_IF, OP_SWAP,
+    OP_ADD, OP_CHECKMULTISIG, OP_CHECKSIG, OP_CHECKSIGADD, OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF,
+    OP_GREATERTHAN, OP_IF, OP_NUMEQUAL, OP_SWAP,
 };
 use bitcoin::blockdata::opcodes::{self, OP_FALSE};
 use bitcoin::blockdata::script::{read_scriptint, Instruction};
+use bitcoin::consensus::encode::VarInt;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::XOnlyPublicKey;
+use bitcoin::util::address::WitnessVersion;
+use bitcoin::util::taproot::{LeafVersion, TapBranchHash, TapLeafHash, TapTweakHash};
 use bitcoin::Script;
 use bitcoin_script::bitcoin_script as script;
 use cosmwasm_schema::schemars::JsonSchema;
 use cosmwasm_schema::serde::{Deserialize, Serialize};
 use cosmwasm_std::Order;
 use cosmwasm_std::Storage;
+use libsecp256k1_core::curve::{Affine, ECMultContext, Jacobian, Scalar};
 // use ed::Encode;
 
 /// The maximum number of signatories in a signatory set.
@@ -33,9 +42,44 @@ use cosmwasm_std::Storage;
 /// decentralization of the signatory set and the size of the resulting script
 /// (affecting fees).
 ///
-/// It is expected that future versions of this protocol will use aggregated
-/// signatures, allowing for more signatories to be included without making an
-/// impact on script size and fees.
+/// Signatory sets using aggregated signatures (see [`ScriptType::Musig2`]) are
+/// instead capped at [`MAX_AGGREGATED_SIGNATORIES`], since their outputs are
+/// spent with a single signature regardless of the number of signatories.
+
+/// The x-only "nothing up my sleeve" point from BIP341, which has no known
+/// discrete logarithm. It is used as the internal key of taproot outputs so
+/// they can only be spent through the multisig script path.
+const NUMS_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+/// The length of the destination commitment assumed when estimating witness
+/// sizes, in bytes. Commitments are account addresses or 32-byte hashes.
+const EST_COMMITMENT_LEN: u64 = 64;
+
+/// The type of output which secures funds held by a signatory set.
+#[derive(
+    Clone, Copy, Debug, Default, PartialOrd, PartialEq, Eq, Ord, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(crate = "cosmwasm_schema::serde", rename_all = "snake_case")]
+#[schemars(crate = "cosmwasm_schema::schemars")]
+pub enum ScriptType {
+    /// A pay-to-witness-script-hash output, spent with ECDSA signatures.
+    #[default]
+    P2wsh,
+
+    /// A pay-to-taproot output with a NUMS internal key and the multisig as
+    /// its only leaf, spent through the script path with Schnorr signatures.
+    P2tr,
+
+    /// A pay-to-taproot output with the MuSig2 aggregate of all signatory keys
+    /// as its internal key, spent through the key path with a single
+    /// aggregated Schnorr signature. The multisig is kept as its only leaf, so
+    /// the output can still be spent by a threshold of the signatories (e.g.
+    /// in recovery transactions) if some of them are unavailable.
+    Musig2,
+}
 
 /// A signatory in a signatory set, consisting of a public key and voting power.
 #[derive(Clone, Debug, PartialOrd, PartialEq, Eq, Ord, Deserialize, Serialize, JsonSchema)]
@@ -46,6 +90,49 @@ pub struct Signatory {
     pub pubkey: Pubkey,
 }
 
+/// A backup multisig which can spend the outputs of a signatory set once they
+/// have been confirmed for a given number of blocks, so that funds can still be
+/// recovered if the signatories become unavailable.
+#[derive(Clone, Debug, PartialOrd, PartialEq, Eq, Ord, Deserialize, Serialize, JsonSchema)]
+#[serde(crate = "cosmwasm_schema::serde")]
+#[schemars(crate = "cosmwasm_schema::schemars")]
+pub struct BackupConfig {
+    /// The public keys of the backup multisig.
+    pub keys: Vec<Pubkey>,
+
+    /// The number of backup keys required to spend.
+    pub threshold: u32,
+
+    /// The relative timelock of the backup branch, in blocks, enforced with
+    /// `OP_CHECKSEQUENCEVERIFY`.
+    pub csv_delay: u32,
+}
+
+impl BackupConfig {
+    /// Checks that the backup multisig can be encoded in a script and spent.
+    pub fn validate(&self) -> ContractResult<()> {
+        if self.keys.is_empty() || self.keys.len() > MAX_BACKUP_KEYS {
+            return Err(ContractError::App(format!(
+                "Backup must have between 1 and {} keys",
+                MAX_BACKUP_KEYS
+            )));
+        }
+        if self.threshold == 0 || self.threshold as usize > self.keys.len() {
+            return Err(ContractError::App(
+                "Backup threshold must be between 1 and the number of keys".to_string(),
+            ));
+        }
+        // Only block-based relative timelocks are supported, which use the low
+        // 16 bits of the sequence number.
+        if self.csv_delay == 0 || self.csv_delay > 0xffff {
+            return Err(ContractError::App(
+                "Backup delay must be between 1 and 65535 blocks".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
 /// A signatory set is a set of signers who secure a UTXO in the network
 /// reserve.
 ///
@@ -79,6 +166,16 @@ pub struct SignatorySet {
 
     /// The signatories in this set, sorted by voting power.
     pub signatories: Vec<Signatory>,
+
+    /// The type of output used for deposits to and reserves held by this
+    /// signatory set.
+    #[serde(default)]
+    pub script_type: ScriptType,
+
+    /// The backup multisig which can spend outputs of this signatory set after
+    /// a timelock, if one was configured when the set was created.
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
 }
 
 type IterItem<'a> = std::result::Result<Instruction<'a>, bitcoin::blockdata::script::Error>;
@@ -90,12 +187,15 @@ impl SignatorySet {
         create_time: u64,
         index: u32,
     ) -> ContractResult<Self> {
+        let config = CHECKPOINT_CONFIG.may_load(store)?.unwrap_or_default();
         let mut sigset = SignatorySet {
             create_time,
             present_vp: 0,
             possible_vp: 0,
             index,
             signatories: vec![],
+            script_type: config.script_type,
+            backup: config.backup,
         };
 
         let val_set = get_validators(store)?;
@@ -120,9 +220,18 @@ impl SignatorySet {
         Ok(sigset)
     }
 
+    /// Parses a redeem script created by `redeem_script` for a signatory set
+    /// of the given script type, returning the signatory set and the
+    /// commitment bytes.
+    ///
+    /// Tapscripts only contain x-only pubkeys, so the pubkeys of P2TR
+    /// signatory sets are returned with an even y coordinate, which results in
+    /// the same output script. MuSig2 signatory sets can not be parsed, since
+    /// the internal key of their outputs is aggregated from the full pubkeys.
     pub fn from_script(
         script: &bitcoin::Script,
         threshold_ratio: (u64, u64),
+        script_type: ScriptType,
     ) -> ContractResult<(Self, Vec<u8>)> {
         fn take_instruction<'a>(
             ins: &mut impl Iterator<Item = IterItem<'a>>,
@@ -154,9 +263,30 @@ impl SignatorySet {
             Pubkey::try_from_slice(bytes)
         }
 
+        fn read_xonly_key(bytes: &[u8]) -> ContractResult<Pubkey> {
+            if bytes.len() != 32 {
+                return Err(ContractError::App("Expected 32 bytes".into()));
+            }
+
+            let mut key = vec![2];
+            key.extend_from_slice(bytes);
+            Pubkey::try_from_slice(&key)
+        }
+
+        fn read_number(instruction: Instruction) -> ContractResult<i64> {
+            match instruction {
+                Instruction::PushBytes(bytes) => read_scriptint(bytes)
+                    .map_err(|_| ContractError::App("Failed to read scriptint".into())),
+                // Small numbers are pushed with `OP_PUSHNUM_N`.
+                Instruction::Op(op) => match op.classify(opcodes::ClassifyContext::Legacy) {
+                    opcodes::Class::PushNum(n) => Ok(n as i64),
+                    _ => Err(ContractError::App("Expected number".into())),
+                },
+            }
+        }
+
         fn take_number<'a>(ins: &mut impl Iterator<Item = IterItem<'a>>) -> ContractResult<i64> {
-            let bytes = take_bytes(ins)?;
-            read_scriptint(bytes).map_err(|_| ContractError::App("Failed to read scriptint".into()))
+            read_number(take_instruction(ins)?)
         }
 
         fn take_op<'a>(
@@ -212,12 +342,85 @@ impl SignatorySet {
             })
         }
 
+        fn take_tapscript_signatory<'a>(
+            ins: &mut impl Iterator<Item = IterItem<'a>>,
+        ) -> ContractResult<Signatory> {
+            take_op(ins, OP_SWAP)?;
+            take_op(ins, OP_FALSE)?;
+            let pubkey = read_xonly_key(take_bytes(ins)?)?;
+            take_op(ins, OP_CHECKSIGADD)?;
+            take_op(ins, OP_IF)?;
+            let voting_power = take_number(ins)?;
+            take_op(ins, OP_ADD)?;
+            take_op(ins, OP_ENDIF)?;
+
+            Ok::<_, ContractError>(Signatory {
+                pubkey,
+                voting_power: voting_power as u64,
+            })
+        }
+
         fn take_threshold<'a>(ins: &mut impl Iterator<Item = IterItem<'a>>) -> ContractResult<u64> {
             let threshold = take_number(ins)?;
             take_op(ins, OP_GREATERTHAN)?;
             Ok(threshold as u64)
         }
 
+        fn take_backup<'a>(
+            ins: &mut impl Iterator<Item = IterItem<'a>>,
+            tapscript: bool,
+        ) -> ContractResult<BackupConfig> {
+            take_op(ins, OP_ELSE)?;
+            let csv_delay = take_number(ins)?;
+            take_op(ins, OP_CSV)?;
+            take_op(ins, OP_DROP)?;
+
+            let mut keys = vec![];
+            let threshold = if tapscript {
+                // Keys are checked with `OP_CHECKSIG` for the first and
+                // `OP_CHECKSIGADD` for the rest, followed by the threshold.
+                keys.push(read_xonly_key(take_bytes(ins)?)?);
+                take_op(ins, OP_CHECKSIG)?;
+                let threshold = loop {
+                    match take_instruction(ins)? {
+                        Instruction::PushBytes(bytes) if bytes.len() == 32 => {
+                            keys.push(read_xonly_key(bytes)?);
+                            take_op(ins, OP_CHECKSIGADD)?;
+                        }
+                        instruction => break read_number(instruction)?,
+                    }
+                };
+                take_op(ins, OP_NUMEQUAL)?;
+                threshold
+            } else {
+                let threshold = take_number(ins)?;
+
+                // Keys are followed by the number of keys.
+                let num_keys = loop {
+                    match take_instruction(ins)? {
+                        Instruction::PushBytes(bytes) if bytes.len() == 33 => {
+                            keys.push(Pubkey::try_from_slice(bytes)?);
+                        }
+                        instruction => break read_number(instruction)?,
+                    }
+                };
+                if num_keys != keys.len() as i64 {
+                    return Err(ContractError::App(
+                        "Unexpected number of backup keys".into(),
+                    ));
+                }
+                take_op(ins, OP_CHECKMULTISIG)?;
+                threshold
+            };
+            take_op(ins, OP_ENDIF)?;
+
+            Ok(BackupConfig {
+                keys,
+                threshold: threshold as u32,
+                csv_delay: csv_delay as u32,
+            })
+        }
+
         fn take_commitment<'a>(
             ins: &mut impl Iterator<Item = IterItem<'a>>,
         ) -> ContractResult<&'a [u8]> {
@@ -226,8 +429,25 @@ impl SignatorySet {
             Ok(bytes)
         }
 
+        if script_type == ScriptType::Musig2 {
+            return Err(ContractError::App(
+                "Cannot parse the script of a MuSig2 signatory set".into(),
+            ));
+        }
+        let tapscript = script_type == ScriptType::P2tr;
+
         let mut ins = script.instructions().peekable();
-        let mut sigs = vec![take_first_signatory(&mut ins)?];
+        let has_backup = matches!(ins.peek(), Some(Ok(Instruction::Op(OP_IF))));
+        if has_backup {
+            take_op(&mut ins, OP_IF)?;
+        }
+        let mut sigs = vec![];
+        if tapscript {
+            // The voting power accumulator starts at zero.
+            take_op(&mut ins, OP_FALSE)?;
+        } else {
+            sigs.push(take_first_signatory(&mut ins)?);
+        }
         loop {
             let next = ins
                 .peek()
@@ -236,13 +456,22 @@ impl SignatorySet {
                 .map_err(|_| ContractError::App("Failed to read script".into()))?;
 
             if let Instruction::Op(opcodes::all::OP_SWAP) = next {
-                sigs.push(take_nth_signatory(&mut ins)?);
+                sigs.push(if tapscript {
+                    take_tapscript_signatory(&mut ins)?
+                } else {
+                    take_nth_signatory(&mut ins)?
+                });
             } else {
                 break;
             }
         }
 
         let expected_threshold = take_threshold(&mut ins)?;
+        let backup = if has_backup {
+            Some(take_backup(&mut ins, tapscript)?)
+        } else {
+            None
+        };
         let commitment = take_commitment(&mut ins)?;
 
         assert!(ins.next().is_none());
@@ -254,6 +483,8 @@ impl SignatorySet {
             possible_vp: total_vp,
             create_time: 0,
             index: 0,
+            script_type,
+            backup,
         };
 
         for _ in 0..100 {
@@ -288,8 +519,12 @@ impl SignatorySet {
     fn sort_and_truncate(&mut self) {
         self.signatories.sort_by(|a, b| b.cmp(a));
 
-        if self.signatories.len() as u64 > MAX_SIGNATORIES {
-            for removed in self.signatories.drain(MAX_SIGNATORIES as usize..) {
+        let max_signatories = match self.script_type {
+            ScriptType::Musig2 => MAX_AGGREGATED_SIGNATORIES,
+            _ => MAX_SIGNATORIES,
+        };
+        if self.signatories.len() as u64 > max_signatories {
+            for removed in self.signatories.drain(max_signatories as usize..) {
                 self.present_vp -= removed.voting_power;
             }
         }
@@ -345,9 +580,51 @@ impl SignatorySet {
     ///
     /// This script is essentially a weighted multisig script, where each
     /// signatory has a weight equal to their voting power. It is specified in
-    /// the input witness when the UTXO is spent. The output contains a hash of
-    /// this script, since it is a pay-to-witness-script-hash (P2WSH) output.
+    /// the input witness when the UTXO is spent. For P2WSH outputs, the output
+    /// contains a hash of this script. For P2TR outputs, it is the tapscript of
+    /// the only leaf of the output's script tree.
+    ///
+    /// If the signatory set has a backup multisig, the weighted multisig is
+    /// wrapped in an `OP_IF` branch, with an `OP_ELSE` branch which can be
+    /// spent by the backup keys once the output has been confirmed for the
+    /// backup delay.
     pub fn redeem_script(&self, dest: &[u8], threshold: (u64, u64)) -> ContractResult<Script> {
+        let mut bytes = vec![];
+        if self.backup.is_some() {
+            bytes.extend(&script!(OP_IF).into_bytes());
+        }
+
+        let multisig = match self.script_type {
+            ScriptType::P2wsh => self.p2wsh_multisig(threshold)?,
+            ScriptType::P2tr | ScriptType::Musig2 => self.tapscript_multisig(threshold)?,
+        };
+        bytes.extend(&multisig);
+
+        if let Some(backup) = &self.backup {
+            // Require the input's sequence number to be at least the backup
+            // delay, then check the backup multisig.
+            let csv_delay = backup.csv_delay as i64;
+            let script = script! {
+                OP_ELSE
+                    <csv_delay> OP_CSV OP_DROP
+            };
+            bytes.extend(&script.into_bytes());
+            bytes.extend(&self.backup_multisig(backup));
+            bytes.extend(&script!(OP_ENDIF).into_bytes());
+        }
+
+        // Add a commitment of arbitrary data so that deposits can be tied to a
+        // specific destination, then remove it from the stack so that the final
+        // value on the stack is the result of the branch.
+        let script = script!(<dest> OP_DROP);
+        bytes.extend(&script.into_bytes());
+
+        Ok(bytes.into())
+    }
+
+    /// Builds the weighted multisig part of the P2WSH redeem script, ending
+    /// with the threshold check.
+    fn p2wsh_multisig(&self, threshold: (u64, u64)) -> ContractResult<Vec<u8>> {
         // We will truncate voting power values to 23 bits, to reduce the amount
         // of bytes used in the resulting encoded script. In practice, this
         // should be enough precision for effective voting power threshold
@@ -405,22 +682,152 @@ impl SignatorySet {
         };
         bytes.extend(&script.into_bytes());
 
-        // Depositor data commitment, vector is the same
+        Ok(bytes)
+    }
 
-        // Add a commitment of arbitrary data so that deposits can be tied to a
-        // specific destination, then remove it from the stack so that the final
-        // value on the stack is the threshold check result.
-        let script = script!(<dest> OP_DROP);
+    /// Builds the weighted multisig as a tapscript, using x-only pubkeys and
+    /// `OP_CHECKSIGADD`, ending with the threshold check.
+    fn tapscript_multisig(&self, threshold: (u64, u64)) -> ContractResult<Vec<u8>> {
+        let truncation = self.get_truncation(23);
+
+        if self.signatories.is_empty() {
+            return Err(ContractError::App(
+                "Cannot create redeem script for empty signatory set".to_string(),
+            ));
+        }
+
+        // Start the voting power accumulator at zero.
+        let mut bytes = script!(0).into_bytes();
+
+        for signatory in self.iter() {
+            let truncated_voting_power = signatory.voting_power >> truncation;
+            let xonly_pubkey = &signatory.pubkey.as_slice()[1..];
+            // Move the accumulator below the next signature, then check the
+            // signature against a zero counter with `OP_CHECKSIGADD`, which
+            // leaves 1 for a valid signature and 0 for an empty one. Invalid
+            // signatures fail the script. The signatory's voting power is added
+            // to the accumulator if the signature was valid.
+            let script = script! {
+                OP_SWAP
+                0 <xonly_pubkey> OP_CHECKSIGADD
+                OP_IF
+                    <truncated_voting_power as i64> OP_ADD
+                OP_ENDIF
+            };
+            bytes.extend(&script.into_bytes());
+        }
+
+        // Check the accumulated voting power against the threshold, as in the
+        // P2WSH script.
+        let truncated_threshold = self.signature_threshold(threshold) >> truncation;
+        let script = script! {
+            <truncated_threshold as i64> OP_GREATERTHAN
+        };
         bytes.extend(&script.into_bytes());
 
-        Ok(bytes.into())
+        Ok(bytes)
+    }
+
+    /// Builds the backup multisig, using `OP_CHECKMULTISIG` for P2WSH outputs
+    /// and x-only pubkeys with `OP_CHECKSIGADD` for P2TR outputs, since
+    /// `OP_CHECKMULTISIG` is disabled in tapscript.
+    fn backup_multisig(&self, backup: &BackupConfig) -> Vec<u8> {
+        let threshold = backup.threshold as i64;
+        if self.script_type == ScriptType::P2wsh {
+            let mut bytes = script!(<threshold>).into_bytes();
+            for key in &backup.keys {
+                bytes.extend(&script!(<key.as_slice()>).into_bytes());
+            }
+            let num_keys = backup.keys.len() as i64;
+            let script = script! {
+                <num_keys> OP_CHECKMULTISIG
+            };
+            bytes.extend(&script.into_bytes());
+            return bytes;
+        }
+
+        let mut bytes = vec![];
+        for (i, key) in backup.keys.iter().enumerate() {
+            let xonly_pubkey = &key.as_slice()[1..];
+            let script = if i == 0 {
+                script!(<xonly_pubkey> OP_CHECKSIG)
+            } else {
+                script!(<xonly_pubkey> OP_CHECKSIGADD)
+            };
+            bytes.extend(&script.into_bytes());
+        }
+        let script = script! {
+            <threshold> OP_NUMEQUAL
+        };
+        bytes.extend(&script.into_bytes());
+        bytes
     }
 
-    /// Hashes the weighted multisig redeem script to create a P2WSH output
-    /// script, which is what is used as the script pubkey in deposit outputs
-    /// and reserve outputs.
-    pub fn output_script(&self, dest: &[u8], threshold: (u64, u64)) -> ContractResult<Script> {
-        Ok(self.redeem_script(dest, threshold)?.to_v0_p2wsh())
+    /// Creates the output script which is used as the script pubkey in deposit
+    /// outputs and reserve outputs. For P2WSH outputs, this is a hash of the
+    /// weighted multisig redeem script. For P2TR outputs, this is the internal
+    /// key tweaked with the redeem script as the only leaf.
+    pub fn output_script(
+        &self,
+        ctx: &ECMultContext,
+        dest: &[u8],
+        threshold: (u64, u64),
+    ) -> ContractResult<Script> {
+        let redeem_script = self.redeem_script(dest, threshold)?;
+        match self.internal_key(ctx)? {
+            None => Ok(redeem_script.to_v0_p2wsh()),
+            Some(internal_key) => {
+                let (output_key, _) = taproot_output_key(ctx, &internal_key, &redeem_script)?;
+                Ok(Script::new_witness_program(WitnessVersion::V1, &output_key))
+            }
+        }
+    }
+
+    /// The control block which proves that the redeem script is a leaf of a
+    /// P2TR output's script tree, or `None` for P2WSH outputs.
+    pub fn control_block(
+        &self,
+        ctx: &ECMultContext,
+        dest: &[u8],
+        threshold: (u64, u64),
+    ) -> ContractResult<Option<Vec<u8>>> {
+        let Some(internal_key) = self.internal_key(ctx)? else {
+            return Ok(None);
+        };
+
+        let redeem_script = self.redeem_script(dest, threshold)?;
+        let (_, odd) = taproot_output_key(ctx, &internal_key, &redeem_script)?;
+        let mut control_block = vec![LeafVersion::TapScript.to_consensus() | odd as u8];
+        control_block.extend_from_slice(&internal_key);
+        Ok(Some(control_block))
+    }
+
+    /// The x-only taproot tweak which is added to the internal key to get the
+    /// output key, committing to the redeem script as the only leaf. Errors for
+    /// P2WSH signatory sets.
+    pub fn taproot_tweak(
+        &self,
+        ctx: &ECMultContext,
+        dest: &[u8],
+        threshold: (u64, u64),
+    ) -> ContractResult<[u8; 32]> {
+        let internal_key = self.internal_key(ctx)?.ok_or_else(|| {
+            ContractError::App("Signatory set does not use taproot outputs".to_string())
+        })?;
+        taproot_tweak(&internal_key, &self.redeem_script(dest, threshold)?)
+    }
+
+    /// The x-only internal key of the P2TR outputs of this signatory set, or
+    /// `None` for P2WSH outputs.
+    fn internal_key(&self, ctx: &ECMultContext) -> ContractResult<Option<[u8; 32]>> {
+        Ok(match self.script_type {
+            ScriptType::P2wsh => None,
+            ScriptType::P2tr => Some(NUMS_KEY),
+            ScriptType::Musig2 => {
+                let pubkeys: Vec<_> = self.iter().map(|s| s.pubkey.clone()).collect();
+                Some(musig::aggregate_key(ctx, &pubkeys)?)
+            }
+        })
     }
 
     /// Calculates the number of bits of precision to remove from voting power
@@ -454,10 +861,110 @@ impl SignatorySet {
     /// for signatories beyond the threshold, but for fee estimation we err on
     /// the side of paying too much.
     pub fn est_witness_vsize(&self) -> u64 {
-        self.signatories.len() as u64 * 79 + 39
+        match self.script_type {
+            ScriptType::P2wsh | ScriptType::P2tr => self.est_script_path_witness_vsize(),
+            // A single 64-byte signature.
+            ScriptType::Musig2 => witness_vsize(&[64]),
+        }
+    }
+
+    /// The estimated size of a witness spending an output of this signatory
+    /// set through the weighted multisig, in virtual bytes. For P2TR outputs
+    /// this is the multisig leaf, with its control block.
+    pub fn est_script_path_witness_vsize(&self) -> u64 {
+        let taproot = self.script_type != ScriptType::P2wsh;
+        // DER-encoded ECDSA signatures take up to 72 bytes and the sighash
+        // type. Schnorr signatures imply the default sighash type.
+        let sig_len = if taproot { 64 } else { 73 };
+        let mut items = vec![sig_len; self.signatories.len()];
+        if self.backup.is_some() {
+            // The branch selector
+            items.push(1);
+        }
+        items.push(self.est_redeem_script_len());
+        if taproot {
+            // The leaf version and the x-only internal key
+            items.push(33);
+        }
+        witness_vsize(&items)
+    }
+
+    /// The estimated length of the redeem script, in bytes, for voting powers
+    /// and thresholds taking the full 23 bits and a commitment of
+    /// `EST_COMMITMENT_LEN` bytes.
+    fn est_redeem_script_len(&self) -> u64 {
+        // Each signatory takes a pubkey push, a voting power push of up to 4
+        // bytes and 5 opcodes, and tapscripts start the accumulator at zero.
+        let mut len = self.signatories.len() as u64 * 43 + 1;
+        // The threshold check
+        len += 5;
+        // The commitment and `OP_DROP`
+        len += EST_COMMITMENT_LEN + 2;
+        if let Some(backup) = &self.backup {
+            // The backup keys, the threshold and key count pushes, the delay
+            // push and the branch opcodes
+            len += backup.keys.len() as u64 * 34 + 14;
+        }
+        len
     }
 }
 
+/// The size of a witness with items of the given lengths, in virtual bytes.
+/// Witness data weighs one unit per byte, a quarter of a virtual byte.
+fn witness_vsize(item_lens: &[u64]) -> u64 {
+    let varint_len = |n: u64| VarInt(n).len() as u64;
+    let weight = item_lens
+        .iter()
+        .fold(varint_len(item_lens.len() as u64), |weight, len| {
+            weight + varint_len(*len) + len
+        });
+    weight.div_ceil(4)
+}
+
+/// Computes the taproot tweak of a P2TR output with the given x-only internal
+/// key and the given tapscript as its only leaf.
+fn taproot_tweak(internal_key: &[u8; 32], tapscript: &Script) -> ContractResult<[u8; 32]> {
+    let leaf_hash = TapLeafHash::from_script(tapscript, LeafVersion::TapScript);
+    let tweak = TapTweakHash::from_key_and_tweak(
+        XOnlyPublicKey::from_slice(internal_key)?,
+        Some(TapBranchHash::from_inner(leaf_hash.into_inner())),
+    );
+    Ok(tweak.into_inner())
+}
+
+/// Computes the x-only output key of a P2TR output with the given x-only
+/// internal key and the given tapscript as its only leaf, along with the parity
+/// of its y coordinate.
+fn taproot_output_key(
+    ctx: &ECMultContext,
+    internal_key: &[u8; 32],
+    tapscript: &Script,
+) -> ContractResult<([u8; 32], bool)> {
+    let mut tweak_scalar = Scalar::default();
+    if bool::from(tweak_scalar.set_b32(&taproot_tweak(internal_key, tapscript)?)) {
+        return Err(ContractError::App("Invalid taproot tweak".to_string()));
+    }
+
+    // Q = P + t⋅G
+    let internal_key = lift_x(internal_key)?;
+    let mut output = Jacobian::default();
+    ctx.ecmult(
+        &mut output,
+        &Jacobian::from_ge(&internal_key),
+        &Scalar::from_int(1),
+        &tweak_scalar,
+    );
+
+    let mut output_key = Affine::default();
+    output_key.set_gej(&output);
+    output_key.x.normalize_var();
+    output_key.y.normalize_var();
+
+    let mut x = [0; 32];
+    output_key.x.fill_b32(&mut x);
+    Ok((x, output_key.y.is_odd()))
+}
+
 /// A collection storing the signatory extended public keys of each validator
 /// who has submitted one.
 ///
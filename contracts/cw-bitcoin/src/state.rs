@@ -1,15 +1,16 @@
 use bitcoin::util::uint::Uint256;
+use bitcoin::Script;
 use cosmwasm_schema::cw_serde;
-use cosmwasm_std::{Order, Storage};
+use cosmwasm_std::{Addr, Order, Storage, Uint128};
 use cw_storage_plus::{Item, Map};
 
 use crate::{
     adapter::Adapter,
-    app::ConsensusKey,
+    app::{ConsensusKey, RelayedDeposit},
     checkpoint::Checkpoint,
     constants::BTC_NATIVE_TOKEN_DENOM,
     error::ContractResult,
-    header::WorkHeader,
+    header::{Fork, Reorg, WorkHeader},
     interface::{BitcoinConfig, CheckpointConfig, DequeExtension, HeaderConfig, Validator, Xpub},
     msg::Config,
     recovery::RecoveryTx,
@@ -53,16 +54,73 @@ pub const XPUBS: Map<&[u8], ()> = Map::new("xpubs");
 /// contains more work than the current chain, however it can not process reorgs
 /// that are deeper than the length of the queue (the length will be at the
 /// configured pruning level based on the `max_length` config parameter).
+/// Competing chains with less work are kept in `FORKS`.
 pub const HEADERS: DequeExtension<WorkHeader> = DequeExtension::new("headers");
 
+/// Mapping block hash => height, for every header in `HEADERS` and
+/// `HISTORICAL_HEADERS`
+pub const HEADER_HASHES: Map<&[u8], u32> = Map::new("header_hashes");
+
+/// Headers below the front of `HEADERS`, by height. They are either pruned
+/// from the queue or relayed backwards, and kept up to the configured
+/// `max_history_length`.
+pub const HISTORICAL_HEADERS: Map<u32, WorkHeader> = Map::new("historical_headers");
+
+/// Candidate forks of the header queue, by id
+pub const FORKS: Map<u64, Fork> = Map::new("forks");
+
+/// Mapping (fork id, height) => header, for every header of a candidate fork
+pub const FORK_HEADERS: Map<(u64, u32), WorkHeader> = Map::new("fork_headers");
+
+/// Mapping block hash => (fork id, height), for every header in `FORK_HEADERS`
+pub const FORK_HEADER_HASHES: Map<&[u8], (u64, u32)> = Map::new("fork_header_hashes");
+
+/// Mapping fork tip hash => fork id
+pub const FORK_TIPS: Map<&[u8], u64> = Map::new("fork_tips");
+
+pub const NEXT_FORK_ID: Item<u64> = Item::new("next_fork_id");
+
+/// Mapping (height, block hash) => relayer, for relayed headers which are not
+/// yet deep enough to be rewarded
+pub const PENDING_HEADER_REWARDS: Map<(u32, &[u8]), Addr> = Map::new("pending_header_rewards");
+
+/// Mapping (relayer, denom) => header rewards which can be claimed
+pub const HEADER_REWARDS: Map<(&Addr, &str), Uint128> = Map::new("header_rewards");
+
+/// The most recent reorgs of the best chain, up to `MAX_REORGS`
+pub const REORGS: DequeExtension<Reorg> = DequeExtension::new("reorgs");
+
 pub const RECOVERY_TXS: DequeExtension<RecoveryTx> = DequeExtension::new("recovery_txs");
 
+/// Mapping nBTC account => Bitcoin script which the account's balance is paid
+/// to in the emergency disbursal
+pub const RECOVERY_SCRIPTS: Map<&Addr, Adapter<Script>> = Map::new("recovery_scripts");
+
+/// The number of accounts in RECOVERY_SCRIPTS, up to `MAX_RECOVERY_SCRIPTS`
+pub const RECOVERY_SCRIPT_COUNT: Item<u32> = Item::new("recovery_script_count");
+
+/// Snapshot of the non-zero nBTC balance of each account in RECOVERY_SCRIPTS,
+/// updated `RECOVERY_BALANCES_PER_BLOCK` accounts at a time
+pub const RECOVERY_BALANCES: Map<&Addr, Uint128> = Map::new("recovery_balances");
+
+/// The last account whose balance was snapshotted, or none to start over from
+/// the first account
+pub const RECOVERY_BALANCES_CURSOR: Item<Addr> = Item::new("recovery_balances_cursor");
+
 /// A queue of outpoints to expire, sorted by expiration timestamp.
 pub const EXPIRATION_QUEUE: Map<(u64, &str), ()> = Map::new("expiration_queue");
 
 /// A set of outpoints.
 pub const OUTPOINTS: Map<&str, ()> = Map::new("outpoints");
 
+/// Mapping (block height, block hash) => deposits relayed from the block, for
+/// blocks which can still be reorged out of `HEADERS`
+pub const DEPOSIT_BLOCKS: Map<(u32, &[u8]), Vec<RelayedDeposit>> = Map::new("deposit_blocks");
+
+/// Mapping outpoint => deposit whose block was reorged out of the best chain
+/// after its checkpoint stopped building, so its credit could not be undone
+pub const REORGED_DEPOSITS: Map<&str, RelayedDeposit> = Map::new("reorged_deposits");
+
 pub const FEE_POOL: Item<i64> = Item::new("fee_pool");
 
 pub const CHECKPOINTS: DequeExtension<Checkpoint> = DequeExtension::new("checkpoints");
@@ -72,7 +130,8 @@ pub const BUILDING_INDEX: Item<u32> = Item::new("building_index");
 pub const CONFIRMED_INDEX: Item<u32> = Item::new("confirmed_index");
 /// Checkpoint unhandled confirmed index
 pub const FIRST_UNHANDLED_CONFIRMED_INDEX: Item<u32> = Item::new("first_unhandled_confirmed_index");
-/// Header current work
+/// The cumulative work of the best chain, which is the chain work of the last
+/// header in `HEADERS`. It does not change when headers are pruned.
 pub const CURRENT_WORK: Item<Adapter<Uint256>> = Item::new("current_work");
 
 /// Fee
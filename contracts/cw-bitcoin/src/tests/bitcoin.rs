@@ -1,6 +1,6 @@
 use super::helper::sign;
 use adapter::Adapter;
-use app::Bitcoin;
+use app::{Bitcoin, RelayedDeposit};
 use bitcoin::hashes::Hash;
 use bitcoin::util::bip32::ExtendedPubKey;
 use bitcoin::util::merkleblock::PartialMerkleTree;
@@ -11,22 +11,25 @@ use bitcoin::{
 };
 use bitcoin::{Script, Transaction};
 use checkpoint::{BatchType, Input};
-use constants::BTC_NATIVE_TOKEN_DENOM;
-use cosmwasm_std::testing::{mock_dependencies, mock_env};
-use cosmwasm_std::{Addr, Api, Coin, DepsMut, Env, Storage, Uint128};
+use constants::{BTC_NATIVE_TOKEN_DENOM, MAX_RECOVERY_SCRIPTS};
+use cosmwasm_std::testing::{mock_dependencies, mock_env, mock_info, MockQuerier};
+use cosmwasm_std::{Addr, Api, Coin, DepsMut, Empty, Env, QuerierWrapper, Storage, Uint128};
 use error::ContractResult;
-use interface::{BitcoinConfig, CheckpointConfig, Dest, HeaderConfig, Xpub};
+use interface::{BitcoinConfig, CheckpointConfig, CoinbaseProof, Dest, HeaderConfig, Xpub};
+use libsecp256k1_core::curve::ECMultContext;
+use oraiswap::asset::AssetInfo;
 use state::{
-    BITCOIN_CONFIG, BUILDING_INDEX, CHECKPOINT_CONFIG, CONFIRMED_INDEX, FEE_POOL,
-    FIRST_UNHANDLED_CONFIRMED_INDEX, HEADERS, HEADER_CONFIG, SIGNERS, VALIDATORS,
+    get_full_btc_denom, BITCOIN_CONFIG, BUILDING_INDEX, CHECKPOINT_CONFIG, CONFIG, CONFIRMED_INDEX,
+    DEPOSIT_BLOCKS, FEE_POOL, FIRST_UNHANDLED_CONFIRMED_INDEX, HEADERS, HEADER_CONFIG,
+    RECOVERY_SCRIPT_COUNT, REORGED_DEPOSITS, SIGNERS, VALIDATORS,
 };
 use std::cell::RefCell;
-use tests::helper::set_time;
+use tests::helper::{mine_header, set_time};
 
 use crate::interface::IbcDest;
 
 use crate::{
-    header::{WorkHeader, WrappedHeader},
+    header::{Reorg, WorkHeader, WrappedHeader},
     *,
 };
 
@@ -83,10 +86,12 @@ fn relay_height_validity() -> ContractResult<()> {
             deps.as_mut().storage,
             Adapter::new(btc_tx),
             height,
+            None,
             Adapter::new(btc_proof),
             0,
             0,
             Dest::Address(Addr::unchecked("")),
+            None,
         )
     };
 
@@ -138,7 +143,7 @@ fn check_change_rates() -> ContractResult<()> {
 
     let btc = RefCell::new(Bitcoin::default());
     let secp = Secp256k1::new();
-    let network = btc.borrow().network();
+    let network = btc.borrow().network(deps.as_ref().storage)?;
     let xpriv = vec![
         ExtendedPrivKey::new_master(network, &[0])?,
         ExtendedPrivKey::new_master(network, &[1])?,
@@ -148,10 +153,12 @@ fn check_change_rates() -> ContractResult<()> {
         ExtendedPubKey::from_priv(&secp, &xpriv[1]),
     ];
 
+    let ctx = ECMultContext::new_boxed();
     let push_deposit = |store: &mut dyn Storage| -> ContractResult<()> {
         let btc = btc.borrow();
         let sigset = &btc.checkpoints.building(store)?.sigset;
         let input = Input::new(
+            &ctx,
             OutPoint {
                 txid: Txid::from_slice(&[0; 32])?,
                 vout: 0,
@@ -223,7 +230,14 @@ fn check_change_rates() -> ContractResult<()> {
     };
     let maybe_step = |env: Env, store: &mut dyn Storage| -> ContractResult<()> {
         let mut btc = btc.borrow_mut();
-        btc.begin_block_step(&env, store, vec![1, 2, 3])?;
+        let querier = MockQuerier::<Empty>::new(&[]);
+        btc.begin_block_step(
+            &env,
+            store,
+            &QuerierWrapper::new(&querier),
+            vec![1, 2, 3],
+            &[],
+        )?;
         Ok(())
     };
 
@@ -361,7 +375,7 @@ fn test_take_pending() -> ContractResult<()> {
 
     let btc = RefCell::new(Bitcoin::default());
     let secp = Secp256k1::new();
-    let network = btc.borrow().network();
+    let network = btc.borrow().network(deps.as_ref().storage)?;
     let xpriv = vec![
         ExtendedPrivKey::new_master(network, &[0])?,
         ExtendedPrivKey::new_master(network, &[1])?,
@@ -371,10 +385,12 @@ fn test_take_pending() -> ContractResult<()> {
         ExtendedPubKey::from_priv(&secp, &xpriv[1]),
     ];
 
+    let ctx = ECMultContext::new_boxed();
     let push_deposit = |store: &mut dyn Storage, dest: Dest, coin: Coin| -> ContractResult<()> {
         let fixed_amount: u64 = 100_000_000;
         assert_eq!(coin.amount.le(&Uint128::new(fixed_amount.into())), true);
         let input = Input::new(
+            &ctx,
             OutPoint {
                 txid: Txid::from_slice(&[0; 32])?,
                 vout: 0,
@@ -447,7 +463,14 @@ fn test_take_pending() -> ContractResult<()> {
     let maybe_step = |env: Env, store: &mut dyn Storage| -> ContractResult<()> {
         let mut btc = btc.borrow_mut();
 
-        btc.begin_block_step(&env, store, vec![1, 2, 3])?;
+        let querier = MockQuerier::<Empty>::new(&[]);
+        btc.begin_block_step(
+            &env,
+            store,
+            &QuerierWrapper::new(&querier),
+            vec![1, 2, 3],
+            &[],
+        )?;
 
         Ok(())
     };
@@ -598,3 +621,505 @@ fn test_take_pending() -> ContractResult<()> {
     assert_eq!(second_cp.pending.iter().count(), 0);
     Ok(())
 }
+
+#[test]
+fn signatory_key_network() -> ContractResult<()> {
+    let mut deps = mock_dependencies();
+
+    let header_config = HeaderConfig::for_network(bitcoin::Network::Regtest)?;
+    assert_eq!(header_config.trusted_height, 0);
+    assert_eq!(header_config.max_target, 0x207fffff);
+    HEADER_CONFIG.save(deps.as_mut().storage, &header_config)?;
+
+    let consensus_key = [0; 32];
+    SIGNERS.save(deps.as_mut().storage, "validator1", &consensus_key)?;
+
+    let secp = Secp256k1::new();
+    let mut btc = Bitcoin::default();
+    assert_eq!(
+        btc.network(deps.as_ref().storage)?,
+        bitcoin::Network::Regtest
+    );
+
+    let mainnet_xpriv = ExtendedPrivKey::new_master(bitcoin::Network::Bitcoin, &[0])?;
+    let err = btc
+        .set_signatory_key(
+            deps.as_mut().storage,
+            Addr::unchecked("validator1"),
+            Xpub::new(ExtendedPubKey::from_priv(&secp, &mainnet_xpriv)),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "App Error: Signatory key network does not match network"
+    );
+
+    let testnet_xpriv = ExtendedPrivKey::new_master(bitcoin::Network::Testnet, &[0])?;
+    btc.set_signatory_key(
+        deps.as_mut().storage,
+        Addr::unchecked("validator1"),
+        Xpub::new(ExtendedPubKey::from_priv(&secp, &testnet_xpriv)),
+    )?;
+
+    Ok(())
+}
+
+#[test]
+fn invalidate_reorged_deposits() -> ContractResult<()> {
+    let mut deps = mock_dependencies();
+
+    let header_config = HeaderConfig::mainnet()?;
+    HEADER_CONFIG.save(deps.as_mut().storage, &header_config)?;
+    HEADERS.push_back(deps.as_mut().storage, &header_config.work_header())?;
+
+    BITCOIN_CONFIG.save(deps.as_mut().storage, &BitcoinConfig::default())?;
+    FEE_POOL.save(deps.as_mut().storage, &0)?;
+    CHECKPOINT_CONFIG.save(deps.as_mut().storage, &CheckpointConfig::default())?;
+    BUILDING_INDEX.save(deps.as_mut().storage, &0)?;
+
+    let consensus_key = [0; 32];
+    VALIDATORS.save(
+        deps.as_mut().storage,
+        &consensus_key,
+        &(100, "validator1".to_string()),
+    )?;
+    SIGNERS.save(deps.as_mut().storage, "validator1", &consensus_key)?;
+
+    let mut btc = Bitcoin::default();
+    let secp = Secp256k1::new();
+    let network = btc.network(deps.as_ref().storage)?;
+    let xpriv = ExtendedPrivKey::new_master(network, &[0])?;
+    btc.set_signatory_key(
+        deps.as_mut().storage,
+        Addr::unchecked("validator1"),
+        Xpub::new(ExtendedPubKey::from_priv(&secp, &xpriv)),
+    )?;
+    let deps_mut = deps.as_mut();
+    btc.begin_block_step(
+        &set_time(0),
+        deps_mut.storage,
+        &deps_mut.querier,
+        vec![1, 2, 3],
+        &[],
+    )?;
+
+    // a block on top of the trusted header, which is reorged out later
+    let header = WorkHeader::new(
+        WrappedHeader::new(
+            Adapter::new(BlockHeader {
+                bits: 0,
+                merkle_root: TxMerkleNode::all_zeros(),
+                nonce: 1,
+                prev_blockhash: BlockHash::all_zeros(),
+                time: 0,
+                version: 0,
+            }),
+            header_config.trusted_height + 1,
+        ),
+        uint::Uint256([0, 0, 0, 0]),
+    );
+    let block_hash = header.block_hash();
+    let block_key = (header.height(), &block_hash[..]);
+    let reorg = Reorg {
+        fork_height: header_config.trusted_height,
+        old_tip: Adapter::new(block_hash),
+        new_tip: Adapter::new(BlockHash::all_zeros()),
+        depth: 1,
+        work_replaced: Adapter::new(uint::Uint256([0, 0, 0, 0])),
+        time: 0,
+    };
+    let fee = Uint128::from(100_000_000_000_000u128);
+
+    let ctx = ECMultContext::new_boxed();
+    let push_deposit = |btc: &mut Bitcoin, store: &mut dyn Storage, vout| -> ContractResult<()> {
+        let outpoint = OutPoint {
+            txid: Txid::from_slice(&[0; 32])?,
+            vout,
+        };
+        let mut building = btc.checkpoints.building(store)?;
+        let input = Input::new(
+            &ctx,
+            outpoint,
+            &building.sigset,
+            &[0u8],
+            100_000_000,
+            (9, 10),
+        )?;
+        building.batches[BatchType::Checkpoint]
+            .get_mut(0)
+            .unwrap()
+            .input
+            .push(input);
+        let dest = Dest::Address(Addr::unchecked("receiver"));
+        let nbtc = Coin::new(99_000_000, BTC_NATIVE_TOKEN_DENOM);
+        building.insert_pending_deposit(outpoint, dest.clone(), nbtc.clone())?;
+        let index = btc.checkpoints.index(store);
+        btc.checkpoints.set(store, index, &building)?;
+        btc.give_miner_fee(store, fee)?;
+        btc.processed_outpoints.insert(store, outpoint, u64::MAX)?;
+
+        let mut deposits = DEPOSIT_BLOCKS
+            .may_load(store, block_key)?
+            .unwrap_or_default();
+        deposits.push(RelayedDeposit {
+            checkpoint_index: index,
+            outpoint: Adapter::new(outpoint),
+            dest,
+            nbtc,
+            fee,
+        });
+        DEPOSIT_BLOCKS.save(store, block_key, &deposits)?;
+        Ok(())
+    };
+
+    // deposits in the building checkpoint are undone
+    push_deposit(&mut btc, deps.as_mut().storage, 0)?;
+    push_deposit(&mut btc, deps.as_mut().storage, 1)?;
+    btc.invalidate_deposits(deps.as_mut().storage, &reorg)?;
+
+    let building = btc.checkpoints.building(deps.as_ref().storage)?;
+    assert!(building.batches[BatchType::Checkpoint][0].input.is_empty());
+    assert!(building.pending.is_empty());
+    assert_eq!(building.fees_collected, 0);
+    assert_eq!(FEE_POOL.load(deps.as_ref().storage)?, 0);
+    let outpoint = OutPoint {
+        txid: Txid::from_slice(&[0; 32])?,
+        vout: 0,
+    };
+    assert!(!btc
+        .processed_outpoints
+        .contains(deps.as_ref().storage, outpoint));
+    assert!(DEPOSIT_BLOCKS
+        .may_load(deps.as_ref().storage, block_key)?
+        .is_none());
+
+    // deposits in a signing checkpoint raise an alert instead
+    push_deposit(&mut btc, deps.as_mut().storage, 0)?;
+    let deps_mut = deps.as_mut();
+    btc.begin_block_step(
+        &set_time(1000),
+        deps_mut.storage,
+        &deps_mut.querier,
+        vec![1, 2, 3],
+        &[],
+    )?;
+    assert!(btc.checkpoints.signing(deps.as_ref().storage)?.is_some());
+    btc.invalidate_deposits(deps.as_mut().storage, &reorg)?;
+
+    let signing = btc.checkpoints.signing(deps.as_ref().storage)?.unwrap();
+    assert_eq!(signing.batches[BatchType::Checkpoint][0].input.len(), 1);
+    assert!(btc
+        .processed_outpoints
+        .contains(deps.as_ref().storage, outpoint));
+    let reorged = REORGED_DEPOSITS.load(deps.as_ref().storage, &outpoint.to_string())?;
+    assert_eq!(reorged.checkpoint_index, 0);
+
+    // deposits from blocks below the header queue are forgotten
+    let stale_key = (header_config.trusted_height - 1, &block_hash[..]);
+    DEPOSIT_BLOCKS.save(deps.as_mut().storage, stale_key, &vec![])?;
+    push_deposit(&mut btc, deps.as_mut().storage, 2)?;
+    btc.prune_deposit_blocks(deps.as_mut().storage)?;
+    assert!(!DEPOSIT_BLOCKS.has(deps.as_ref().storage, stale_key));
+    assert!(DEPOSIT_BLOCKS.has(deps.as_ref().storage, block_key));
+
+    // deposits are still undone when the fee pool can not cover their fee,
+    // taking back only what the pool holds
+    FEE_POOL.save(deps.as_mut().storage, &(fee.u128() as i64 / 2))?;
+    btc.invalidate_deposits(deps.as_mut().storage, &reorg)?;
+
+    let building = btc.checkpoints.building(deps.as_ref().storage)?;
+    assert!(building.batches[BatchType::Checkpoint][0].input.is_empty());
+    assert!(building.pending.is_empty());
+    assert_eq!(building.fees_collected, 0);
+    assert_eq!(FEE_POOL.load(deps.as_ref().storage)?, 0);
+    assert!(!DEPOSIT_BLOCKS.has(deps.as_ref().storage, block_key));
+
+    Ok(())
+}
+
+#[test]
+fn header_rewards() -> ContractResult<()> {
+    let mut deps = mock_dependencies();
+    CONFIG.save(
+        deps.as_mut().storage,
+        &crate::msg::Config {
+            owner: Addr::unchecked("owner"),
+            token_factory_addr: Addr::unchecked("token_factory_addr"),
+            relayer_fee_receiver: Addr::unchecked("relayer_fee_receiver"),
+            token_fee_receiver: Addr::unchecked("token_fee_receiver"),
+            relayer_fee_token: AssetInfo::NativeToken {
+                denom: "orai".to_string(),
+            },
+            relayer_fee: Uint128::from(0u128),
+            swap_router_contract: None,
+            osor_entry_point_contract: None,
+        },
+    )?;
+    let nbtc_denom = get_full_btc_denom("token_factory_addr");
+
+    let mut btc = Bitcoin::default();
+    let header_config = HeaderConfig::for_network(bitcoin::Network::Regtest)?;
+    let genesis = *header_config.trusted_header;
+    btc.headers
+        .configure(deps.as_mut().storage, header_config)?;
+    let mut bitcoin_config = BitcoinConfig::default();
+    bitcoin_config.header_reward = 100;
+    bitcoin_config.header_reward_confirmations = 2;
+    BITCOIN_CONFIG.save(deps.as_mut().storage, &bitcoin_config)?;
+    FEE_POOL.save(deps.as_mut().storage, &250)?;
+
+    let mine = |prev: BlockHeader, heights: std::ops::RangeInclusive<u32>, spacing: u32| {
+        let mut prev = prev;
+        let mut headers = vec![];
+        for height in heights {
+            prev = mine_header(&prev, prev.time + spacing, 0x207fffff);
+            headers.push((prev, WrappedHeader::new(Adapter::new(prev), height)));
+        }
+        headers
+    };
+    let relayer_a = Addr::unchecked("relayer_a");
+    let relayer_b = Addr::unchecked("relayer_b");
+    let relay = |btc: &mut Bitcoin,
+                 store: &mut dyn Storage,
+                 relayer: &Addr,
+                 headers: Vec<WrappedHeader>|
+     -> ContractResult<()> {
+        btc.headers
+            .add(&mock_env(), store, headers.clone().into())?;
+        btc.reward_headers(store, relayer, &headers)
+    };
+
+    let (raw_main, main): (Vec<_>, Vec<_>) = mine(genesis, 1..=3, 600).into_iter().unzip();
+    relay(&mut btc, deps.as_mut().storage, &relayer_a, main)?;
+    assert_eq!(FEE_POOL.load(deps.as_ref().storage)?, 150);
+
+    // reorg out the last header of relayer a
+    let fork: Vec<_> = mine(raw_main[1], 3..=6, 601)
+        .into_iter()
+        .map(|(_, header)| header)
+        .collect();
+    relay(
+        &mut btc,
+        deps.as_mut().storage,
+        &relayer_b,
+        fork[..2].to_vec(),
+    )?;
+    assert_eq!(FEE_POOL.load(deps.as_ref().storage)?, 50);
+
+    // the reorged header is dropped, and the fee pool can not pay for more
+    relay(
+        &mut btc,
+        deps.as_mut().storage,
+        &relayer_b,
+        fork[2..3].to_vec(),
+    )?;
+    assert_eq!(FEE_POOL.load(deps.as_ref().storage)?, 50);
+    assert!(btc
+        .header_rewards(deps.as_ref().storage, &relayer_b)?
+        .is_empty());
+
+    // the reward stays pending until the fee pool can pay it
+    FEE_POOL.save(deps.as_mut().storage, &150)?;
+    btc.reward_headers(deps.as_mut().storage, &relayer_b, &[])?;
+    assert_eq!(FEE_POOL.load(deps.as_ref().storage)?, 50);
+    assert_eq!(
+        btc.header_rewards(deps.as_ref().storage, &relayer_b)?,
+        vec![Coin::new(100, nbtc_denom.clone())]
+    );
+
+    // rewards in a dedicated denom do not use the fee pool
+    bitcoin_config.header_reward_denom = Some("orai".to_string());
+    BITCOIN_CONFIG.save(deps.as_mut().storage, &bitcoin_config)?;
+    relay(
+        &mut btc,
+        deps.as_mut().storage,
+        &relayer_b,
+        fork[3..].to_vec(),
+    )?;
+    assert_eq!(
+        btc.header_rewards(deps.as_ref().storage, &relayer_b)?,
+        vec![Coin::new(100, nbtc_denom.clone()), Coin::new(100, "orai")]
+    );
+
+    assert_eq!(
+        btc.take_header_rewards(deps.as_mut().storage, &relayer_a)?,
+        vec![Coin::new(200, nbtc_denom)]
+    );
+    assert!(btc
+        .header_rewards(deps.as_ref().storage, &relayer_a)?
+        .is_empty());
+
+    // nBTC rewards can only be paid from the fee pool
+    bitcoin_config.header_reward_denom = Some(nbtc_denom);
+    assert!(entrypoints::update_bitcoin_config(
+        deps.as_mut().storage,
+        mock_info("owner", &[]),
+        bitcoin_config,
+    )
+    .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn set_recovery_script() -> ContractResult<()> {
+    let mut deps = mock_dependencies();
+    let header_config = HeaderConfig::for_network(bitcoin::Network::Regtest)?;
+    HEADER_CONFIG.save(deps.as_mut().storage, &header_config)?;
+
+    let mut btc = Bitcoin::default();
+    BITCOIN_CONFIG.save(deps.as_mut().storage, &btc.config)?;
+    let account = Addr::unchecked("account");
+    assert_eq!(btc.recovery_script(deps.as_ref().storage, &account)?, None);
+
+    let err = btc
+        .set_recovery_script(
+            deps.as_mut().storage,
+            account.clone(),
+            Adapter::new(Script::from(vec![0x6a; 65])),
+        )
+        .unwrap_err();
+    assert_eq!(err.to_string(), "App Error: Script exceeds maximum length");
+
+    let err = btc
+        .set_recovery_script(
+            deps.as_mut().storage,
+            account.clone(),
+            Adapter::new(Script::from(vec![0x51])),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "App Error: Script is not a standard script for the network"
+    );
+
+    let script = Script::new_v0_p2wsh(&bitcoin::WScriptHash::hash(&[0]));
+    btc.set_recovery_script(
+        deps.as_mut().storage,
+        account.clone(),
+        Adapter::new(script.clone()),
+    )?;
+    assert_eq!(
+        btc.recovery_script(deps.as_ref().storage, &account)?,
+        Some(Adapter::new(script.clone()))
+    );
+
+    RECOVERY_SCRIPT_COUNT.save(deps.as_mut().storage, &MAX_RECOVERY_SCRIPTS)?;
+    let err = btc
+        .set_recovery_script(
+            deps.as_mut().storage,
+            Addr::unchecked("other_account"),
+            Adapter::new(script.clone()),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "App Error: Maximum number of recovery scripts reached"
+    );
+    // accounts with a registered script can still update it
+    btc.set_recovery_script(deps.as_mut().storage, account, Adapter::new(script))?;
+
+    Ok(())
+}
+
+#[test]
+fn reject_merkle_ambiguous_proofs() -> ContractResult<()> {
+    let header_with_root = |root: TxMerkleNode| {
+        let header = BlockHeader {
+            version: 0x2000_0000,
+            prev_blockhash: BlockHash::all_zeros(),
+            merkle_root: root,
+            time: 0,
+            bits: 0x1d00ffff,
+            nonce: 0,
+        };
+        WorkHeader::new(
+            WrappedHeader::from_header(&header, 1),
+            uint::Uint256::from_u64(1).unwrap(),
+        )
+    };
+    let root_of =
+        |proof: &PartialMerkleTree| proof.extract_matches(&mut vec![], &mut vec![]).unwrap();
+    let tx = |input: OutPoint, script_sig: Vec<u8>, script_pubkey: Vec<u8>| Transaction {
+        version: 1,
+        lock_time: bitcoin::PackedLockTime(0),
+        input: vec![bitcoin::TxIn {
+            previous_output: input,
+            script_sig: Script::from(script_sig),
+            sequence: bitcoin::Sequence::MAX,
+            witness: bitcoin::Witness::default(),
+        }],
+        output: vec![bitcoin::TxOut {
+            value: 0,
+            script_pubkey: Script::from(script_pubkey),
+        }],
+    };
+
+    // a 64-byte transaction whose serialization doubles as an inner node
+    let short_tx = tx(OutPoint::new(Txid::all_zeros(), 0), vec![], vec![0x51; 4]);
+    let short_bytes = bitcoin::consensus::serialize(&short_tx);
+    assert_eq!(short_bytes.len(), 64);
+    let left = Txid::from_slice(&short_bytes[..32]).unwrap();
+    let right = Txid::from_slice(&short_bytes[32..]).unwrap();
+
+    let header = header_with_root(TxMerkleNode::from_inner(short_tx.txid().into_inner()));
+    let inner_proof = PartialMerkleTree::from_txids(&[left, right], &[true, false]);
+    assert_eq!(root_of(&inner_proof), header.merkle_root());
+    let short_proof = PartialMerkleTree::from_txids(&[short_tx.txid()], &[true]);
+    assert_eq!(
+        app::verify_tx_proof(&short_tx, &short_proof, &header, None)
+            .unwrap_err()
+            .to_string(),
+        "App Error: Transactions of 64 bytes are not accepted"
+    );
+
+    // a block of [coinbase, tx, left, right], where the 64-byte transaction
+    // is forged as a leaf one level above the real leaves
+    let coinbase = tx(
+        OutPoint::null(),
+        vec![0x03, 0x01, 0x00, 0x00],
+        vec![0x51; 22],
+    );
+    let deposit_tx = tx(OutPoint::new(Txid::all_zeros(), 1), vec![], vec![0x51; 22]);
+    let txids = [coinbase.txid(), deposit_tx.txid(), left, right];
+    let header = header_with_root(root_of(&PartialMerkleTree::from_txids(
+        &txids,
+        &[true, false, false, false],
+    )));
+    let coinbase_proof = CoinbaseProof {
+        tx: Adapter::new(coinbase.clone()),
+        proof: Adapter::new(PartialMerkleTree::from_txids(
+            &txids,
+            &[true, false, false, false],
+        )),
+    };
+
+    let first_pair = root_of(&PartialMerkleTree::from_txids(&txids[..2], &[true, false]));
+    let forged_proof = PartialMerkleTree::from_txids(
+        &[Txid::from_inner(first_pair.into_inner()), short_tx.txid()],
+        &[false, true],
+    );
+    assert_eq!(root_of(&forged_proof), header.merkle_root());
+    assert_eq!(
+        app::verify_tx_proof(&short_tx, &forged_proof, &header, Some(&coinbase_proof))
+            .unwrap_err()
+            .to_string(),
+        "App Error: Bitcoin merkle proof depth does not match the coinbase proof"
+    );
+
+    let deposit_proof = PartialMerkleTree::from_txids(&txids, &[false, true, false, false]);
+    app::verify_tx_proof(&deposit_tx, &deposit_proof, &header, Some(&coinbase_proof))?;
+
+    let not_coinbase = CoinbaseProof {
+        tx: Adapter::new(deposit_tx.clone()),
+        proof: Adapter::new(deposit_proof.clone()),
+    };
+    assert_eq!(
+        app::verify_tx_proof(&deposit_tx, &deposit_proof, &header, Some(&not_coinbase))
+            .unwrap_err()
+            .to_string(),
+        "App Error: Coinbase proof does not contain a coinbase transaction"
+    );
+
+    Ok(())
+}
@@ -1,13 +1,26 @@
-use cosmwasm_std::{testing::mock_dependencies, Storage};
+use bitcoin::{hashes::Hash, Network, OutPoint, Script, Sequence, TxOut, Txid, WScriptHash};
+use cosmwasm_std::testing::{mock_dependencies, mock_env, MockQuerier};
+use cosmwasm_std::{coins, Addr, Coin, Empty, Order, QuerierWrapper, Storage, Uint128};
+use libsecp256k1_core::curve::ECMultContext;
 
 use crate::{
-    checkpoint::{adjust_fee_rate, BitcoinTx, Checkpoint, CheckpointQueue, CheckpointStatus},
-    constants::DEFAULT_FEE_RATE,
+    adapter::Adapter,
+    app::RelayedDeposit,
+    checkpoint::{
+        adjust_fee_rate, BatchType, BitcoinTx, Checkpoint, CheckpointQueue, CheckpointStatus, Input,
+    },
+    constants::{DEFAULT_FEE_RATE, MUSIG_SIGNING_TIMEOUT, RECOVERY_BALANCES_PER_BLOCK},
+    contract::migrate,
     error::ContractResult,
-    interface::CheckpointConfig,
-    signatory::{Signatory, SignatorySet},
-    state::{BUILDING_INDEX, CHECKPOINTS, CONFIRMED_INDEX},
-    tests::helper::push_bitcoin_tx_output,
+    header::HeaderQueue,
+    interface::{BitcoinConfig, CheckpointConfig, Dest, EmergencyDisbursalConfig, HeaderConfig},
+    msg::{Config, MigrateMsg},
+    signatory::{ScriptType, Signatory, SignatorySet},
+    state::{
+        get_full_btc_denom, BUILDING_INDEX, CHECKPOINTS, CONFIG, CONFIRMED_INDEX, FEE_POOL,
+        RECOVERY_BALANCES, RECOVERY_BALANCES_CURSOR, RECOVERY_SCRIPTS,
+    },
+    tests::helper::{push_bitcoin_tx_output, set_time},
     threshold_sig::Pubkey,
 };
 
@@ -65,6 +78,7 @@ fn create_queue_with_status(
             sigset: SignatorySet::default(),
             fees_collected: 0,
             pending: vec![],
+            pending_outpoints: vec![],
             batches: vec![],
         };
 
@@ -266,3 +280,453 @@ fn sigset(n: u32) -> SignatorySet {
 
     sigset
 }
+
+#[test]
+fn emergency_disbursal() -> ContractResult<()> {
+    let mut deps = mock_dependencies();
+    let store = deps.as_mut().storage;
+    let queue = CheckpointQueue::default();
+    BUILDING_INDEX.save(store, &0)?;
+    let ctx = ECMultContext::new_boxed();
+
+    let config = CheckpointConfig::default();
+    let disbursal_config = EmergencyDisbursalConfig {
+        max_tx_size: 1,
+        ..Default::default()
+    };
+    let lock_time = 1_700_000_000;
+    let reserve_outpoint = OutPoint {
+        txid: Txid::from_slice(&[1; 32])?,
+        vout: 0,
+    };
+    let recovery_output = |i: u8, value| TxOut {
+        value,
+        script_pubkey: Script::new_v0_p2wsh(&WScriptHash::hash(&[i])),
+    };
+    let outputs = vec![
+        recovery_output(0, 10_000_000),
+        recovery_output(1, 500),
+        recovery_output(2, 10_000_000),
+        recovery_output(3, 10_000_000),
+    ];
+
+    // Nothing to disburse
+    CHECKPOINTS.push_back(store, &Checkpoint::new(sigset(0))?)?;
+    let mut building = queue.building(store)?;
+    building.generate_emergency_disbursal_txs(
+        &ctx,
+        lock_time,
+        reserve_outpoint,
+        100_000_000,
+        vec![recovery_output(0, 500)],
+        &config,
+        &disbursal_config,
+    )?;
+    assert!(building.batches[BatchType::IntermediateTx].is_empty());
+    assert!(building.batches[BatchType::Disbursal].is_empty());
+
+    building.generate_emergency_disbursal_txs(
+        &ctx,
+        lock_time,
+        reserve_outpoint,
+        100_000_000,
+        outputs.clone(),
+        &config,
+        &disbursal_config,
+    )?;
+
+    // The reserve is spent by the intermediate tx, with one output per final
+    // tx and the remaining funds paid back to the signatory set.
+    let intermediate_tx = &building.batches[BatchType::IntermediateTx][0];
+    let final_txs = &building.batches[BatchType::Disbursal];
+    assert_eq!(final_txs.len(), 3);
+    assert_eq!(intermediate_tx.lock_time, lock_time);
+    assert_eq!(*intermediate_tx.input[0].prevout, reserve_outpoint);
+    assert_eq!(intermediate_tx.output.len(), 4);
+    let intermediate_fee = intermediate_tx.est_vsize()? * DEFAULT_FEE_RATE;
+    assert_eq!(
+        intermediate_tx.output[3].value,
+        100_000_000 - 30_000_000 - intermediate_fee
+    );
+    assert_eq!(
+        intermediate_tx.output[3].script_pubkey,
+        sigset(0).output_script(&ctx, &[0u8], config.sigset_threshold)?
+    );
+    let bitcoin_tx = intermediate_tx.to_bitcoin_tx()?;
+    assert_eq!(
+        bitcoin_tx.input[0].sequence,
+        Sequence::ENABLE_LOCKTIME_NO_RBF
+    );
+    assert_ne!(intermediate_tx.input[0].signatures.message(), [0; 32]);
+
+    let intermediate_txid = intermediate_tx.txid()?;
+    for (i, tx) in final_txs.iter().enumerate() {
+        assert_eq!(tx.lock_time, lock_time);
+        assert_eq!(tx.input[0].prevout.txid, intermediate_txid);
+        assert_eq!(tx.input[0].prevout.vout, i as u32);
+        assert_eq!(tx.input[0].amount, 10_000_000);
+        assert_eq!(intermediate_tx.output[i].value, 10_000_000);
+        assert_ne!(tx.input[0].signatures.message(), [0; 32]);
+
+        let fee = tx.est_vsize()? * DEFAULT_FEE_RATE;
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(tx.output[0].value, 10_000_000 - fee);
+    }
+    assert_eq!(
+        final_txs[1].output[0].script_pubkey,
+        outputs[2].script_pubkey
+    );
+
+    // The outputs are reduced in proportion if the reserve can't pay them
+    CHECKPOINTS.push_back(store, &Checkpoint::new(sigset(0))?)?;
+    BUILDING_INDEX.save(store, &1)?;
+    let mut building = queue.building(store)?;
+    building.generate_emergency_disbursal_txs(
+        &ctx,
+        lock_time,
+        reserve_outpoint,
+        15_000_000,
+        outputs,
+        &config,
+        &disbursal_config,
+    )?;
+    let intermediate_tx = &building.batches[BatchType::IntermediateTx][0];
+    assert_eq!(intermediate_tx.output.len(), 3);
+    let paid = intermediate_tx.value()?;
+    assert!(paid <= 15_000_000 - intermediate_tx.est_vsize()? * DEFAULT_FEE_RATE);
+    assert!(paid > 14_900_000);
+    assert_eq!(
+        intermediate_tx.output[0].value,
+        intermediate_tx.output[1].value
+    );
+
+    // Outputs which are below the minimum amount once reduced are left in the
+    // change output
+    CHECKPOINTS.push_back(store, &Checkpoint::new(sigset(0))?)?;
+    BUILDING_INDEX.save(store, &2)?;
+    let mut building = queue.building(store)?;
+    building.generate_emergency_disbursal_txs(
+        &ctx,
+        lock_time,
+        reserve_outpoint,
+        500_000,
+        vec![recovery_output(0, 10_000_000), recovery_output(1, 20_000)],
+        &config,
+        &disbursal_config,
+    )?;
+    let final_txs = &building.batches[BatchType::Disbursal];
+    assert_eq!(final_txs.len(), 1);
+    assert_eq!(
+        final_txs[0].output[0].script_pubkey,
+        outputs[0].script_pubkey
+    );
+
+    // Outputs which can't pay their share of the miner fee are left in the
+    // change output
+    CHECKPOINTS.push_back(store, &Checkpoint::new(sigset(0))?)?;
+    BUILDING_INDEX.save(store, &3)?;
+    let mut building = queue.building(store)?;
+    building.generate_emergency_disbursal_txs(
+        &ctx,
+        lock_time,
+        reserve_outpoint,
+        100_000_000,
+        vec![recovery_output(0, 10_000_000), recovery_output(1, 1_500)],
+        &config,
+        &disbursal_config,
+    )?;
+    let intermediate_tx = &building.batches[BatchType::IntermediateTx][0];
+    let final_txs = &building.batches[BatchType::Disbursal];
+    assert_eq!(final_txs.len(), 1);
+    assert_eq!(intermediate_tx.output.len(), 2);
+    assert_eq!(
+        intermediate_tx.output[1].value,
+        100_000_000 - 10_000_000 - intermediate_tx.est_vsize()? * DEFAULT_FEE_RATE
+    );
+    assert_eq!(final_txs[0].input[0].prevout.vout, 0);
+    assert_eq!(final_txs[0].input[0].amount, 10_000_000);
+
+    Ok(())
+}
+
+#[test]
+fn musig_fallback_to_script_path() -> ContractResult<()> {
+    let mut deps = mock_dependencies();
+    let store = deps.as_mut().storage;
+    let mut queue = CheckpointQueue::default();
+    let ctx = ECMultContext::new_boxed();
+
+    let mut signing_sigset = sigset(0);
+    signing_sigset.script_type = ScriptType::Musig2;
+    let pubkey = signing_sigset.signatories[0].pubkey.clone();
+    let mut signing = Checkpoint::new(signing_sigset.clone())?;
+    signing.status = CheckpointStatus::Signing;
+    let tx = &mut signing.batches[BatchType::Checkpoint][0];
+    tx.input.push(Input::new(
+        &ctx,
+        OutPoint::default(),
+        &signing_sigset,
+        &[0u8],
+        100_000,
+        (2, 3),
+    )?);
+    tx.output.push(Adapter::new(TxOut {
+        value: 90_000,
+        script_pubkey: Script::new_v0_p2wsh(&WScriptHash::hash(&[0])),
+    }));
+    tx.populate_input_sig_message(0)?;
+    let key_path_message = tx.input[0].signatures.message();
+    let key_path_vsize = tx.est_vsize()?;
+    let reserve_outpoint = OutPoint::new(tx.txid()?, 0);
+    CHECKPOINTS.push_back(store, &signing)?;
+
+    // The building checkpoint is created when the previous one starts signing,
+    // and spends its reserve output.
+    let mut building = Checkpoint::new(sigset(1))?;
+    let building_sigset = building.sigset.clone();
+    building.batches[BatchType::Checkpoint][0]
+        .input
+        .push(Input::new(
+            &ctx,
+            reserve_outpoint,
+            &building_sigset,
+            &[0u8],
+            90_000,
+            (2, 3),
+        )?);
+    let signing_since = building.create_time();
+    CHECKPOINTS.push_back(store, &building)?;
+    BUILDING_INDEX.save(store, &1)?;
+    FEE_POOL.save(store, &0)?;
+    let config = BitcoinConfig::default();
+
+    let checkpoint_tx = |store: &dyn Storage, index: u32| -> ContractResult<BitcoinTx> {
+        let checkpoint = CHECKPOINTS.get(store, index)?.unwrap();
+        Ok(checkpoint.batches[BatchType::Checkpoint][0].clone())
+    };
+
+    queue.maybe_fallback_to_script_path(
+        &set_time(signing_since + MUSIG_SIGNING_TIMEOUT - 1),
+        store,
+        &config,
+    )?;
+    let still_musig = checkpoint_tx(store, 0)?.input[0].clone();
+    assert!(still_musig.signatures.musig.is_some());
+    assert!(!still_musig.signatures.needs_sig(pubkey.clone()));
+
+    queue.maybe_fallback_to_script_path(
+        &set_time(signing_since + MUSIG_SIGNING_TIMEOUT),
+        store,
+        &config,
+    )?;
+    let tx = checkpoint_tx(store, 0)?;
+    let fallback = &tx.input[0];
+    assert!(fallback.signatures.musig.is_none());
+    assert!(fallback.signatures.needs_sig(pubkey));
+    assert_ne!(fallback.signatures.message(), key_path_message);
+    assert_eq!(
+        fallback.est_witness_vsize,
+        signing_sigset.est_script_path_witness_vsize()
+    );
+
+    // The larger witness is paid for from the reserve output and the fee pool.
+    let extra_fee = (tx.est_vsize()? - key_path_vsize) * DEFAULT_FEE_RATE;
+    assert!(extra_fee > 0);
+    assert_eq!(tx.output[0].value, 90_000 - extra_fee);
+    assert_eq!(
+        FEE_POOL.load(store)?,
+        -((extra_fee * config.units_per_sat) as i64)
+    );
+
+    // The building checkpoint now spends the new reserve output.
+    let reserve_input = checkpoint_tx(store, 1)?.input[0].clone();
+    assert_eq!(*reserve_input.prevout, OutPoint::new(tx.txid()?, 0));
+    assert_eq!(reserve_input.amount, 90_000 - extra_fee);
+
+    // Nothing changes once the fallback has happened.
+    queue.maybe_fallback_to_script_path(
+        &set_time(signing_since + MUSIG_SIGNING_TIMEOUT + 1),
+        store,
+        &config,
+    )?;
+    assert_eq!(checkpoint_tx(store, 0)?.txid()?, tx.txid()?);
+
+    Ok(())
+}
+
+#[test]
+fn migrate_adds_disbursal_batches() -> ContractResult<()> {
+    let mut deps = mock_dependencies();
+    let store = deps.as_mut().storage;
+    HeaderQueue::default().configure(store, HeaderConfig::for_network(Network::Regtest)?)?;
+
+    for n in 0..3 {
+        let mut checkpoint = Checkpoint::new(sigset(n))?;
+        checkpoint.batches.drain(..BatchType::Checkpoint as usize);
+        CHECKPOINTS.push_back(store, &checkpoint)?;
+    }
+    BUILDING_INDEX.save(store, &2)?;
+    // the first checkpoint was pruned
+    CHECKPOINTS.pop_front(store)?;
+    cw2::set_contract_version(store, "crates.io:cw_bitcoin", "0.0.1")?;
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {})?;
+
+    let store = deps.as_ref().storage;
+    let queue = CheckpointQueue::default();
+    for index in 1..=2 {
+        let checkpoint = queue.get(store, index)?;
+        assert_eq!(checkpoint.batches.len(), 3);
+        assert_eq!(checkpoint.sigset.index, index);
+        assert!(checkpoint.batches[BatchType::Disbursal].is_empty());
+        assert_eq!(checkpoint.batches[BatchType::Checkpoint].len(), 1);
+    }
+    assert!(store.get(&CHECKPOINTS.get_key(0)).is_none());
+    assert!(CHECKPOINTS
+        .set(deps.as_mut().storage, 2, &Checkpoint::new(sigset(3))?)
+        .is_err());
+
+    Ok(())
+}
+
+#[test]
+fn snapshot_recovery_balances() -> ContractResult<()> {
+    let mut deps = mock_dependencies();
+    let token_factory_addr = Addr::unchecked("token_factory_addr");
+    CONFIG.save(
+        deps.as_mut().storage,
+        &Config {
+            owner: Addr::unchecked("owner"),
+            token_factory_addr: token_factory_addr.clone(),
+            relayer_fee_receiver: Addr::unchecked("relayer_fee_receiver"),
+            token_fee_receiver: Addr::unchecked("token_fee_receiver"),
+            relayer_fee_token: oraiswap::asset::AssetInfo::NativeToken {
+                denom: "orai".to_string(),
+            },
+            relayer_fee: Uint128::zero(),
+            swap_router_contract: None,
+            osor_entry_point_contract: None,
+        },
+    )?;
+    let denom = get_full_btc_denom(token_factory_addr.as_str());
+
+    let accounts: Vec<_> = (0..RECOVERY_BALANCES_PER_BLOCK + 2)
+        .map(|i| Addr::unchecked(format!("account{:03}", i)))
+        .collect();
+    let script = Adapter::new(Script::new_v0_p2wsh(&WScriptHash::hash(&[0])));
+    for account in &accounts {
+        RECOVERY_SCRIPTS.save(deps.as_mut().storage, account, &script)?;
+    }
+
+    // the first account holds no nBTC
+    let balances: Vec<_> = accounts
+        .iter()
+        .enumerate()
+        .map(|(i, account)| (account.as_str(), coins(i as u128 * 1_000, &denom)))
+        .collect();
+    let balances: Vec<_> = balances
+        .iter()
+        .map(|(account, coins)| (*account, coins.as_slice()))
+        .collect();
+    let querier = MockQuerier::<Empty>::new(&balances);
+    let querier = QuerierWrapper::new(&querier);
+
+    let queue = CheckpointQueue::default();
+    queue.snapshot_recovery_balances(deps.as_mut().storage, &querier)?;
+    assert_eq!(
+        RECOVERY_BALANCES_CURSOR.load(deps.as_ref().storage)?,
+        accounts[RECOVERY_BALANCES_PER_BLOCK - 1]
+    );
+    assert!(!RECOVERY_BALANCES.has(deps.as_ref().storage, &accounts[0]));
+    assert_eq!(
+        RECOVERY_BALANCES.load(deps.as_ref().storage, &accounts[1])?,
+        Uint128::new(1_000)
+    );
+    assert!(!RECOVERY_BALANCES.has(
+        deps.as_ref().storage,
+        &accounts[RECOVERY_BALANCES_PER_BLOCK]
+    ));
+
+    queue.snapshot_recovery_balances(deps.as_mut().storage, &querier)?;
+    assert!(RECOVERY_BALANCES_CURSOR
+        .may_load(deps.as_ref().storage)?
+        .is_none());
+    assert_eq!(
+        RECOVERY_BALANCES.load(
+            deps.as_ref().storage,
+            &accounts[RECOVERY_BALANCES_PER_BLOCK + 1]
+        )?,
+        Uint128::new((RECOVERY_BALANCES_PER_BLOCK as u128 + 1) * 1_000)
+    );
+    assert_eq!(
+        RECOVERY_BALANCES
+            .keys(deps.as_ref().storage, None, None, Order::Ascending)
+            .count(),
+        accounts.len() - 1
+    );
+
+    Ok(())
+}
+
+#[test]
+fn remove_deposit_by_outpoint() -> ContractResult<()> {
+    let ctx = ECMultContext::new_boxed();
+    let mut checkpoint = Checkpoint::new(sigset(0))?;
+    let dest = Dest::Address(Addr::unchecked("receiver"));
+    let nbtc = Coin::new(99_000_000, "nbtc");
+    let outpoint = |vout| OutPoint {
+        txid: Txid::from_slice(&[0; 32]).unwrap(),
+        vout,
+    };
+
+    // a transfer and two deposits which credit the same amount to the same
+    // destination
+    checkpoint.insert_pending(dest.clone(), nbtc.clone())?;
+    for vout in 0..2 {
+        let input = Input::new(
+            &ctx,
+            outpoint(vout),
+            &checkpoint.sigset,
+            &[0u8],
+            100_000_000,
+            (2, 3),
+        )?;
+        checkpoint.batches[BatchType::Checkpoint][0]
+            .input
+            .push(input);
+        checkpoint.insert_pending_deposit(outpoint(vout), dest.clone(), nbtc.clone())?;
+    }
+
+    checkpoint.remove_deposit(&RelayedDeposit {
+        checkpoint_index: 0,
+        outpoint: Adapter::new(outpoint(0)),
+        dest: dest.clone(),
+        nbtc: nbtc.clone(),
+        fee: Uint128::zero(),
+    })?;
+
+    let tx = &checkpoint.batches[BatchType::Checkpoint][0];
+    assert_eq!(tx.input.len(), 1);
+    assert_eq!(*tx.input[0].prevout, outpoint(1));
+    assert_eq!(checkpoint.pending.len(), 2);
+    assert_eq!(
+        checkpoint.pending_outpoints,
+        vec![None, Some(Adapter::new(outpoint(1)))]
+    );
+
+    // transfers inserted before outpoints were kept are matched by content
+    checkpoint.pending_outpoints.clear();
+    checkpoint.remove_deposit(&RelayedDeposit {
+        checkpoint_index: 0,
+        outpoint: Adapter::new(outpoint(1)),
+        dest,
+        nbtc,
+        fee: Uint128::zero(),
+    })?;
+    assert!(checkpoint.batches[BatchType::Checkpoint][0]
+        .input
+        .is_empty());
+    assert_eq!(checkpoint.pending.len(), 1);
+
+    Ok(())
+}
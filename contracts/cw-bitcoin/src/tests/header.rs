@@ -1,15 +1,27 @@
 use bitcoin::consensus::Decodable;
 use bitcoin::hashes::hex::FromHex;
 use bitcoin::hashes::sha256d::Hash;
-use bitcoin::BlockHash;
+use bitcoin::hashes::Hash as _;
+use bitcoin::util::merkleblock::PartialMerkleTree;
+use bitcoin::util::uint::Uint256;
 use bitcoin::{hash_types::TxMerkleNode, BlockHeader};
+use bitcoin::{BlockHash, PackedLockTime, Script, Transaction, TxOut};
 use chrono::{TimeZone, Utc};
-use cosmwasm_std::{from_json, testing::mock_dependencies, to_json_binary, Binary};
+use cosmwasm_std::testing::{mock_dependencies, mock_env};
+use cosmwasm_std::{from_json, to_json_binary, Binary, Order, Storage};
 
-use crate::adapter::Adapter;
-use crate::header::{HeaderQueue, WrappedHeader};
-use crate::interface::HeaderConfig;
-use crate::state::{HEADERS, HEADER_CONFIG};
+use crate::adapter::{Adapter, WrappedBinary};
+use crate::constants::{MAX_FORKS, MAX_FORK_AGE};
+use crate::contract::migrate;
+use crate::entrypoints::{
+    query_chain_tip, query_find_fork_point, query_header_by_hash, query_header_by_height,
+    query_header_locator, query_headers, query_verify_tx_inclusion,
+};
+use crate::header::{HeaderList, HeaderQueue, WrappedHeader};
+use crate::interface::{HeaderConfig, HeaderPreset, RetargetAnchor};
+use crate::msg::MigrateMsg;
+use crate::state::{CURRENT_WORK, HEADERS, HEADER_CONFIG, HEADER_HASHES};
+use crate::tests::helper::{mine_header, set_time, CountingStorage};
 
 #[test]
 fn primitive_adapter_encode_decode() {
@@ -186,6 +198,11 @@ fn add_multiple() {
         max_target: 0x1d00ffff,
         retargeting: true,
         min_difficulty_blocks: false,
+        enforce_bip94: false,
+        retarget_anchor: None,
+        max_history_length: 0,
+        checkpoints: vec![],
+        network: bitcoin::Network::Bitcoin,
         trusted_header: BlockHeader {
             version: 1,
             prev_blockhash: BlockHash::from_hex(
@@ -212,7 +229,8 @@ fn add_multiple() {
 
     let mut q = HeaderQueue::default();
     q.configure(deps.as_mut().storage, test_config).unwrap();
-    q.add(deps.as_mut().storage, header_list.into()).unwrap();
+    q.add(&mock_env(), deps.as_mut().storage, header_list.into())
+        .unwrap();
 }
 
 #[test]
@@ -247,6 +265,11 @@ fn add_into_iterator() {
         max_target: 0x1d00ffff,
         retargeting: true,
         min_difficulty_blocks: false,
+        enforce_bip94: false,
+        retarget_anchor: None,
+        max_history_length: 0,
+        checkpoints: vec![],
+        network: bitcoin::Network::Bitcoin,
         trusted_header: BlockHeader {
             version: 1,
             prev_blockhash: Hash::from_hex(
@@ -276,7 +299,8 @@ fn add_into_iterator() {
     let header_list = [WrappedHeader::new(adapter, 43)];
     let mut q = HeaderQueue::default();
     q.configure(deps.as_mut().storage, test_config).unwrap();
-    q.add_into_iter(deps.as_mut().storage, header_list).unwrap();
+    q.add_into_iter(&mock_env(), deps.as_mut().storage, header_list)
+        .unwrap();
 }
 
 #[test]
@@ -310,6 +334,11 @@ fn add_wrong_bits_non_retarget() {
         max_target: 0x1d00ffff,
         retargeting: true,
         min_difficulty_blocks: false,
+        enforce_bip94: false,
+        retarget_anchor: None,
+        max_history_length: 0,
+        checkpoints: vec![],
+        network: bitcoin::Network::Bitcoin,
         trusted_header: BlockHeader {
             version: 1,
             prev_blockhash: Hash::from_hex(
@@ -340,5 +369,1316 @@ fn add_wrong_bits_non_retarget() {
     let header_list = [WrappedHeader::new(adapter, 43)];
     let mut q = HeaderQueue::default();
     q.configure(deps.as_mut().storage, test_config).unwrap();
-    q.add_into_iter(deps.as_mut().storage, header_list).unwrap();
+    q.add_into_iter(&mock_env(), deps.as_mut().storage, header_list)
+        .unwrap();
+}
+
+#[test]
+fn regtest_preset() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    assert!(!config.retargeting);
+    assert_eq!(config.max_target, 0x207fffff);
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config.clone()).unwrap();
+
+    let mut prev = *config.trusted_header;
+    let mut headers = vec![];
+    for height in 1..=5 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+    q.add(&mock_env(), deps.as_mut().storage, headers.into())
+        .unwrap();
+    assert_eq!(q.height(deps.as_ref().storage).unwrap(), 5);
+}
+
+#[test]
+fn testnet4_genesis_preset() {
+    let preset: HeaderPreset = from_json(br#""testnet4""#).unwrap();
+    let config = HeaderConfig::for_preset(preset).unwrap();
+    assert_eq!(config, HeaderConfig::testnet4_genesis());
+    assert_eq!(config.network, bitcoin::Network::Testnet);
+    assert!(config.enforce_bip94);
+    assert!(config.min_difficulty_blocks);
+    assert_eq!(
+        config.trusted_header.block_hash(),
+        BlockHash::from_hex("00000000da84f2bafbbc53dee25a72ae507ff4914b867c565be350b0da8bf043")
+            .unwrap()
+    );
+}
+
+#[test]
+fn bip94_time_warp() {
+    let genesis = *HeaderConfig::for_network(bitcoin::Network::Regtest)
+        .unwrap()
+        .trusted_header;
+    let config = HeaderConfig {
+        retarget_interval: 4,
+        enforce_bip94: true,
+        ..HeaderConfig::regtest(0, genesis)
+    };
+
+    let mut prev = genesis;
+    let mut headers = vec![];
+    for height in 1..=3 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+
+    let relay = |first_block_time: u32| {
+        let mut deps = mock_dependencies();
+        let mut q = HeaderQueue::default();
+        q.configure(deps.as_mut().storage, config.clone()).unwrap();
+
+        let mut headers = headers.clone();
+        let header = mine_header(&prev, first_block_time, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(header), 4));
+        q.add(&mock_env(), deps.as_mut().storage, headers.into())
+    };
+
+    relay(prev.time - 600).unwrap();
+    let err = relay(prev.time - 601).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "App Error: Header timestamp violates the time warp rule"
+    );
+}
+
+#[test]
+fn bip94_retarget_from_first_block() {
+    let trusted_header = BlockHeader {
+        version: 0x2000_0000,
+        prev_blockhash: BlockHash::all_zeros(),
+        merkle_root: TxMerkleNode::all_zeros(),
+        time: 1_500_000_000,
+        bits: 0x1f03ffff,
+        nonce: 0,
+    };
+
+    let relay = |enforce_bip94: bool, bits: u32| {
+        let mut deps = mock_dependencies();
+        let config = HeaderConfig {
+            retarget_interval: 4,
+            target_timespan: 4 * 600,
+            max_target: 0x1f0fffff,
+            enforce_bip94,
+            ..HeaderConfig::testnet3(4, trusted_header)
+        };
+        let mut q = HeaderQueue::default();
+        q.configure(deps.as_mut().storage, config).unwrap();
+
+        // the last block of the period uses the minimum difficulty
+        let header_5 = mine_header(&trusted_header, trusted_header.time + 600, 0x1f03ffff);
+        let header_6 = mine_header(&header_5, header_5.time + 600, 0x1f03ffff);
+        let header_7 = mine_header(&header_6, header_6.time + 1300, 0x1f0fffff);
+        let header_8 = mine_header(&header_7, header_7.time + 600, bits);
+        let headers = vec![
+            WrappedHeader::new(Adapter::new(header_5), 5),
+            WrappedHeader::new(Adapter::new(header_6), 6),
+            WrappedHeader::new(Adapter::new(header_7), 7),
+            WrappedHeader::new(Adapter::new(header_8), 8),
+        ];
+        q.add(&mock_env(), deps.as_mut().storage, headers.into())
+    };
+
+    let timespan = Uint256::from_u64(2500).unwrap();
+    let target_timespan = Uint256::from_u64(2400).unwrap();
+    let bip94_bits = BlockHeader::compact_target_from_u256(
+        &(WrappedHeader::u256_from_compact(0x1f03ffff) * timespan / target_timespan),
+    );
+
+    relay(false, 0x1f0fffff).unwrap();
+    relay(true, bip94_bits).unwrap();
+    assert!(relay(true, 0x1f0fffff).is_err());
+}
+
+#[test]
+fn max_time_increase() {
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+    let now = genesis.time as u64 + 600;
+
+    let relay = |time: u32| {
+        let mut deps = mock_dependencies();
+        let mut q = HeaderQueue::default();
+        q.configure(deps.as_mut().storage, config.clone()).unwrap();
+
+        let header = mine_header(&genesis, time, 0x207fffff);
+        let headers = vec![WrappedHeader::new(Adapter::new(header), 1)];
+        q.add(&set_time(now), deps.as_mut().storage, headers.into())
+    };
+
+    relay(now as u32 + config.max_time_increase).unwrap();
+    let err = relay(now as u32 + config.max_time_increase + 1).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "App Error: Header timestamp is too far ahead of the current time"
+    );
+}
+
+#[test]
+fn header_queries() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    let mut prev = genesis;
+    let mut headers = vec![];
+    for height in 1..=5 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+    q.add(&mock_env(), deps.as_mut().storage, headers.clone().into())
+        .unwrap();
+
+    let store = deps.as_ref().storage;
+    let header = query_header_by_height(store, 2).unwrap().unwrap();
+    assert_eq!(header.height, 2);
+    assert_eq!(header.hash.0, headers[1].block_hash());
+    assert_eq!(header.prev_hash.0, headers[0].block_hash());
+    assert_eq!(header.time, headers[1].time());
+    assert_eq!(header.bits, 0x207fffff);
+    assert_eq!(header.confirmations, 4);
+    assert_eq!(
+        *header.chain_work.unwrap(),
+        genesis.work() + headers[0].work() + headers[1].work()
+    );
+
+    let by_hash = query_header_by_hash(store, WrappedBinary(headers[1].block_hash()))
+        .unwrap()
+        .unwrap();
+    assert_eq!(by_hash, header);
+    assert!(
+        query_header_by_hash(store, WrappedBinary(BlockHash::all_zeros()))
+            .unwrap()
+            .is_none()
+    );
+    assert!(query_header_by_height(store, 6).unwrap().is_none());
+
+    let page = query_headers(store, Some(1), Some(3)).unwrap();
+    assert_eq!(
+        page.iter().map(|header| header.height).collect::<Vec<_>>(),
+        vec![1, 2, 3]
+    );
+    assert_eq!(query_headers(store, None, None).unwrap().len(), 6);
+    assert_eq!(page[2].confirmations, 3);
+}
+
+#[test]
+fn raw_header_list() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    let mut prev = genesis;
+    let mut headers = vec![];
+    let mut data = vec![];
+    for height in 1..=3 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+        data.extend(bitcoin::consensus::serialize(&prev));
+    }
+
+    let header_list = HeaderList::from_raw(1, &data).unwrap();
+    assert_eq!(Vec::<WrappedHeader>::from(header_list.clone()), headers);
+    q.add(&mock_env(), deps.as_mut().storage, header_list)
+        .unwrap();
+    assert_eq!(q.height(deps.as_ref().storage).unwrap(), 3);
+
+    let err = HeaderList::from_raw(4, &data[..79]).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "App Error: Raw headers must be a multiple of 80 bytes"
+    );
+}
+
+#[test]
+fn chain_tip() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    let mut prev = genesis;
+    let mut headers = vec![];
+    for height in 1..=15 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+
+    // fewer than 11 headers use the median of all of them
+    q.add(
+        &mock_env(),
+        deps.as_mut().storage,
+        headers[..2].to_vec().into(),
+    )
+    .unwrap();
+    let tip = query_chain_tip(deps.as_ref().storage).unwrap();
+    assert_eq!(tip.height, 2);
+    assert_eq!(tip.median_time_past, headers[0].time());
+
+    q.add(
+        &mock_env(),
+        deps.as_mut().storage,
+        headers[2..].to_vec().into(),
+    )
+    .unwrap();
+    let tip = query_chain_tip(deps.as_ref().storage).unwrap();
+    assert_eq!(tip.height, 15);
+    assert_eq!(tip.hash.0, headers[14].block_hash());
+    assert_eq!(tip.time, headers[14].time());
+    assert_eq!(tip.median_time_past, headers[9].time());
+    assert_eq!(tip.bits, 0x207fffff);
+    assert_eq!(*tip.target, headers[14].target());
+    assert_eq!(
+        tip.chain_work,
+        q.current_work(deps.as_ref().storage).unwrap()
+    );
+}
+
+#[test]
+fn verify_tx_inclusion() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    let tx = Transaction {
+        version: 2,
+        lock_time: PackedLockTime(0),
+        input: vec![],
+        output: vec![TxOut {
+            value: 1_000,
+            script_pubkey: Script::new(),
+        }],
+    };
+    let proof = PartialMerkleTree::from_txids(&[tx.txid()], &[true]);
+    let mine_tx = |prev: &BlockHeader, spacing: u32| {
+        let mut header = mine_header(prev, prev.time + spacing, 0x207fffff);
+        header.merkle_root = TxMerkleNode::from_hash(tx.txid().as_hash());
+        header.nonce = 0;
+        while header.validate_pow(&header.target()).is_err() {
+            header.nonce += 1;
+        }
+        header
+    };
+
+    let mut prev = genesis;
+    let mut raw = vec![];
+    let mut headers = vec![];
+    for height in 1..=5 {
+        prev = if height == 2 {
+            mine_tx(&prev, 600)
+        } else {
+            mine_header(&prev, prev.time + 600, 0x207fffff)
+        };
+        raw.push(prev);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+    q.add(&mock_env(), deps.as_mut().storage, headers.clone().into())
+        .unwrap();
+
+    // a candidate fork which also includes the transaction
+    let fork = mine_tx(&raw[0], 601);
+    q.add(
+        &mock_env(),
+        deps.as_mut().storage,
+        vec![WrappedHeader::new(Adapter::new(fork), 2)].into(),
+    )
+    .unwrap();
+
+    let store = deps.as_ref().storage;
+    let verify = |height, block_hash: Option<BlockHash>| {
+        query_verify_tx_inclusion(
+            store,
+            Adapter::new(tx.clone()),
+            Adapter::new(proof.clone()),
+            height,
+            block_hash.map(WrappedBinary),
+            None,
+        )
+    };
+
+    let by_height = verify(Some(2), None).unwrap();
+    assert_eq!(by_height.txid.0, tx.txid());
+    assert_eq!(by_height.confirmations, 4);
+    assert!(by_height.on_best_chain);
+    assert_eq!(
+        verify(None, Some(headers[1].block_hash())).unwrap(),
+        by_height
+    );
+
+    let in_fork = verify(None, Some(fork.block_hash())).unwrap();
+    assert_eq!(in_fork.confirmations, 0);
+    assert!(!in_fork.on_best_chain);
+
+    assert_eq!(
+        verify(Some(3), None).unwrap_err().to_string(),
+        "App Error: Bitcoin merkle proof does not match header"
+    );
+    assert_eq!(
+        verify(Some(3), Some(headers[1].block_hash()))
+            .unwrap_err()
+            .to_string(),
+        format!(
+            "App Error: Block {} is at height 2, not 3",
+            headers[1].block_hash()
+        )
+    );
+    assert_eq!(
+        verify(None, None).unwrap_err().to_string(),
+        "App Error: Either a block hash or a height is required"
+    );
+}
+
+#[test]
+fn header_hash_index() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig {
+        max_length: 5,
+        ..HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap()
+    };
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config.clone()).unwrap();
+
+    let mut prev = genesis;
+    let mut chain_a = vec![];
+    let mut fork_point = genesis;
+    for height in 1..=4 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        chain_a.push(WrappedHeader::new(Adapter::new(prev), height));
+        if height == 2 {
+            fork_point = prev;
+        }
+    }
+    q.add(&mock_env(), deps.as_mut().storage, chain_a.clone().into())
+        .unwrap();
+    for header in chain_a.iter() {
+        let found = q
+            .get_by_hash(deps.as_ref().storage, header.block_hash())
+            .unwrap()
+            .unwrap();
+        assert_eq!(found.height(), header.height());
+    }
+
+    // reorg away the last two headers of chain A
+    let mut prev = fork_point;
+    let mut chain_b = vec![];
+    for height in 3..=5 {
+        prev = mine_header(&prev, prev.time + 601, 0x207fffff);
+        chain_b.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+    q.add(&mock_env(), deps.as_mut().storage, chain_b.clone().into())
+        .unwrap();
+
+    let store = deps.as_ref().storage;
+    assert!(q
+        .get_by_hash(store, chain_a[2].block_hash())
+        .unwrap()
+        .is_none());
+    assert!(q
+        .get_by_hash(store, chain_a[3].block_hash())
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        q.get_for_proof(store, 3, Some(chain_a[2].block_hash()))
+            .unwrap_err()
+            .to_string(),
+        format!(
+            "App Error: Block {} is not on the best chain",
+            chain_a[2].block_hash()
+        )
+    );
+    assert_eq!(
+        q.get_for_proof(store, 4, Some(chain_b[0].block_hash()))
+            .unwrap_err()
+            .to_string(),
+        format!(
+            "App Error: Block {} is at height 3, not 4",
+            chain_b[0].block_hash()
+        )
+    );
+    let header = q
+        .get_for_proof(store, 3, Some(chain_b[0].block_hash()))
+        .unwrap();
+    assert_eq!(header.block_hash(), chain_b[0].block_hash());
+
+    // the genesis header was pruned
+    assert!(q
+        .get_by_hash(store, genesis.block_hash())
+        .unwrap()
+        .is_none());
+
+    q.configure(deps.as_mut().storage, config).unwrap();
+    for header in chain_b.iter() {
+        assert!(q
+            .get_by_hash(deps.as_ref().storage, header.block_hash())
+            .unwrap()
+            .is_none());
+    }
+    assert!(q
+        .get_by_hash(deps.as_ref().storage, genesis.block_hash())
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn header_locator() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config.clone()).unwrap();
+
+    let mut prev = *config.trusted_header;
+    let mut headers = vec![];
+    for height in 1..=30 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+    q.add(&mock_env(), deps.as_mut().storage, headers.clone().into())
+        .unwrap();
+
+    let store = deps.as_ref().storage;
+    let locator = query_header_locator(store).unwrap();
+    let heights: Vec<u32> = locator
+        .iter()
+        .map(|hash| q.get_by_hash(store, hash.0).unwrap().unwrap().height())
+        .collect();
+    assert_eq!(
+        heights,
+        vec![30, 29, 28, 27, 26, 25, 24, 23, 22, 21, 20, 18, 14, 6, 0]
+    );
+
+    let fork_point = query_find_fork_point(
+        store,
+        vec![
+            WrappedBinary(BlockHash::all_zeros()),
+            WrappedBinary(headers[6].block_hash()),
+            WrappedBinary(headers[11].block_hash()),
+        ],
+    )
+    .unwrap();
+    assert_eq!(fork_point, Some(12));
+    assert_eq!(
+        query_find_fork_point(store, vec![WrappedBinary(BlockHash::all_zeros())]).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn incremental_fork() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    let mine = |prev: BlockHeader, heights: std::ops::RangeInclusive<u32>, spacing: u32| {
+        let mut prev = prev;
+        let mut headers = vec![];
+        for height in heights {
+            prev = mine_header(&prev, prev.time + spacing, 0x207fffff);
+            headers.push((prev, WrappedHeader::new(Adapter::new(prev), height)));
+        }
+        headers
+    };
+
+    let (raw_main, main): (Vec<_>, Vec<_>) = mine(genesis, 1..=6, 600).into_iter().unzip();
+    q.add(&mock_env(), deps.as_mut().storage, main.clone().into())
+        .unwrap();
+    let main_tip = q.hash(deps.as_ref().storage).unwrap();
+
+    // a fork with less work is stored without touching the best chain
+    let fork: Vec<_> = mine(raw_main[1], 3..=7, 601)
+        .into_iter()
+        .map(|(_, header)| header)
+        .collect();
+    q.add(
+        &mock_env(),
+        deps.as_mut().storage,
+        fork[..3].to_vec().into(),
+    )
+    .unwrap();
+    assert_eq!(q.height(deps.as_ref().storage).unwrap(), 6);
+    assert_eq!(q.hash(deps.as_ref().storage).unwrap(), main_tip);
+    let forks = q.forks(deps.as_ref().storage).unwrap();
+    assert_eq!(forks.len(), 1);
+    assert_eq!(forks[0].1.base_height, 2);
+    assert_eq!(forks[0].1.tip_height, 5);
+
+    let err = q
+        .add(
+            &mock_env(),
+            deps.as_mut().storage,
+            fork[..3].to_vec().into(),
+        )
+        .unwrap_err();
+    assert_eq!(err.to_string(), "App Error: Provided redundant header.");
+
+    // equal work does not reorg
+    q.add(
+        &mock_env(),
+        deps.as_mut().storage,
+        fork[3..4].to_vec().into(),
+    )
+    .unwrap();
+    assert_eq!(q.hash(deps.as_ref().storage).unwrap(), main_tip);
+    let forks = q.forks(deps.as_ref().storage).unwrap();
+    assert_eq!(forks.len(), 1);
+    assert_eq!(forks[0].1.tip_height, 6);
+
+    // more work makes the fork the best chain
+    let reorg = q
+        .add(
+            &mock_env(),
+            deps.as_mut().storage,
+            fork[4..].to_vec().into(),
+        )
+        .unwrap()
+        .unwrap();
+    let store = deps.as_ref().storage;
+    assert_eq!(reorg.fork_height, 2);
+    assert_eq!(reorg.depth, 4);
+    assert_eq!(*reorg.old_tip, main_tip);
+    assert_eq!(*reorg.new_tip, fork[4].block_hash());
+    assert_eq!(
+        *reorg.work_replaced,
+        main[2..]
+            .iter()
+            .fold(Uint256::zero(), |work, header| work + header.work())
+    );
+    assert_eq!(q.recent_reorgs(store).unwrap(), vec![reorg]);
+    assert_eq!(q.height(store).unwrap(), 7);
+    assert_eq!(q.hash(store).unwrap(), fork[4].block_hash());
+    assert!(q.forks(store).unwrap().is_empty());
+    assert!(q
+        .get_by_hash(store, main[2].block_hash())
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        q.get_by_hash(store, fork[0].block_hash())
+            .unwrap()
+            .unwrap()
+            .height(),
+        3
+    );
+    assert_eq!(
+        *q.current_work(store).unwrap(),
+        fork.iter()
+            .chain(main[..2].iter())
+            .fold(genesis.work(), |work, header| work + header.work())
+    );
+}
+
+#[test]
+fn stale_forks() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    let mut prev = genesis;
+    let mut fork_base = genesis;
+    let mut main = vec![];
+    for height in 1..=4 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        main.push(WrappedHeader::new(Adapter::new(prev), height));
+        if height == 2 {
+            fork_base = prev;
+        }
+    }
+    q.add(&mock_env(), deps.as_mut().storage, main.into())
+        .unwrap();
+
+    let fork = mine_header(&fork_base, fork_base.time + 601, 0x207fffff);
+    q.add(
+        &mock_env(),
+        deps.as_mut().storage,
+        vec![WrappedHeader::new(Adapter::new(fork), 3)].into(),
+    )
+    .unwrap();
+    assert_eq!(q.forks(deps.as_ref().storage).unwrap().len(), 1);
+    assert!(q
+        .get_fork_header_by_hash(deps.as_ref().storage, fork.block_hash())
+        .unwrap()
+        .is_some());
+
+    // forks which are not extended in time are removed
+    let now = mock_env().block.time.seconds() + MAX_FORK_AGE + 1;
+    let next = mine_header(&prev, prev.time + 600, 0x207fffff);
+    let next = vec![WrappedHeader::new(Adapter::new(next), 5)];
+    q.add(&set_time(now), deps.as_mut().storage, next.into())
+        .unwrap();
+    assert!(q.forks(deps.as_ref().storage).unwrap().is_empty());
+    assert!(q
+        .get_fork_header_by_hash(deps.as_ref().storage, fork.block_hash())
+        .unwrap()
+        .is_none());
+
+    let extension = mine_header(&fork, fork.time + 600, 0x207fffff);
+    let err = q
+        .add(
+            &set_time(now),
+            deps.as_mut().storage,
+            vec![WrappedHeader::new(Adapter::new(extension), 4)].into(),
+        )
+        .unwrap_err();
+    assert_eq!(err.to_string(), "App Error: Headers not connect to chain");
+}
+
+#[test]
+fn fork_limit() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    let mut raw = vec![genesis];
+    for _ in 1..=3 {
+        let prev = *raw.last().unwrap();
+        raw.push(mine_header(&prev, prev.time + 600, 0x207fffff));
+    }
+    let main: Vec<_> = (1..=3)
+        .map(|height| WrappedHeader::new(Adapter::new(raw[height as usize]), height))
+        .collect();
+    q.add(&mock_env(), deps.as_mut().storage, main.into())
+        .unwrap();
+
+    let mut add_fork = |parent: usize, offset: u32| {
+        let fork = mine_header(&raw[parent], raw[parent].time + 601 + offset, 0x207fffff);
+        let header = WrappedHeader::new(Adapter::new(fork), parent as u32 + 1);
+        q.add(&mock_env(), deps.as_mut().storage, vec![header].into())
+    };
+    for offset in 0..MAX_FORKS as u32 {
+        add_fork(1, offset).unwrap();
+    }
+
+    // a fork with no more work than the stored ones is rejected
+    let err = add_fork(1, MAX_FORKS as u32).unwrap_err();
+    assert_eq!(err.to_string(), "App Error: Too many candidate forks");
+
+    // a fork with more work replaces the one with the least work
+    add_fork(2, 0).unwrap();
+    let forks = q.forks(deps.as_ref().storage).unwrap();
+    assert_eq!(forks.len(), MAX_FORKS);
+    assert!(forks.iter().all(|(id, _)| *id != 0));
+    assert_eq!(forks.last().unwrap().1.base_height, 2);
+}
+
+#[test]
+fn batch_verification_reads() {
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+
+    let mut prev = *config.trusted_header;
+    let mut headers = vec![];
+    for height in 1..=220 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+    let (base, headers) = headers.split_at(20);
+
+    // the storage reads taken to relay the given batches on top of the base
+    // headers
+    let relay = |batches: Vec<&[WrappedHeader]>| {
+        let mut store = CountingStorage::default();
+        let mut q = HeaderQueue::default();
+        q.configure(&mut store, config.clone()).unwrap();
+        q.add(&mock_env(), &mut store, base.to_vec().into())
+            .unwrap();
+
+        store.reads.set(0);
+        for batch in batches {
+            q.add(&mock_env(), &mut store, batch.to_vec().into())
+                .unwrap();
+        }
+        store.reads.get()
+    };
+
+    // relaying one header at a time reads the config, the timestamp window
+    // and the previous minimum difficulty blocks again for every header
+    let per_header = relay(headers.chunks(1).collect());
+    let batched = relay(vec![headers]);
+    let half = relay(vec![&headers[..100]]);
+
+    // within a batch, a header only takes the read needed to push it to the
+    // queue, the rest is read once per batch
+    assert_eq!(batched - half, 100);
+    assert!(
+        per_header > 20 * batched,
+        "{} reads relaying one header at a time, {} in one batch",
+        per_header,
+        batched
+    );
+}
+
+#[test]
+fn reanchor() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    let mut prev = genesis;
+    let mut headers = vec![];
+    for height in 1..=10 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+    q.add(&mock_env(), deps.as_mut().storage, headers.clone().into())
+        .unwrap();
+
+    // moving the anchor along the best chain keeps the later headers
+    q.reanchor(deps.as_mut().storage, headers[3].clone(), None)
+        .unwrap();
+    let store = deps.as_ref().storage;
+    assert_eq!(q.get_initial_height(store).unwrap(), 4);
+    assert_eq!(q.height(store).unwrap(), 10);
+    assert_eq!(q.trusted_height(store).unwrap(), 4);
+    assert!(q
+        .get_by_hash(store, headers[2].block_hash())
+        .unwrap()
+        .is_none());
+    assert!(q
+        .get_by_hash(store, headers[5].block_hash())
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        *q.current_work(store).unwrap(),
+        headers
+            .iter()
+            .fold(genesis.work(), |work, header| work + header.work())
+    );
+
+    // headers keep being relayed on top of the kept queue
+    let next = mine_header(&prev, prev.time + 600, 0x207fffff);
+    q.add(
+        &mock_env(),
+        deps.as_mut().storage,
+        vec![WrappedHeader::new(Adapter::new(next), 11)].into(),
+    )
+    .unwrap();
+
+    // an anchor which is not on the best chain restarts the queue
+    let other = mine_header(&genesis, genesis.time + 1, 0x207fffff);
+    q.reanchor(
+        deps.as_mut().storage,
+        WrappedHeader::new(Adapter::new(other), 1),
+        None,
+    )
+    .unwrap();
+    let store = deps.as_ref().storage;
+    assert_eq!(q.len(store).unwrap(), 1);
+    assert_eq!(q.hash(store).unwrap(), other.block_hash());
+    assert!(q.get_by_hash(store, next.block_hash()).unwrap().is_none());
+
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::mainnet().unwrap();
+    let trusted = WrappedHeader::new(config.trusted_header.clone(), config.trusted_height + 1);
+    q.configure(deps.as_mut().storage, config).unwrap();
+    assert_eq!(
+        q.reanchor(deps.as_mut().storage, trusted.clone(), None)
+            .unwrap_err()
+            .to_string(),
+        "Trusted header is not the first header of its retargeting period and no retarget anchor was given"
+    );
+    let anchor = RetargetAnchor {
+        time: trusted.time() - 600,
+        bits: trusted.bits(),
+    };
+    q.reanchor(deps.as_mut().storage, trusted, Some(anchor.clone()))
+        .unwrap();
+    assert_eq!(
+        q.config(deps.as_ref().storage).unwrap().retarget_anchor,
+        Some(anchor)
+    );
+}
+
+#[test]
+fn retarget_anchor() {
+    let trusted_header = BlockHeader {
+        version: 0x2000_0000,
+        prev_blockhash: BlockHash::all_zeros(),
+        merkle_root: TxMerkleNode::all_zeros(),
+        time: 1_500_000_000,
+        bits: 0x1f03ffff,
+        nonce: 0,
+    };
+
+    // the trusted header is in the middle of the period starting at height 4
+    let relay = |retarget_anchor: Option<RetargetAnchor>, bits: u32| {
+        let mut deps = mock_dependencies();
+        let config = HeaderConfig {
+            retarget_interval: 4,
+            target_timespan: 4 * 600,
+            max_target: 0x1f0fffff,
+            retarget_anchor,
+            ..HeaderConfig::bitcoin(6, trusted_header)
+        };
+        let mut q = HeaderQueue::default();
+        q.configure(deps.as_mut().storage, config).unwrap();
+
+        let header_7 = mine_header(&trusted_header, trusted_header.time + 600, 0x1f03ffff);
+        let header_8 = mine_header(&header_7, header_7.time + 600, bits);
+        let headers = vec![
+            WrappedHeader::new(Adapter::new(header_7), 7),
+            WrappedHeader::new(Adapter::new(header_8), 8),
+        ];
+        q.add(&mock_env(), deps.as_mut().storage, headers.into())
+    };
+
+    let anchor = RetargetAnchor {
+        time: trusted_header.time - 1200,
+        bits: 0x1f03ffff,
+    };
+    let bits = BlockHeader::compact_target_from_u256(
+        &(WrappedHeader::u256_from_compact(0x1f03ffff) * Uint256::from_u64(1800).unwrap()
+            / Uint256::from_u64(2400).unwrap()),
+    );
+
+    relay(Some(anchor.clone()), bits).unwrap();
+    assert!(relay(Some(anchor), 0x1f03ffff).is_err());
+    assert_eq!(
+        relay(None, bits).unwrap_err().to_string(),
+        "App Error: No previous retargeting header exists"
+    );
+}
+
+#[test]
+fn historical_headers() {
+    let genesis = *HeaderConfig::for_network(bitcoin::Network::Regtest)
+        .unwrap()
+        .trusted_header;
+    let mut raw = vec![genesis];
+    for _ in 1..=12 {
+        let prev = *raw.last().unwrap();
+        raw.push(mine_header(&prev, prev.time + 600, 0x207fffff));
+    }
+    let wrap = |height: u32| WrappedHeader::new(Adapter::new(raw[height as usize]), height);
+
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig {
+        max_length: 5,
+        max_history_length: 4,
+        ..HeaderConfig::regtest(6, raw[6])
+    };
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    q.add_historical(
+        deps.as_mut().storage,
+        vec![wrap(3), wrap(4), wrap(5)].into(),
+    )
+    .unwrap();
+    let store = deps.as_ref().storage;
+    assert_eq!(q.first_height(store).unwrap(), 3);
+    assert_eq!(q.get_initial_height(store).unwrap(), 6);
+    let header = q
+        .get_for_proof(store, 4, Some(raw[4].block_hash()))
+        .unwrap();
+    assert_eq!(header.block_hash(), raw[4].block_hash());
+    assert!(query_header_by_height(store, 2).unwrap().is_none());
+
+    // historical headers are returned without chain work
+    let historical = query_header_by_height(store, 3).unwrap().unwrap();
+    assert!(historical.chain_work.is_none());
+    assert_eq!(
+        query_header_by_hash(store, WrappedBinary(raw[3].block_hash()))
+            .unwrap()
+            .unwrap(),
+        historical
+    );
+    let page = query_headers(store, None, Some(4)).unwrap();
+    assert_eq!(
+        page.iter()
+            .map(|header| header.chain_work.is_some())
+            .collect::<Vec<_>>(),
+        vec![false, false, false, true]
+    );
+
+    let err = q
+        .add_historical(deps.as_mut().storage, vec![wrap(1), wrap(2)].into())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "App Error: Historical headers exceed the maximum history length"
+    );
+    let bogus = mine_header(&raw[1], raw[1].time + 601, 0x207fffff);
+    let err = q
+        .add_historical(
+            deps.as_mut().storage,
+            vec![WrappedHeader::new(Adapter::new(bogus), 2)].into(),
+        )
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "App Error: Passed header is not the previous block of the next header"
+    );
+
+    // pruned headers move to the history, which keeps the newest headers
+    q.add(
+        &mock_env(),
+        deps.as_mut().storage,
+        (7..=12).map(wrap).collect::<Vec<_>>().into(),
+    )
+    .unwrap();
+    let store = deps.as_ref().storage;
+    assert_eq!(q.get_initial_height(store).unwrap(), 8);
+    assert_eq!(q.first_height(store).unwrap(), 4);
+    assert!(q.get_by_hash(store, raw[3].block_hash()).unwrap().is_none());
+    assert_eq!(
+        q.get_by_hash(store, raw[7].block_hash())
+            .unwrap()
+            .unwrap()
+            .height(),
+        7
+    );
+
+    // historical headers can not be reorged
+    let fork = mine_header(&raw[7], raw[7].time + 601, 0x207fffff);
+    let err = q
+        .add(
+            &mock_env(),
+            deps.as_mut().storage,
+            vec![WrappedHeader::new(Adapter::new(fork), 8)].into(),
+        )
+        .unwrap_err();
+    assert_eq!(err.to_string(), "App Error: Headers not connect to chain");
+
+    // nor can the queue be reanchored to them
+    let err = q
+        .reanchor(deps.as_mut().storage, wrap(4), None)
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Trusted header is below the front of the header queue"
+    );
+    assert_eq!(q.get_initial_height(deps.as_ref().storage).unwrap(), 8);
+}
+
+#[test]
+fn header_checkpoints() {
+    let genesis = *HeaderConfig::for_network(bitcoin::Network::Regtest)
+        .unwrap()
+        .trusted_header;
+    let mut raw = vec![genesis];
+    for _ in 1..=5 {
+        let prev = *raw.last().unwrap();
+        raw.push(mine_header(&prev, prev.time + 600, 0x207fffff));
+    }
+    let main: Vec<_> = (1..=5)
+        .map(|height| WrappedHeader::new(Adapter::new(raw[height as usize]), height))
+        .collect();
+
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig {
+        checkpoints: vec![(3, WrappedBinary(raw[3].block_hash()))],
+        ..HeaderConfig::regtest(0, genesis)
+    };
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+    q.add(&mock_env(), deps.as_mut().storage, main.into())
+        .unwrap();
+
+    // a fork with more work is rejected if it conflicts with a checkpoint
+    let mut prev = raw[2];
+    let mut fork = vec![];
+    for height in 3..=6 {
+        prev = mine_header(&prev, prev.time + 601, 0x207fffff);
+        fork.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+    let err = q
+        .add(&mock_env(), deps.as_mut().storage, fork.into())
+        .unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "App Error: Header conflicts with checkpoint at height 3"
+    );
+
+    assert_eq!(
+        q.add_checkpoint(deps.as_mut().storage, 2, raw[2].block_hash())
+            .unwrap_err()
+            .to_string(),
+        "Checkpoints must be added in ascending order of height"
+    );
+    assert_eq!(
+        q.add_checkpoint(deps.as_mut().storage, 5, raw[4].block_hash())
+            .unwrap_err()
+            .to_string(),
+        "Checkpoint conflicts with the best chain"
+    );
+
+    // candidate forks which conflict with a new checkpoint are dropped
+    let fork = mine_header(&raw[4], raw[4].time + 601, 0x207fffff);
+    q.add(
+        &mock_env(),
+        deps.as_mut().storage,
+        vec![WrappedHeader::new(Adapter::new(fork), 5)].into(),
+    )
+    .unwrap();
+    assert_eq!(q.forks(deps.as_ref().storage).unwrap().len(), 1);
+    q.add_checkpoint(deps.as_mut().storage, 5, raw[5].block_hash())
+        .unwrap();
+    assert!(q.forks(deps.as_ref().storage).unwrap().is_empty());
+    assert_eq!(
+        q.config(deps.as_ref().storage).unwrap().checkpoints.len(),
+        2
+    );
+}
+
+#[test]
+fn pruning_keeps_chain_work() {
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+
+    let mut prev = genesis;
+    let mut main = vec![];
+    let mut fork_base = genesis;
+    for height in 1..=10 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        main.push(WrappedHeader::new(Adapter::new(prev), height));
+        if height == 7 {
+            fork_base = prev;
+        }
+    }
+    let mut prev = fork_base;
+    let mut fork = vec![];
+    for height in 8..=11 {
+        prev = mine_header(&prev, prev.time + 601, 0x207fffff);
+        fork.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+
+    // the same relays give the same best chain with and without pruning
+    for max_length in [5, 100] {
+        let mut deps = mock_dependencies();
+        let mut q = HeaderQueue::default();
+        q.configure(
+            deps.as_mut().storage,
+            HeaderConfig {
+                max_length,
+                ..config.clone()
+            },
+        )
+        .unwrap();
+        q.add(&mock_env(), deps.as_mut().storage, main.clone().into())
+            .unwrap();
+
+        let store = deps.as_ref().storage;
+        let work = main
+            .iter()
+            .fold(genesis.work(), |work, header| work + header.work());
+        assert_eq!(*q.current_work(store).unwrap(), work);
+        assert_eq!(
+            HEADERS.back(store).unwrap().unwrap().chain_work,
+            q.current_work(store).unwrap()
+        );
+
+        q.add(
+            &mock_env(),
+            deps.as_mut().storage,
+            fork[..3].to_vec().into(),
+        )
+        .unwrap();
+        assert_eq!(q.hash(deps.as_ref().storage).unwrap(), main[9].block_hash());
+
+        q.add(
+            &mock_env(),
+            deps.as_mut().storage,
+            fork[3..].to_vec().into(),
+        )
+        .unwrap();
+        let store = deps.as_ref().storage;
+        assert_eq!(q.hash(store).unwrap(), fork[3].block_hash());
+        assert_eq!(*q.current_work(store).unwrap(), work + fork[3].work());
+    }
+}
+
+#[test]
+fn recompute_chain_work() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap();
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    let mut prev = genesis;
+    let mut headers = vec![];
+    for height in 1..=5 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+    q.add(&mock_env(), deps.as_mut().storage, headers.into())
+        .unwrap();
+    let expected: Vec<_> = HEADERS
+        .iter(deps.as_ref().storage)
+        .unwrap()
+        .map(|header| header.unwrap().chain_work)
+        .collect();
+
+    // simulate a queue where pruned work was subtracted from the current
+    // work and then used as the base of newer headers
+    let store = deps.as_mut().storage;
+    let pruned = HEADERS.pop_front(store).unwrap().unwrap();
+    let mut header = HEADERS.pop_back(store).unwrap().unwrap();
+    header.chain_work = Adapter::new(*header.chain_work - pruned.work());
+    HEADERS.push_back(store, &header).unwrap();
+    CURRENT_WORK.save(store, &header.chain_work).unwrap();
+    HEADERS.push_front(store, &pruned).unwrap();
+
+    q.recompute_chain_work(store).unwrap();
+    let chain_work: Vec<_> = HEADERS
+        .iter(store)
+        .unwrap()
+        .map(|header| header.unwrap().chain_work)
+        .collect();
+    assert_eq!(chain_work, expected);
+    assert_eq!(q.current_work(store).unwrap(), expected[5]);
+}
+
+#[test]
+fn migrate_indexes_hashes() {
+    let mut deps = mock_dependencies();
+    let config = HeaderConfig {
+        max_length: 5,
+        max_history_length: 3,
+        ..HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap()
+    };
+    let genesis = *config.trusted_header;
+
+    let mut q = HeaderQueue::default();
+    q.configure(deps.as_mut().storage, config).unwrap();
+
+    let mut prev = genesis;
+    let mut headers = vec![WrappedHeader::new(Adapter::new(genesis), 0)];
+    for height in 1..=7 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        headers.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+    q.add(
+        &mock_env(),
+        deps.as_mut().storage,
+        headers[1..].to_vec().into(),
+    )
+    .unwrap();
+    assert_eq!(q.first_height(deps.as_ref().storage).unwrap(), 0);
+    assert_eq!(q.get_initial_height(deps.as_ref().storage).unwrap(), 3);
+
+    // simulate a store from before the hash index
+    let store = deps.as_mut().storage;
+    let keys = HEADER_HASHES
+        .keys(store, None, None, Order::Ascending)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(keys.len(), headers.len());
+    for key in keys {
+        HEADER_HASHES.remove(store, &key);
+    }
+    cw2::set_contract_version(store, "crates.io:cw_bitcoin", "0.0.1").unwrap();
+
+    migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+    let store = deps.as_ref().storage;
+    for header in headers.iter() {
+        let stored = q.get_by_hash(store, header.block_hash()).unwrap().unwrap();
+        assert_eq!(stored.height(), header.height());
+    }
+}
+
+#[test]
+fn migrate_recomputes_chain_work() {
+    let config = HeaderConfig {
+        max_length: 5,
+        ..HeaderConfig::for_network(bitcoin::Network::Regtest).unwrap()
+    };
+    let genesis = *config.trusted_header;
+
+    let mut prev = genesis;
+    let mut main = vec![];
+    let mut fork_base = genesis;
+    for height in 1..=8 {
+        prev = mine_header(&prev, prev.time + 600, 0x207fffff);
+        main.push(WrappedHeader::new(Adapter::new(prev), height));
+        if height == 6 {
+            fork_base = prev;
+        }
+    }
+    let mut prev = fork_base;
+    let mut fork = vec![];
+    for height in 7..=9 {
+        prev = mine_header(&prev, prev.time + 601, 0x207fffff);
+        fork.push(WrappedHeader::new(Adapter::new(prev), height));
+    }
+
+    let mut q = HeaderQueue::default();
+    let mut clean = mock_dependencies();
+    q.configure(clean.as_mut().storage, config.clone()).unwrap();
+    q.add(&mock_env(), clean.as_mut().storage, main.clone().into())
+        .unwrap();
+
+    // simulate a queue which subtracted the work of pruned headers from the
+    // current work
+    let mut drifted = mock_dependencies();
+    q.configure(drifted.as_mut().storage, config).unwrap();
+    q.add(&mock_env(), drifted.as_mut().storage, main.into())
+        .unwrap();
+    let store = drifted.as_mut().storage;
+    let pruned = (0..q.get_initial_height(store).unwrap())
+        .fold(Uint256::zero(), |work, _| work + genesis.work());
+    let current_work = *q.current_work(store).unwrap() - pruned;
+    CURRENT_WORK
+        .save(store, &Adapter::new(current_work))
+        .unwrap();
+
+    // migrations run whether or not the queue drifted
+    for deps in [&mut clean, &mut drifted] {
+        cw2::set_contract_version(deps.as_mut().storage, "crates.io:cw_bitcoin", "0.0.1").unwrap();
+        migrate(deps.as_mut(), mock_env(), MigrateMsg {}).unwrap();
+    }
+
+    // the migrated queue reorgs to the same chain with the same work as a
+    // queue which never drifted
+    for deps in [&mut clean, &mut drifted] {
+        q.add(&mock_env(), deps.as_mut().storage, fork.clone().into())
+            .unwrap();
+    }
+    let chain_work = |store: &dyn Storage| {
+        HEADERS
+            .iter(store)
+            .unwrap()
+            .map(|header| header.unwrap().chain_work)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        q.hash(drifted.as_ref().storage).unwrap(),
+        fork[2].block_hash()
+    );
+    assert_eq!(
+        chain_work(drifted.as_ref().storage),
+        chain_work(clean.as_ref().storage)
+    );
+    assert_eq!(
+        q.current_work(drifted.as_ref().storage).unwrap(),
+        q.current_work(clean.as_ref().storage).unwrap()
+    );
 }
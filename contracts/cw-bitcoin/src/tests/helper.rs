@@ -1,12 +1,16 @@
 use crate::checkpoint::{BitcoinTx, Output};
 use crate::msg::{self};
-use cosmwasm_std::{testing::mock_env, Env, Timestamp};
+use cosmwasm_std::testing::{mock_env, MockStorage};
 use cosmwasm_std::{Addr, Coin};
+use cosmwasm_std::{Env, Order, Record, Storage, Timestamp};
 use cosmwasm_testing_util::MockResult;
+use std::cell::Cell;
 
 use crate::{error::ContractResult, threshold_sig::Signature};
+use bitcoin::hashes::Hash;
 use bitcoin::secp256k1::{Message, Secp256k1};
 use bitcoin::util::bip32::{ChildNumber, ExtendedPrivKey};
+use bitcoin::{BlockHeader, TxMerkleNode};
 
 use derive_more::{Deref, DerefMut};
 
@@ -41,12 +45,61 @@ pub fn push_bitcoin_tx_output(tx: &mut BitcoinTx, value: u64) {
     tx.output.push(Output::new(tx_out));
 }
 
+/// Mines a header on top of `prev` with the given timestamp and bits. Only
+/// usable with easy targets such as the regtest proof-of-work limit.
+pub fn mine_header(prev: &BlockHeader, time: u32, bits: u32) -> BlockHeader {
+    let mut header = BlockHeader {
+        version: 0x2000_0000,
+        prev_blockhash: prev.block_hash(),
+        merkle_root: TxMerkleNode::all_zeros(),
+        time,
+        bits,
+        nonce: 0,
+    };
+    while header.validate_pow(&header.target()).is_err() {
+        header.nonce += 1;
+    }
+    header
+}
+
 pub fn set_time(seconds: u64) -> Env {
     let mut env = mock_env();
     env.block.time = Timestamp::from_seconds(seconds);
     env
 }
 
+/// A mock storage which counts the reads made through it.
+#[derive(Default)]
+pub struct CountingStorage {
+    pub inner: MockStorage,
+    pub reads: Cell<u64>,
+}
+
+impl Storage for CountingStorage {
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+        self.reads.set(self.reads.get() + 1);
+        self.inner.get(key)
+    }
+
+    fn range<'a>(
+        &'a self,
+        start: Option<&[u8]>,
+        end: Option<&[u8]>,
+        order: Order,
+    ) -> Box<dyn Iterator<Item = Record> + 'a> {
+        self.reads.set(self.reads.get() + 1);
+        self.inner.range(start, end, order)
+    }
+
+    fn set(&mut self, key: &[u8], value: &[u8]) {
+        self.inner.set(key, value)
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        self.inner.remove(key)
+    }
+}
+
 #[cfg(not(feature = "test-tube"))]
 pub type TestMockApp = cosmwasm_testing_util::MultiTestMockApp;
 #[cfg(feature = "test-tube")]
@@ -83,6 +136,13 @@ impl MockApp {
         (Self { app, bridge_id }, accounts)
     }
 
+    /// Moves the block time forward by the passed header timestamp, so the
+    /// block time is past it and headers up to that timestamp are not
+    /// rejected as too far in the future.
+    pub fn advance_past(&mut self, time: u32) {
+        self.increase_time(time as u64);
+    }
+
     /// external method
     pub fn create_bridge(
         &mut self,
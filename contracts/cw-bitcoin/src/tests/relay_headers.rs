@@ -27,6 +27,8 @@ fn test_relay_headers() {
                 token_fee_receiver: Addr::unchecked("token_fee_receiver"),
                 swap_router_contract: None,
                 osor_entry_point_contract: None,
+                network: None,
+                preset: None,
             },
         )
         .unwrap();
@@ -55,6 +57,11 @@ fn test_relay_headers() {
         max_target: 0x1d00ffff,
         retargeting: true,
         min_difficulty_blocks: false,
+        enforce_bip94: false,
+        retarget_anchor: None,
+        max_history_length: 0,
+        checkpoints: vec![],
+        network: bitcoin::Network::Bitcoin,
         trusted_header: Adapter::from(trusted_header),
     };
 
@@ -140,6 +147,8 @@ fn test_relay_headers() {
 #[test]
 fn test_relay_headers_2() {
     let (mut app, accounts) = MockApp::new(&[("perfogic", &coins(100_000_000_000, "orai"))]);
+    // the timestamp of the newest relayed header
+    app.advance_past(1721291358);
     let obtc_minter = Addr::unchecked(&accounts[0]);
     let token_factory_addr = app.create_tokenfactory(obtc_minter.clone()).unwrap();
     let bridge_addr = app
@@ -155,6 +164,8 @@ fn test_relay_headers_2() {
                 token_fee_receiver: Addr::unchecked("token_fee_receiver"),
                 swap_router_contract: None,
                 osor_entry_point_contract: None,
+                network: None,
+                preset: None,
             },
         )
         .unwrap();
@@ -184,6 +195,11 @@ fn test_relay_headers_2() {
         max_target: 0x1d00ffff,
         retargeting: true,
         min_difficulty_blocks: false,
+        enforce_bip94: false,
+        retarget_anchor: None,
+        max_history_length: 0,
+        checkpoints: vec![],
+        network: bitcoin::Network::Bitcoin,
         trusted_header: Adapter::from(trusted_header),
     };
 
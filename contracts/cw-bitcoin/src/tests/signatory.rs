@@ -1,9 +1,21 @@
-use bitcoin::{hashes::hex::FromHex, Script};
+use bitcoin::consensus::encode::serialize;
+use bitcoin::hashes::Hash;
+use bitcoin::secp256k1::{
+    schnorr, KeyPair, Message, PublicKey, Secp256k1, SecretKey, XOnlyPublicKey,
+};
+use bitcoin::util::sighash::{Prevouts, SchnorrSighashType, SighashCache};
+use bitcoin::util::taproot::{LeafVersion, TaprootBuilder};
+use bitcoin::{hashes::hex::FromHex, OutPoint, Script};
+use cosmwasm_std::{testing::mock_dependencies, Binary};
+use libsecp256k1_core::curve::{ECMultContext, Scalar};
 
 use crate::{
+    adapter::Adapter,
+    checkpoint::{BitcoinTx, Input},
     error::ContractResult,
-    signatory::{Signatory, SignatorySet},
-    threshold_sig::Pubkey,
+    musig,
+    signatory::{BackupConfig, ScriptType, Signatory, SignatorySet},
+    threshold_sig::{Pubkey, Signature},
 };
 
 fn mock_signatory_set() -> SignatorySet {
@@ -36,10 +48,18 @@ fn mock_signatory_set() -> SignatorySet {
                 ]),
             },
         ],
+        script_type: ScriptType::P2wsh,
+        backup: None,
     };
     sigsets
 }
 
+/// The size of the witness of the given signed input, in virtual bytes.
+fn witness_vsize(input: &Input) -> ContractResult<u64> {
+    let witness = input.to_txin()?.witness;
+    Ok((serialize(&witness).len() as u64).div_ceil(4))
+}
+
 #[test]
 fn test_redeem_script_creation() {
     let sigsets = mock_signatory_set();
@@ -70,8 +90,10 @@ fn test_redeem_script_creation() {
 #[test]
 fn test_output_script() {
     let sigsets = mock_signatory_set();
+    let ctx = ECMultContext::new_boxed();
     let output_script = sigsets
         .output_script(
+            &ctx,
             &[
                 19, 147, 69, 143, 37, 59, 136, 7, 10, 113, 245, 63, 123, 14, 25, 150, 162, 72, 106,
                 16, 123, 127, 34, 111, 110, 139, 181, 102, 8, 1, 206, 250,
@@ -90,7 +112,8 @@ fn test_output_script() {
 fn from_script() {
     let script = bitcoin::Script::from_hex("21028891f36b691a40036f2b3ecb17c13780a932503ef2c39f3faed9b95bf71ea27fac630339e0116700687c2102f6fee7ad7dc87d0a636ae1584273c849bf540f4c1780434a0430888b0c5b151cac63033c910e93687c2102d207371a1e9a588e447d91dc12a8f3479f1f9ff8da748aae04bb5d07f0737790ac630371730893687c2103713e9bb6025fa9dc3c26507762cffd2a9524ff48f1d84c6753caa581347e5e10ac63031def0793687c2103d8fc0412a866bfb14d3fbc9e1b714ca31141d0f7e211d0fa634d53dda9789ecaac6303d1f00693687c2102c7961e04206af92f4b4cf3f19b43722f301e4915a49f5ca2908d9af5ce343830ac6303496f0693687c2103205472bb87799cb9140b5d471cc045b65821a4e75591026a8411ee3ac3e27027ac6303fe500693687c2102c923df10e8141072504b1f9513ee6796dc4d748d774ce9396942b63d42d3d575ac6303ed1f0593687c21031e8124547a5f28e04652d61fab1053ba8af41b682ccecdf5fa58595add7c7d9eac6303d4a00493687c21038060738940b9b3513851aa45df9f8b9d8e3304ef5abc5f8c1928bf4f1c8601adac630347210493687c21022e1efe78c688bceb7a36bf8af0e905da65e1942b84afe31716a356a91c0d9c05ac6303c5620393687c21020598956ed409e190b763bed8ed1ec3a18138c582c761eb8a4cf60861bfb44f13ac6303b3550393687c2102c8b2e54cafced96b1438e9ee6ebddc27c4aca68f14b2199eb8b8da111b584c2cac63036c330393687c2102d8a4c0accefa93b6a8d390a81dbffa4d05cd0a844371b2bed0ba1b1b65e14300ac6303521d0393687c2102460ccc0db97b1027e4fe2ab178f015a786b6b8f016b580f495dde3230f34984cac630304060393687c2102def64dfc155e17988ea6dee5a5659e2ec0a19fce54af90ca84dcd4df53b1a222ac630341d20293687c21030c9057c92c19f749c891037379766c0642d03bd1c50e3b262fc7d954c232f4d8ac630356c30293687c21027e1ebe3dd4fbbf250a8161a8a7af19815d5c07363e220f28f81c535c3950c7cbac6303d3ab0293687c210235e1d72961cb475971e2bc437ac21f9be13c83f1aa039e64f406aae87e2b4816ac6303bdaa0293687c210295d565c8ae94d46d439b4591dcd146742f918893292c23c49d000c4023bad4ffac630308aa029368030fb34aa0010075").unwrap();
 
-    let (sigset, commitment) = SignatorySet::from_script(&script, (2, 3)).unwrap();
+    let (sigset, commitment) =
+        SignatorySet::from_script(&script, (2, 3), ScriptType::P2wsh).unwrap();
 
     let pk = |bytes| Pubkey::new(bytes).unwrap().into();
     assert_eq!(
@@ -246,8 +269,449 @@ fn from_script() {
                         116, 47, 145, 136, 147, 41, 44, 35, 196, 157, 0, 12, 64, 35, 186, 212, 255
                     ])
                 }
-            ]
+            ],
+            script_type: ScriptType::P2wsh,
+            backup: None,
         }
     );
     assert_eq!(commitment, vec![0]);
 }
+
+#[test]
+fn taproot_output_script() {
+    let secp = Secp256k1::new();
+    let ctx = ECMultContext::new_boxed();
+    let mut sigset = mock_signatory_set();
+    sigset.script_type = ScriptType::P2tr;
+    let dest = [0u8];
+
+    let tapscript = sigset.redeem_script(&dest, (2, 3)).unwrap();
+    for signatory in sigset.iter() {
+        let xonly_pubkey = &signatory.pubkey.as_slice()[1..];
+        assert!(tapscript
+            .as_bytes()
+            .windows(32)
+            .any(|bytes| bytes == xonly_pubkey));
+    }
+    let internal_key = XOnlyPublicKey::from_slice(
+        &Vec::from_hex("50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0").unwrap(),
+    )
+    .unwrap();
+    let spend_info = TaprootBuilder::new()
+        .add_leaf(0, tapscript.clone())
+        .unwrap()
+        .finalize(&secp, internal_key)
+        .unwrap();
+
+    assert_eq!(
+        sigset.output_script(&ctx, &dest, (2, 3)).unwrap(),
+        Script::new_v1_p2tr_tweaked(spend_info.output_key())
+    );
+    assert_eq!(
+        sigset.control_block(&ctx, &dest, (2, 3)).unwrap().unwrap(),
+        spend_info
+            .control_block(&(tapscript, LeafVersion::TapScript))
+            .unwrap()
+            .serialize()
+    );
+
+    sigset.script_type = ScriptType::P2wsh;
+    assert!(sigset.control_block(&ctx, &dest, (2, 3)).unwrap().is_none());
+}
+
+#[test]
+fn taproot_input_signing() -> ContractResult<()> {
+    let secp = Secp256k1::new();
+    let ctx = ECMultContext::new_boxed();
+    let deps = mock_dependencies();
+    let keypairs: Vec<_> = (1..=3u8)
+        .map(|i| KeyPair::from_secret_key(&secp, &SecretKey::from_slice(&[i; 32]).unwrap()))
+        .collect();
+    let mut sigset = SignatorySet {
+        present_vp: 300,
+        possible_vp: 300,
+        signatories: keypairs
+            .iter()
+            .map(|keypair| Signatory {
+                voting_power: 100,
+                pubkey: keypair.public_key().into(),
+            })
+            .collect(),
+        script_type: ScriptType::P2tr,
+        ..Default::default()
+    };
+    sigset.signatories.sort_by(|a, b| b.cmp(a));
+
+    let mut tx = BitcoinTx::default();
+    tx.input.push(Input::new(
+        &ctx,
+        OutPoint::default(),
+        &sigset,
+        &[0u8],
+        100_000,
+        (2, 3),
+    )?);
+    tx.output.push(Adapter::new(bitcoin::TxOut {
+        value: 90_000,
+        script_pubkey: sigset.output_script(&ctx, &[0u8], (2, 3))?,
+    }));
+    tx.populate_input_sig_message(0)?;
+
+    let input = &mut tx.input[0];
+    assert!(input.signatures.schnorr);
+    let msg = Message::from_slice(&input.signatures.message()).unwrap();
+    let sign = |keypair: &KeyPair| {
+        Signature(
+            secp.sign_schnorr_no_aux_rand(&msg, keypair)
+                .as_ref()
+                .to_vec(),
+        )
+    };
+
+    assert!(input
+        .signatures
+        .sign(
+            &deps.api,
+            &ctx,
+            keypairs[1].public_key().into(),
+            &sign(&keypairs[0])
+        )
+        .is_err());
+    for keypair in &keypairs {
+        input
+            .signatures
+            .sign(&deps.api, &ctx, keypair.public_key().into(), &sign(keypair))?;
+    }
+    assert!(input.signatures.signed());
+
+    let txin = input.to_txin()?;
+    let witness = txin.witness.to_vec();
+    assert_eq!(witness.len(), 5);
+    assert!(witness[..3].iter().all(|sig| sig.len() == 64));
+    assert_eq!(witness[3], input.redeem_script.to_bytes());
+    assert_eq!(
+        Some(&witness[4]),
+        sigset.control_block(&ctx, &[0u8], (2, 3))?.as_ref()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn musig_key_aggregation() {
+    let ctx = ECMultContext::new_boxed();
+    // Test vectors from BIP327
+    let pk = |hex: &str| Pubkey::try_from_slice(&Vec::from_hex(hex).unwrap()).unwrap();
+    let x1 = pk("02f9308a019258c31049344f85f89d5229b531c845836f99b08601f113bce036f9");
+    let x2 = pk("03dff1d77f2a671c5f36183726db2341be58feae1da2deced843240f7b502ba659");
+    let x3 = pk("023590a94e768f8e1815c2f24b4d80a8e3149316c3518ce7b7ad338368d038ca66");
+
+    assert_eq!(
+        musig::aggregate_key(&ctx, &[x1.clone(), x2, x3])
+            .unwrap()
+            .to_vec(),
+        Vec::from_hex("90539eede565f5d054f32cc0c220126889ed1e5d193baf15aef344fe59d4610c").unwrap()
+    );
+    assert_eq!(
+        musig::aggregate_key(&ctx, &[x1.clone(), x1.clone(), x1])
+            .unwrap()
+            .to_vec(),
+        Vec::from_hex("b436e3bad62b8cd409969a224731c193d051162d8c5ae8b109306127da3aa935").unwrap()
+    );
+    assert!(musig::aggregate_key(&ctx, &[]).is_err());
+}
+
+#[test]
+fn musig_input_signing() -> ContractResult<()> {
+    let secp = Secp256k1::new();
+    let ctx = ECMultContext::new_boxed();
+    let secret_keys: Vec<_> = (1..=3u8)
+        .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+        .collect();
+    let mut sigset = SignatorySet {
+        present_vp: 300,
+        possible_vp: 300,
+        signatories: secret_keys
+            .iter()
+            .map(|secret_key| Signatory {
+                voting_power: 100,
+                pubkey: PublicKey::from_secret_key(&secp, secret_key).into(),
+            })
+            .collect(),
+        script_type: ScriptType::Musig2,
+        ..Default::default()
+    };
+    sigset.signatories.sort_by(|a, b| b.cmp(a));
+    let pubkeys: Vec<Pubkey> = sigset.iter().map(|s| s.pubkey.clone()).collect();
+    let secret_key = |pubkey: &Pubkey| {
+        secret_keys
+            .iter()
+            .find(|secret_key| {
+                Pubkey::from(PublicKey::from_secret_key(&secp, secret_key)) == *pubkey
+            })
+            .unwrap()
+    };
+    let scalar = |secret_key: &SecretKey| {
+        let mut scalar = Scalar::default();
+        let _ = scalar.set_b32(&secret_key.secret_bytes());
+        scalar
+    };
+
+    let mut tx = BitcoinTx::default();
+    tx.input.push(Input::new(
+        &ctx,
+        OutPoint::default(),
+        &sigset,
+        &[0u8],
+        100_000,
+        (2, 3),
+    )?);
+    tx.output.push(Adapter::new(bitcoin::TxOut {
+        value: 90_000,
+        script_pubkey: sigset.output_script(&ctx, &[0u8], (2, 3))?,
+    }));
+    tx.populate_input_sig_message(0)?;
+
+    let unsigned_tx = tx.to_bitcoin_tx()?;
+    let sighash = SighashCache::new(&unsigned_tx).taproot_key_spend_signature_hash(
+        0,
+        &Prevouts::All(&[tx.input[0].prevout_txout()]),
+        SchnorrSighashType::Default,
+    )?;
+    let input = &mut tx.input[0];
+    assert_eq!(input.signatures.message(), sighash.into_inner());
+    assert_eq!(input.est_witness_vsize, 17);
+    assert!(!input.signatures.needs_sig(pubkeys[0].clone()));
+
+    // Round 1: nonces
+    let secret_nonces: Vec<_> = (0..3u8)
+        .map(|i| {
+            (
+                SecretKey::from_slice(&[10 + i; 32]).unwrap(),
+                SecretKey::from_slice(&[20 + i; 32]).unwrap(),
+            )
+        })
+        .collect();
+    for (pubkey, (k1, k2)) in pubkeys.iter().zip(&secret_nonces) {
+        assert!(!input.signatures.needs_partial_sig(pubkey.clone()));
+        let mut nonce = PublicKey::from_secret_key(&secp, k1).serialize().to_vec();
+        nonce.extend_from_slice(&PublicKey::from_secret_key(&secp, k2).serialize());
+        input
+            .signatures
+            .add_nonce(pubkey.clone(), &Binary::from(nonce.clone()))?;
+        assert!(input
+            .signatures
+            .add_nonce(pubkey.clone(), &Binary::from(nonce))
+            .is_err());
+    }
+
+    // Round 2: partial signatures
+    let session = input.signatures.musig.clone().unwrap();
+    let message = input.signatures.message();
+    for (i, pubkey) in pubkeys.iter().enumerate() {
+        let (k1, k2) = &secret_nonces[i];
+        let wrong_partial_sig = musig::partial_sign(
+            &ctx,
+            &pubkeys,
+            &session,
+            &message,
+            i,
+            &scalar(secret_key(&pubkeys[(i + 1) % 3])),
+            (scalar(k1), scalar(k2)),
+        )?;
+        assert!(input
+            .signatures
+            .add_partial_sig(
+                &ctx,
+                pubkey.clone(),
+                &Binary::from(wrong_partial_sig.to_vec())
+            )
+            .is_err());
+
+        assert!(!input.signatures.signed());
+        let partial_sig = musig::partial_sign(
+            &ctx,
+            &pubkeys,
+            &session,
+            &message,
+            i,
+            &scalar(secret_key(pubkey)),
+            (scalar(k1), scalar(k2)),
+        )?;
+        input.signatures.add_partial_sig(
+            &ctx,
+            pubkey.clone(),
+            &Binary::from(partial_sig.to_vec()),
+        )?;
+    }
+    assert!(input.signatures.signed());
+
+    assert_eq!(input.est_witness_vsize, witness_vsize(input)?);
+    let witness = input.to_txin()?.witness.to_vec();
+    assert_eq!(witness.len(), 1);
+    let output_key = XOnlyPublicKey::from_slice(&input.script_pubkey.as_bytes()[2..]).unwrap();
+    let sig = schnorr::Signature::from_slice(&witness[0]).unwrap();
+    secp.verify_schnorr(&sig, &Message::from_slice(&message).unwrap(), &output_key)
+        .unwrap();
+
+    // Recovery spends use the multisig leaf instead.
+    let input = Input::new(&ctx, OutPoint::default(), &sigset, &[0u8], 100_000, (2, 3))?
+        .spend_script_path(&sigset);
+    assert!(input.signatures.musig.is_none());
+    assert!(input.signatures.needs_sig(pubkeys[0].clone()));
+    assert_eq!(
+        input.est_witness_vsize,
+        sigset.est_script_path_witness_vsize()
+    );
+    assert!(input.control_block.is_some());
+
+    Ok(())
+}
+
+#[test]
+fn backup_branch() -> ContractResult<()> {
+    let ctx = ECMultContext::new_boxed();
+    let mut sigset = mock_signatory_set();
+    let backup_keys: Vec<Pubkey> = mock_signatory_set()
+        .iter()
+        .map(|s| s.pubkey.clone())
+        .collect();
+    let without_backup = sigset.redeem_script(&[0u8], (2, 3))?;
+    let vsize_without_backup = sigset.est_witness_vsize();
+
+    sigset.backup = Some(BackupConfig {
+        keys: backup_keys,
+        threshold: 2,
+        csv_delay: 4320,
+    });
+    let script = sigset.redeem_script(&[0u8], (2, 3))?;
+    assert_eq!(
+        script.as_bytes()[0],
+        bitcoin::blockdata::opcodes::all::OP_IF.to_u8()
+    );
+    assert!(script.len() > without_backup.len());
+    assert!(sigset.est_witness_vsize() > vsize_without_backup);
+
+    let (parsed, commitment) = SignatorySet::from_script(&script, (2, 3), ScriptType::P2wsh)?;
+    assert_eq!(parsed.signatories, sigset.signatories);
+    assert_eq!(parsed.backup, sigset.backup);
+    assert_eq!(commitment, vec![0]);
+
+    // Tapscripts round-trip to the same output script, with the x-only keys
+    // read with an even y coordinate.
+    let mut taproot = sigset.clone();
+    taproot.script_type = ScriptType::P2tr;
+    let script = taproot.redeem_script(&[0u8], (2, 3))?;
+    let (parsed, commitment) = SignatorySet::from_script(&script, (2, 3), ScriptType::P2tr)?;
+    assert_eq!(parsed.script_type, ScriptType::P2tr);
+    assert_eq!(
+        parsed.output_script(&ctx, &commitment, (2, 3))?,
+        taproot.output_script(&ctx, &[0u8], (2, 3))?
+    );
+    let xonly = |sigset: &SignatorySet| -> Vec<Vec<u8>> {
+        sigset
+            .iter()
+            .map(|signatory| signatory.pubkey.as_slice()[1..].to_vec())
+            .collect()
+    };
+    assert_eq!(xonly(&parsed), xonly(&taproot));
+    assert_eq!(
+        parsed.backup.as_ref().map(|backup| backup.keys.len()),
+        Some(3)
+    );
+    assert!(SignatorySet::from_script(&script, (2, 3), ScriptType::Musig2).is_err());
+
+    // The signatory branch is selected in the witness.
+    let mut input = Input::new(&ctx, OutPoint::default(), &sigset, &[0u8], 100_000, (2, 3))?;
+    input.signatures.signed = input.signatures.threshold + 1;
+    let witness = input.to_txin()?.witness.to_vec();
+    assert_eq!(witness[witness.len() - 2], vec![1]);
+    assert_eq!(witness[witness.len() - 1], script.to_bytes());
+
+    let mut invalid = sigset.backup.clone().unwrap();
+    invalid.threshold = 4;
+    assert!(invalid.validate().is_err());
+    invalid.threshold = 2;
+    invalid.csv_delay = 0x10000;
+    assert!(invalid.validate().is_err());
+
+    Ok(())
+}
+
+#[test]
+fn est_witness_vsize() -> ContractResult<()> {
+    let secp = Secp256k1::new();
+    let ctx = ECMultContext::new_boxed();
+    let deps = mock_dependencies();
+    let secret_keys: Vec<_> = (1..=3u8)
+        .map(|i| SecretKey::from_slice(&[i; 32]).unwrap())
+        .collect();
+    let pubkeys: Vec<Pubkey> = secret_keys
+        .iter()
+        .map(|secret_key| PublicKey::from_secret_key(&secp, secret_key).into())
+        .collect();
+    let backup = BackupConfig {
+        keys: pubkeys.clone(),
+        threshold: 2,
+        csv_delay: 4320,
+    };
+
+    for script_type in [ScriptType::P2wsh, ScriptType::P2tr, ScriptType::Musig2] {
+        for backup in [None, Some(backup.clone())] {
+            let mut sigset = SignatorySet {
+                present_vp: 300,
+                possible_vp: 300,
+                signatories: pubkeys
+                    .iter()
+                    .map(|pubkey| Signatory {
+                        voting_power: 100,
+                        pubkey: pubkey.clone(),
+                    })
+                    .collect(),
+                script_type,
+                backup,
+                ..Default::default()
+            };
+            sigset.signatories.sort_by(|a, b| b.cmp(a));
+
+            // MuSig2 key-path spends are covered by `musig_input_signing`, sign
+            // through the multisig leaf here.
+            let mut tx = BitcoinTx::default();
+            tx.input.push(
+                Input::new(&ctx, OutPoint::default(), &sigset, &[0u8], 100_000, (2, 3))?
+                    .spend_script_path(&sigset),
+            );
+            tx.output.push(Adapter::new(bitcoin::TxOut {
+                value: 90_000,
+                script_pubkey: sigset.output_script(&ctx, &[0u8], (2, 3))?,
+            }));
+            tx.populate_input_sig_message(0)?;
+
+            let input = &mut tx.input[0];
+            let msg = Message::from_slice(&input.signatures.message()).unwrap();
+            for (secret_key, pubkey) in secret_keys.iter().zip(&pubkeys) {
+                let sig = if input.signatures.schnorr {
+                    let keypair = KeyPair::from_secret_key(&secp, secret_key);
+                    secp.sign_schnorr_no_aux_rand(&msg, &keypair)
+                        .as_ref()
+                        .to_vec()
+                } else {
+                    secp.sign_ecdsa(&msg, secret_key)
+                        .serialize_compact()
+                        .to_vec()
+                };
+                input
+                    .signatures
+                    .sign(&deps.api, &ctx, pubkey.clone(), &Signature(sig))?;
+            }
+
+            // The estimate assumes the largest signatures, voting powers and
+            // commitment, so it can only be slightly larger.
+            let vsize = witness_vsize(input)?;
+            assert!(input.est_witness_vsize >= vsize);
+            assert!(input.est_witness_vsize <= vsize + 25);
+        }
+    }
+
+    Ok(())
+}
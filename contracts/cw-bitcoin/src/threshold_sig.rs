@@ -1,6 +1,7 @@
 use super::constants::SIGSET_THRESHOLD;
-use super::signatory::SignatorySet;
+use super::signatory::{ScriptType, SignatorySet};
 use crate::error::{ContractError, ContractResult};
+use crate::musig::{self, MusigSession};
 use bitcoin::blockdata::transaction::EcdsaSighashType;
 use bitcoin::secp256k1::{
     self,
@@ -10,14 +11,15 @@ use bitcoin::secp256k1::{
 use cosmwasm_schema::cw_serde;
 use cosmwasm_schema::schemars::JsonSchema;
 use cosmwasm_schema::serde::{Deserialize, Serialize};
-use cosmwasm_std::Api;
-
-// TODO: update for taproot-based design (musig rounds, fallback path)
+use cosmwasm_std::{Api, Binary};
+use libsecp256k1_core::curve::{Affine, ECMultContext, Field, Jacobian, Scalar};
+use sha2::{Digest, Sha256};
 
 /// A sighash to be signed by a set of signers.
 pub type Message = [u8; MESSAGE_SIZE];
 
-/// A compact secp256k1 ECDSA signature.
+/// A compact secp256k1 ECDSA signature, or a BIP340 Schnorr signature when
+/// signing taproot inputs.
 #[cw_serde]
 pub struct Signature(#[serde(serialize_with = "<[_]>::serialize")] pub Vec<u8>);
 
@@ -111,6 +113,18 @@ pub struct ThresholdSig {
     /// A map of entries containing the pubkey and voting power of each signer,
     /// and the signature if they have signed.
     pub sigs: Vec<(Pubkey, Share)>,
+
+    /// Whether signers submit BIP340 Schnorr signatures (for taproot
+    /// script-path inputs) rather than ECDSA signatures.
+    #[serde(default)]
+    pub schnorr: bool,
+
+    /// The MuSig2 signing session, for inputs spent through the key path of an
+    /// aggregated-key output. When set, signers submit nonces and partial
+    /// signatures rather than individual signatures, and all signers are
+    /// required to produce the final signature.
+    #[serde(default)]
+    pub musig: Option<MusigSession>,
 }
 
 impl ThresholdSig {
@@ -131,12 +145,16 @@ impl ThresholdSig {
         self.message = message;
     }
 
-    /// Clears all signatures from the state.
+    /// Clears all signatures from the state. For MuSig2 sessions, nonces are
+    /// also cleared since they must never be used to sign twice.
     pub fn clear_sigs(&mut self) {
         self.signed = 0;
         for (_, sig) in &mut self.sigs {
             sig.sig = None;
         }
+        if let Some(session) = &mut self.musig {
+            *session = MusigSession::new(session.tweak(), session.nonces.len());
+        }
     }
 
     /// Returns the message to be signed.
@@ -163,6 +181,10 @@ impl ThresholdSig {
             total_vp += signatory.voting_power;
         }
 
+        ts.schnorr = matches!(
+            signatories.script_type,
+            ScriptType::P2tr | ScriptType::Musig2
+        );
         ts.threshold =
             ((total_vp as u128) * SIGSET_THRESHOLD.0 as u128 / SIGSET_THRESHOLD.1 as u128) as u64;
 
@@ -195,9 +217,13 @@ impl ThresholdSig {
     }
 
     /// Returns `true` if the more than the threshold of voting power has signed
-    /// the message.    
+    /// the message, or if the aggregated signature is complete for MuSig2
+    /// sessions.
     pub fn signed(&self) -> bool {
-        self.signed > self.threshold
+        match &self.musig {
+            Some(session) => session.sig.is_some(),
+            None => self.signed > self.threshold,
+        }
     }
 
     /// Returns a vector of `(pubkey, signature)` tuples for each signer who has
@@ -224,8 +250,12 @@ impl ThresholdSig {
 
     /// Returns `true` if the given pubkey is part of the set of signers and has
     /// not yet signed. Returns `false` if the pubkey is not part of the set of
-    /// signers or has already signed.
+    /// signers or has already signed, or if this is a MuSig2 session.
     pub fn needs_sig(&self, pubkey: Pubkey) -> bool {
+        if self.musig.is_some() {
+            return false;
+        }
+
         self.sigs
             .iter()
             .find(|(key, _)| pubkey.eq(key))
@@ -233,12 +263,98 @@ impl ThresholdSig {
             .unwrap_or(false)
     }
 
+    /// The position of the given pubkey in the set of signers, which is also
+    /// its position in the MuSig2 key aggregation.
+    fn signer_index(&self, pubkey: &Pubkey) -> Option<usize> {
+        self.sigs.iter().position(|(key, _)| pubkey.eq(key))
+    }
+
+    /// The pubkeys of the signers, in order.
+    fn pubkeys(&self) -> Vec<Pubkey> {
+        self.sigs.iter().map(|(key, _)| key.clone()).collect()
+    }
+
+    /// Returns `true` if this is a MuSig2 session, and the given pubkey is part
+    /// of the set of signers and has not yet submitted a public nonce.
+    pub fn needs_nonce(&self, pubkey: Pubkey) -> bool {
+        match (&self.musig, self.signer_index(&pubkey)) {
+            (Some(session), Some(i)) => session.nonces[i].is_none(),
+            _ => false,
+        }
+    }
+
+    /// Adds the given public nonce to the MuSig2 session for the given signer.
+    ///
+    /// Nonces can not be verified, so callers must ensure the nonce was
+    /// submitted by the owner of the pubkey.
+    pub fn add_nonce(&mut self, pubkey: Pubkey, nonce: &Binary) -> ContractResult<()> {
+        if !self.needs_nonce(pubkey.clone()) {
+            return Err(ContractError::App("Pubkey does not need a nonce".into()));
+        }
+        musig::validate_nonce(nonce)?;
+
+        let i = self.signer_index(&pubkey).unwrap();
+        self.musig.as_mut().unwrap().nonces[i] = Some(nonce.clone());
+
+        Ok(())
+    }
+
+    /// Returns `true` if this is a MuSig2 session which has received all public
+    /// nonces, and the given pubkey is part of the set of signers and has not
+    /// yet submitted a partial signature.
+    pub fn needs_partial_sig(&self, pubkey: Pubkey) -> bool {
+        match (&self.musig, self.signer_index(&pubkey)) {
+            (Some(session), Some(i)) => {
+                session.has_all_nonces() && session.partial_sigs[i].is_none()
+            }
+            _ => false,
+        }
+    }
+
+    /// Verifies and adds the given partial signature to the MuSig2 session for
+    /// the given signer. Once all partial signatures are added, they are
+    /// aggregated into the final signature.
+    pub fn add_partial_sig(
+        &mut self,
+        ctx: &ECMultContext,
+        pubkey: Pubkey,
+        partial_sig: &Binary,
+    ) -> ContractResult<()> {
+        if !self.needs_partial_sig(pubkey.clone()) {
+            return Err(ContractError::App(
+                "Pubkey does not need a partial signature".into(),
+            ));
+        }
+
+        let i = self.signer_index(&pubkey).unwrap();
+        let pubkeys = self.pubkeys();
+        let message = self.message;
+        let power = self.sigs[i].1.power;
+        let session = self.musig.as_mut().unwrap();
+        musig::verify_partial_sig(ctx, &pubkeys, session, &message, i, partial_sig)?;
+        session.partial_sigs[i] = Some(partial_sig.clone());
+        self.signed += power;
+
+        if session.partial_sigs.iter().all(Option::is_some) {
+            let sig = musig::aggregate_partial_sigs(ctx, &pubkeys, session, &message)?;
+            session.sig = Some(Binary::from(sig));
+        }
+
+        Ok(())
+    }
+
     /// Verifies and adds the given signature to the state for the given signer.
     ///
     /// Returns an error if the pubkey is not part of the set of signers, if the
     /// signature is invalid, or if the signer has already signed.
     // TODO: exempt from fee
-    pub fn sign(&mut self, api: &dyn Api, pubkey: Pubkey, sig: &Signature) -> ContractResult<()> {
+    pub fn sign(
+        &mut self,
+        api: &dyn Api,
+        ctx: &ECMultContext,
+        pubkey: Pubkey,
+        sig: &Signature,
+    ) -> ContractResult<()> {
         let share = &mut self
             .sigs
             .iter_mut()
@@ -250,7 +366,11 @@ impl ThresholdSig {
             return Err(ContractError::App("Pubkey already signed".into()))?;
         }
 
-        Self::secp_verify(api, self.message.as_slice(), &pubkey, sig)?;
+        if self.schnorr {
+            schnorr_verify(ctx, self.message.as_slice(), &pubkey, sig)?;
+        } else {
+            Self::secp_verify(api, self.message.as_slice(), &pubkey, sig)?;
+        }
 
         share.sig = Some(sig.clone());
         self.signed += share.power;
@@ -277,7 +397,17 @@ impl ThresholdSig {
         Ok(())
     }
 
-    pub fn verify(&self, api: &dyn Api, pubkey: &Pubkey, sig: &Signature) -> ContractResult<()> {
+    pub fn verify(
+        &self,
+        api: &dyn Api,
+        ctx: &ECMultContext,
+        pubkey: &Pubkey,
+        sig: &Signature,
+    ) -> ContractResult<()> {
+        if self.schnorr {
+            return schnorr_verify(ctx, self.message.as_slice(), pubkey, sig);
+        }
+
         Self::secp_verify(api, self.message.as_slice(), pubkey, sig)
     }
 
@@ -294,6 +424,11 @@ impl ThresholdSig {
             return Ok(vec![]);
         }
 
+        // Key-path spends only contain the aggregated signature.
+        if let Some(session) = &self.musig {
+            return Ok(session.sig.iter().map(|sig| sig.to_vec()).collect());
+        }
+
         let mut entries: Vec<_> = self.sigs.clone();
         // Sort ascending by voting power, opposite order of public keys in the
         // script
@@ -309,6 +444,11 @@ impl ThresholdSig {
             .into_iter()
             .map(|(_, share)| {
                 share.sig.map_or(Ok(vec![]), |sig| {
+                    // Schnorr signatures use the default sighash type, which
+                    // is implied by omitting the sighash byte.
+                    if self.schnorr {
+                        return Ok(sig.0);
+                    }
                     let sig = ecdsa::Signature::from_compact(&sig.0)?;
                     let mut v = sig.serialize_der().to_vec();
                     v.push(EcdsaSighashType::All.to_u32() as u8);
@@ -319,6 +459,83 @@ impl ThresholdSig {
     }
 }
 
+/// Computes a BIP340 tagged hash of the concatenation of the given data.
+pub fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    for bytes in data {
+        hasher.update(bytes);
+    }
+    hasher.finalize().into()
+}
+
+/// Parses an x-only public key as the curve point with an even y coordinate,
+/// as done by `lift_x` in BIP340.
+pub fn lift_x(x: &[u8; 32]) -> ContractResult<Affine> {
+    let mut field = Field::default();
+    let mut point = Affine::default();
+    if !field.set_b32(x) || !point.set_xo_var(&field, false) {
+        return Err(ContractError::App("Invalid x-only public key".to_string()));
+    }
+    Ok(point)
+}
+
+/// Verifies a BIP340 Schnorr signature for the message, using the x-only part
+/// of the given signer's pubkey.
+pub fn schnorr_verify(
+    ctx: &ECMultContext,
+    msg: &[u8],
+    pubkey: &Pubkey,
+    sig: &Signature,
+) -> ContractResult<()> {
+    let px = arrayref::array_ref!(pubkey.as_slice(), 1, 32);
+    schnorr_verify_xonly(ctx, msg, px, &sig.0)
+}
+
+/// Verifies a BIP340 Schnorr signature for the message, using the given
+/// x-only public key.
+pub fn schnorr_verify_xonly(
+    ctx: &ECMultContext,
+    msg: &[u8],
+    px: &[u8; 32],
+    sig: &[u8],
+) -> ContractResult<()> {
+    let invalid = || ContractError::App("Can not verify signature".to_string());
+    if sig.len() != 64 {
+        return Err(invalid());
+    }
+
+    let point = lift_x(px)?;
+
+    let mut s = Scalar::default();
+    if bool::from(s.set_b32(arrayref::array_ref!(sig, 32, 32))) {
+        return Err(invalid());
+    }
+    // The challenge is reduced modulo the curve order, so overflow is allowed.
+    let mut e = Scalar::default();
+    let _ = e.set_b32(&tagged_hash("BIP0340/challenge", &[&sig[..32], px, msg]));
+
+    // R = s⋅G - e⋅P must have an even y coordinate and an x coordinate of r.
+    let mut rj = Jacobian::default();
+    ctx.ecmult(&mut rj, &Jacobian::from_ge(&point), &-e, &s);
+    if rj.is_infinity() {
+        return Err(invalid());
+    }
+    let mut big_r = Affine::default();
+    big_r.set_gej(&rj);
+    big_r.x.normalize_var();
+    big_r.y.normalize_var();
+    let mut rx = [0; 32];
+    big_r.x.fill_b32(&mut rx);
+    if big_r.y.is_odd() || rx[..] != sig[..32] {
+        return Err(invalid());
+    }
+
+    Ok(())
+}
+
 use std::fmt::Debug;
 impl Debug for ThresholdSig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -328,6 +545,8 @@ impl Debug for ThresholdSig {
             .field("message", &self.message)
             .field("len", &self.len)
             .field("sigs", &"TODO")
+            .field("schnorr", &self.schnorr)
+            .field("musig", &self.musig)
             .finish()
     }
 }
@@ -1,5 +1,7 @@
 use crate::{
-    adapter::Adapter, error::ContractResult, MAX_LENGTH, MAX_TARGET, MAX_TIME_INCREASE,
+    adapter::Adapter,
+    error::{ContractError, ContractResult},
+    MAX_LENGTH, MAX_TARGET, MAX_TARGET_REGTEST, MAX_TARGET_SIGNET, MAX_TIME_INCREASE,
     RETARGET_INTERVAL, TARGET_SPACING, TARGET_TIMESPAN,
 };
 use bitcoin::{util::uint::Uint256, BlockHash, BlockHeader, TxMerkleNode};
@@ -7,8 +9,15 @@ use serde::{Deserialize, Serialize};
 use tsify::Tsify;
 use wasm_bindgen::prelude::*;
 
+/// The timestamp and bits of the first header of a retargeting period.
+#[derive(Clone, Debug, Serialize, Deserialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct RetargetAnchor {
+    pub time: u32,
+    pub bits: u32,
+}
+
 ///  HeaderConfiguration parameters for Bitcoin header processing.
-// TODO: implement trait that returns constants for bitcoin::Network variants
 #[derive(Clone, Debug, Serialize, Deserialize, Tsify)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
 pub struct HeaderConfig {
@@ -38,9 +47,26 @@ pub struct HeaderConfig {
     /// difficulty after a certain amount of time has passed (used in Bitcoin
     /// testnet).
     pub min_difficulty_blocks: bool,
+    /// Whether or not to enforce the BIP94 rules (used in Bitcoin testnet4).
+    #[serde(default)]
+    pub enforce_bip94: bool,
+    /// The first header of the retargeting period containing the trusted
+    /// header, if the trusted header is not the first header of its period.
+    #[serde(default)]
+    pub retarget_anchor: Option<RetargetAnchor>,
+    /// The maximum number of headers kept below the front of the header queue.
+    #[serde(default)]
+    pub max_history_length: u64,
+    /// Known blocks as `(height, hash)` pairs, in ascending order of height.
+    #[serde(default)]
+    #[tsify(type = "[number, string][]")]
+    pub checkpoints: Vec<(u32, BlockHash)>,
     /// The trusted header (the header which populates the queue when it is
     /// newly created), as encoded bytes.    
     pub trusted_header: Adapter<BlockHeader>,
+    /// The Bitcoin network the headers belong to.
+    #[tsify(type = "string")]
+    pub network: bitcoin::Network,
 }
 
 /// A `WrappedHeader`, along with a total estimated amount of work (measured in
@@ -188,12 +214,19 @@ pub fn newWrappedHeader(header: BlockHeader, height: u32) -> WrappedHeader {
     WrappedHeader::new(header.into(), height)
 }
 
+/// Creates the header config of the given network, which is one of
+/// `bitcoin`, `testnet` (or `testnet3`), `testnet4`, `signet` or `regtest`,
+/// matching the header presets of the contract.
 #[wasm_bindgen]
-pub fn newHeaderConfig(height: u32, block_header: JsValue) -> ContractResult<HeaderConfig> {
+pub fn newHeaderConfig(
+    height: u32,
+    block_header: JsValue,
+    network: String,
+) -> ContractResult<HeaderConfig> {
     // because BlockHeader is not tsify
     let header: BlockHeader = serde_wasm_bindgen::from_value(block_header)?;
 
-    Ok(HeaderConfig {
+    let config = HeaderConfig {
         max_length: MAX_LENGTH,
         max_time_increase: MAX_TIME_INCREASE,
         trusted_height: height,
@@ -204,6 +237,39 @@ pub fn newHeaderConfig(height: u32, block_header: JsValue) -> ContractResult<Hea
         trusted_header: header.into(),
         retargeting: true,
         min_difficulty_blocks: false,
+        enforce_bip94: false,
+        retarget_anchor: None,
+        max_history_length: 0,
+        checkpoints: vec![],
+        network: bitcoin::Network::Bitcoin,
+    };
+
+    Ok(match network.as_str() {
+        "bitcoin" => config,
+        "testnet" | "testnet3" => HeaderConfig {
+            min_difficulty_blocks: true,
+            network: bitcoin::Network::Testnet,
+            ..config
+        },
+        "testnet4" => HeaderConfig {
+            min_difficulty_blocks: true,
+            enforce_bip94: true,
+            network: bitcoin::Network::Testnet,
+            ..config
+        },
+        "signet" => HeaderConfig {
+            max_target: MAX_TARGET_SIGNET,
+            network: bitcoin::Network::Signet,
+            ..config
+        },
+        "regtest" => HeaderConfig {
+            max_target: MAX_TARGET_REGTEST,
+            retargeting: false,
+            min_difficulty_blocks: true,
+            network: bitcoin::Network::Regtest,
+            ..config
+        },
+        _ => return Err(ContractError::App(format!("Unknown network {}", network))),
     })
 }
 
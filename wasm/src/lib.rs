@@ -16,12 +16,11 @@ mod utils;
 
 use wasm_bindgen::prelude::*;
 
-pub const NETWORK: ::bitcoin::Network = ::bitcoin::Network::Bitcoin;
-
 pub const BRIDGE_FEE_RATE: f64 = 0.0;
 pub const SIGSET_THRESHOLD: (u64, u64) = (2, 3);
 pub const HEADER_BATCH_SIZE: usize = 250;
 pub const MAX_SIGNATORIES: u64 = 20;
+pub const MAX_AGGREGATED_SIGNATORIES: u64 = 100;
 
 pub const MAX_LENGTH: u64 = 24_192; // ~6 months
 pub const MAX_RELAY: u64 = 1000;
@@ -30,6 +29,8 @@ pub const RETARGET_INTERVAL: u32 = 2016;
 pub const TARGET_SPACING: u32 = 10 * 60;
 pub const TARGET_TIMESPAN: u32 = RETARGET_INTERVAL * TARGET_SPACING;
 pub const MAX_TARGET: u32 = 0x1d00ffff;
+pub const MAX_TARGET_SIGNET: u32 = 0x1e0377ae;
+pub const MAX_TARGET_REGTEST: u32 = 0x207fffff;
 
 #[wasm_bindgen]
 pub fn getGlobalBridgeFeeRate() -> f64 {
@@ -3,28 +3,55 @@ use std::cmp::Ordering;
 use crate::{
     error::{ContractError, ContractResult},
     threshold_sig::Pubkey,
-    MAX_SIGNATORIES,
+    MAX_AGGREGATED_SIGNATORIES, MAX_SIGNATORIES,
 };
 use bitcoin::{
     blockdata::{
         opcodes::{
             self,
             all::{
-                OP_ADD, OP_CHECKSIG, OP_DROP, OP_ELSE, OP_ENDIF, OP_GREATERTHAN, OP_IF, OP_SWAP,
+                OP_ADD, OP_CHECKMULTISIG, OP_CHECKSIG, OP_CSV, OP_DROP, OP_ELSE, OP_ENDIF,
+                OP_GREATERTHAN, OP_IF, OP_SWAP,
             },
             OP_FALSE,
         },
         script::{read_scriptint, Instruction},
     },
     hashes::hex::FromHex,
+    secp256k1::{PublicKey, Scalar, Secp256k1, XOnlyPublicKey},
+    util::taproot::TaprootBuilder,
     Script,
 };
 use bitcoin_script::bitcoin_script as script;
 use derive_more::Deref;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tsify::Tsify;
 use wasm_bindgen::prelude::*;
 
+/// The x-only "nothing up my sleeve" point from BIP341, used as the internal
+/// key of taproot outputs so they can only be spent through the script path.
+const NUMS_KEY: &str = "50929b74c1a04954b78b4b6035e97a5e078a5a0f28ec96d547bfee9ace803ac0";
+
+/// The type of output which secures funds held by a signatory set.
+#[derive(
+    Clone, Copy, Debug, Default, PartialOrd, PartialEq, Eq, Ord, Deserialize, Serialize, Tsify,
+)]
+#[serde(rename_all = "snake_case")]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub enum ScriptType {
+    /// A pay-to-witness-script-hash output.
+    #[default]
+    P2wsh,
+
+    /// A pay-to-taproot output with the multisig as its only leaf.
+    P2tr,
+
+    /// A pay-to-taproot output with the MuSig2 aggregate of the signatory keys
+    /// as its internal key and the multisig as its only leaf.
+    Musig2,
+}
+
 /// A signatory in a signatory set, consisting of a public key and voting power.
 #[derive(Clone, Debug, PartialOrd, PartialEq, Eq, Ord, Deserialize, Serialize, Tsify)]
 #[tsify(into_wasm_abi, from_wasm_abi)]
@@ -33,6 +60,21 @@ pub struct Signatory {
     pub pubkey: Pubkey,
 }
 
+/// A backup multisig which can spend the outputs of a signatory set once they
+/// have been confirmed for a given number of blocks.
+#[derive(Clone, Debug, PartialOrd, PartialEq, Eq, Ord, Deserialize, Serialize, Tsify)]
+#[tsify(into_wasm_abi, from_wasm_abi)]
+pub struct BackupConfig {
+    /// The public keys of the backup multisig.
+    pub keys: Vec<Pubkey>,
+
+    /// The number of backup keys required to spend.
+    pub threshold: u32,
+
+    /// The relative timelock of the backup branch, in blocks.
+    pub csv_delay: u32,
+}
+
 #[derive(
     Clone, Debug, Default, PartialOrd, Deref, PartialEq, Eq, Ord, Deserialize, Serialize, Tsify,
 )]
@@ -60,6 +102,16 @@ pub struct SignatorySet {
     /// The signatories in this set, sorted by voting power.
     #[deref]
     pub signatories: Vec<Signatory>,
+
+    /// The type of output used for deposits to and reserves held by this
+    /// signatory set.
+    #[serde(default)]
+    pub script_type: ScriptType,
+
+    /// The backup multisig which can spend outputs of this signatory set after
+    /// a timelock, if any.
+    #[serde(default)]
+    pub backup: Option<BackupConfig>,
 }
 
 type IterItem<'a> = std::result::Result<Instruction<'a>, bitcoin::blockdata::script::Error>;
@@ -100,8 +152,15 @@ impl SignatorySet {
         }
 
         fn take_number<'a>(ins: &mut impl Iterator<Item = IterItem<'a>>) -> ContractResult<i64> {
-            let bytes = take_bytes(ins)?;
-            read_scriptint(bytes).map_err(|_| ContractError::App("Failed to read scriptint".into()))
+            match take_instruction(ins)? {
+                Instruction::PushBytes(bytes) => read_scriptint(bytes)
+                    .map_err(|_| ContractError::App("Failed to read scriptint".into())),
+                // Small numbers are pushed with `OP_PUSHNUM_N`.
+                Instruction::Op(op) => match op.classify(opcodes::ClassifyContext::Legacy) {
+                    opcodes::Class::PushNum(n) => Ok(n as i64),
+                    _ => Err(ContractError::App("Expected number".into())),
+                },
+            }
         }
 
         fn take_op<'a>(
@@ -163,6 +222,47 @@ impl SignatorySet {
             Ok(threshold as u64)
         }
 
+        fn take_backup<'a>(
+            ins: &mut impl Iterator<Item = IterItem<'a>>,
+        ) -> ContractResult<BackupConfig> {
+            take_op(ins, OP_ELSE)?;
+            let csv_delay = take_number(ins)?;
+            take_op(ins, OP_CSV)?;
+            take_op(ins, OP_DROP)?;
+            let threshold = take_number(ins)?;
+
+            // Keys are followed by the number of keys.
+            let mut keys = vec![];
+            let num_keys = loop {
+                match take_instruction(ins)? {
+                    Instruction::PushBytes(bytes) if bytes.len() == 33 => {
+                        keys.push(Pubkey::try_from_slice(bytes)?);
+                    }
+                    Instruction::PushBytes(bytes) => {
+                        break read_scriptint(bytes)
+                            .map_err(|_| ContractError::App("Failed to read scriptint".into()))?;
+                    }
+                    Instruction::Op(op) => match op.classify(opcodes::ClassifyContext::Legacy) {
+                        opcodes::Class::PushNum(n) => break n as i64,
+                        _ => return Err(ContractError::App("Expected number".into())),
+                    },
+                }
+            };
+            if num_keys != keys.len() as i64 {
+                return Err(ContractError::App(
+                    "Unexpected number of backup keys".into(),
+                ));
+            }
+            take_op(ins, OP_CHECKMULTISIG)?;
+            take_op(ins, OP_ENDIF)?;
+
+            Ok(BackupConfig {
+                keys,
+                threshold: threshold as u32,
+                csv_delay: csv_delay as u32,
+            })
+        }
+
         fn take_commitment<'a>(
             ins: &mut impl Iterator<Item = IterItem<'a>>,
         ) -> ContractResult<&'a [u8]> {
@@ -172,6 +272,10 @@ impl SignatorySet {
         }
 
         let mut ins = script.instructions().peekable();
+        let has_backup = matches!(ins.peek(), Some(Ok(Instruction::Op(OP_IF))));
+        if has_backup {
+            take_op(&mut ins, OP_IF)?;
+        }
         let mut sigs = vec![take_first_signatory(&mut ins)?];
         loop {
             let next = ins
@@ -188,6 +292,11 @@ impl SignatorySet {
         }
 
         let expected_threshold = take_threshold(&mut ins)?;
+        let backup = if has_backup {
+            Some(take_backup(&mut ins)?)
+        } else {
+            None
+        };
         let commitment = take_commitment(&mut ins)?;
 
         assert!(ins.next().is_none());
@@ -199,6 +308,8 @@ impl SignatorySet {
             possible_vp: total_vp,
             create_time: 0,
             index: 0,
+            script_type: ScriptType::P2wsh,
+            backup,
         };
 
         for _ in 0..100 {
@@ -233,8 +344,12 @@ impl SignatorySet {
     pub fn sort_and_truncate(&mut self) {
         self.signatories.sort_by(|a, b| b.cmp(a));
 
-        if self.signatories.len() as u64 > MAX_SIGNATORIES {
-            for removed in self.signatories.drain(MAX_SIGNATORIES as usize..) {
+        let max_signatories = match self.script_type {
+            ScriptType::Musig2 => MAX_AGGREGATED_SIGNATORIES,
+            _ => MAX_SIGNATORIES,
+        };
+        if self.signatories.len() as u64 > max_signatories {
+            for removed in self.signatories.drain(max_signatories as usize..) {
                 self.present_vp -= removed.voting_power;
             }
         }
@@ -290,9 +405,47 @@ impl SignatorySet {
     ///
     /// This script is essentially a weighted multisig script, where each
     /// signatory has a weight equal to their voting power. It is specified in
-    /// the input witness when the UTXO is spent. The output contains a hash of
-    /// this script, since it is a pay-to-witness-script-hash (P2WSH) output.
+    /// the input witness when the UTXO is spent. For P2WSH outputs, the output
+    /// contains a hash of this script. For P2TR outputs, it is the tapscript of
+    /// the only leaf of the output's script tree.
+    ///
+    /// If the signatory set has a backup multisig, the weighted multisig is
+    /// wrapped in an `OP_IF` branch, with a timelocked `OP_ELSE` branch for the
+    /// backup keys. This must match the script built by the contract.
     pub fn redeem_script(&self, dest: &[u8], threshold: (u64, u64)) -> ContractResult<Script> {
+        let mut bytes = vec![];
+        if self.backup.is_some() {
+            bytes.extend(&script!(OP_IF).into_bytes());
+        }
+
+        let multisig = match self.script_type {
+            ScriptType::P2wsh => self.p2wsh_multisig(threshold)?,
+            ScriptType::P2tr | ScriptType::Musig2 => self.tapscript_multisig(threshold)?,
+        };
+        bytes.extend(&multisig);
+
+        if let Some(backup) = &self.backup {
+            let csv_delay = backup.csv_delay as i64;
+            let script = script! {
+                OP_ELSE
+                    <csv_delay> OP_CSV OP_DROP
+            };
+            bytes.extend(&script.into_bytes());
+            bytes.extend(&self.backup_multisig(backup));
+            bytes.extend(&script!(OP_ENDIF).into_bytes());
+        }
+
+        // Depositor data commitment
+        // Add a commitment of arbitrary data so that deposits can be tied to a
+        // specific destination, then remove it from the stack so that the final
+        // value on the stack is the result of the branch.
+        let script = script!(<dest> OP_DROP);
+        bytes.extend(&script.into_bytes());
+
+        Ok(bytes.into())
+    }
+
+    fn p2wsh_multisig(&self, threshold: (u64, u64)) -> ContractResult<Vec<u8>> {
         // We will truncate voting power values to 23 bits, to reduce the amount
         // of bytes used in the resulting encoded script. In practice, this
         // should be enough precision for effective voting power threshold
@@ -350,21 +503,128 @@ impl SignatorySet {
         };
         bytes.extend(&script.into_bytes());
 
-        // Depositor data commitment
-        // Add a commitment of arbitrary data so that deposits can be tied to a
-        // specific destination, then remove it from the stack so that the final
-        // value on the stack is the threshold check result.
-        let script = script!(<dest> OP_DROP);
+        Ok(bytes)
+    }
+
+    /// Builds the weighted multisig as a tapscript, using x-only pubkeys and
+    /// `OP_CHECKSIGADD`. This must match the script built by the contract.
+    fn tapscript_multisig(&self, threshold: (u64, u64)) -> ContractResult<Vec<u8>> {
+        let truncation = self.get_truncation(23);
+
+        if self.signatories.is_empty() {
+            return Err(ContractError::App(
+                "Cannot create redeem script for empty signatory set".to_string(),
+            ));
+        }
+
+        let mut bytes = script!(0).into_bytes();
+        for signatory in self.iter() {
+            let truncated_voting_power = signatory.voting_power >> truncation;
+            let xonly_pubkey = &signatory.pubkey.as_slice()[1..];
+            let script = script! {
+                OP_SWAP
+                0 <xonly_pubkey> OP_CHECKSIGADD
+                OP_IF
+                    <truncated_voting_power as i64> OP_ADD
+                OP_ENDIF
+            };
+            bytes.extend(&script.into_bytes());
+        }
+
+        let truncated_threshold = self.signature_threshold(threshold) >> truncation;
+        let script = script! {
+            <truncated_threshold as i64> OP_GREATERTHAN
+        };
         bytes.extend(&script.into_bytes());
 
-        Ok(bytes.into())
+        Ok(bytes)
     }
 
-    /// Hashes the weighted multisig redeem script to create a P2WSH output
-    /// script, which is what is used as the script pubkey in deposit outputs
-    /// and reserve outputs.
+    /// Builds the backup multisig, using `OP_CHECKMULTISIG` for P2WSH outputs
+    /// and x-only pubkeys with `OP_CHECKSIGADD` for P2TR outputs.
+    fn backup_multisig(&self, backup: &BackupConfig) -> Vec<u8> {
+        let threshold = backup.threshold as i64;
+        if self.script_type == ScriptType::P2wsh {
+            let mut bytes = script!(<threshold>).into_bytes();
+            for key in &backup.keys {
+                bytes.extend(&script!(<key.as_slice()>).into_bytes());
+            }
+            let num_keys = backup.keys.len() as i64;
+            let script = script! {
+                <num_keys> OP_CHECKMULTISIG
+            };
+            bytes.extend(&script.into_bytes());
+            return bytes;
+        }
+
+        let mut bytes = vec![];
+        for (i, key) in backup.keys.iter().enumerate() {
+            let xonly_pubkey = &key.as_slice()[1..];
+            let script = if i == 0 {
+                script!(<xonly_pubkey> OP_CHECKSIG)
+            } else {
+                script!(<xonly_pubkey> OP_CHECKSIGADD)
+            };
+            bytes.extend(&script.into_bytes());
+        }
+        let script = script! {
+            <threshold> OP_NUMEQUAL
+        };
+        bytes.extend(&script.into_bytes());
+        bytes
+    }
+
+    /// Creates the output script which is used as the script pubkey in deposit
+    /// outputs and reserve outputs, either a P2WSH hash of the redeem script or
+    /// a P2TR output with the redeem script as its only leaf.
     pub fn output_script(&self, dest: &[u8], threshold: (u64, u64)) -> ContractResult<Script> {
-        Ok(self.redeem_script(dest, threshold)?.to_v0_p2wsh())
+        let redeem_script = self.redeem_script(dest, threshold)?;
+        let secp = Secp256k1::verification_only();
+        let internal_key = match self.script_type {
+            ScriptType::P2wsh => return Ok(redeem_script.to_v0_p2wsh()),
+            ScriptType::P2tr => XOnlyPublicKey::from_slice(&Vec::from_hex(NUMS_KEY)?)?,
+            ScriptType::Musig2 => self.aggregate_key(&secp)?,
+        };
+        let spend_info = TaprootBuilder::new()
+            .add_leaf(0, redeem_script)
+            .map_err(|err| ContractError::App(err.to_string()))?
+            .finalize(&secp, internal_key)
+            .map_err(|_| ContractError::App("Failed to build taproot output".into()))?;
+        Ok(Script::new_v1_p2tr_tweaked(spend_info.output_key()))
+    }
+
+    /// Computes the x-only MuSig2 (BIP327) aggregate of the signatory keys, in
+    /// order. This must match the key aggregation done by the contract.
+    fn aggregate_key(
+        &self,
+        secp: &Secp256k1<bitcoin::secp256k1::VerifyOnly>,
+    ) -> ContractResult<XOnlyPublicKey> {
+        let first = self
+            .signatories
+            .first()
+            .ok_or_else(|| ContractError::App("Cannot aggregate an empty key list".to_string()))?;
+        let list: Vec<_> = self.iter().map(|s| s.pubkey.as_slice()).collect();
+        let list_hash = tagged_hash("KeyAgg list", &list);
+        let second = self.iter().find(|s| s.pubkey != first.pubkey);
+
+        let mut terms = vec![];
+        for signatory in self.iter() {
+            let coefficient = if Some(&signatory.pubkey) == second.map(|s| &s.pubkey) {
+                Scalar::ONE
+            } else {
+                let hash = tagged_hash(
+                    "KeyAgg coefficient",
+                    &[&list_hash, signatory.pubkey.as_slice()],
+                );
+                Scalar::from_be_bytes(hash)
+                    .map_err(|_| ContractError::App("Invalid key coefficient".into()))?
+            };
+            let pubkey = PublicKey::from_slice(signatory.pubkey.as_slice())?;
+            terms.push(pubkey.mul_tweak(secp, &coefficient)?);
+        }
+
+        let terms: Vec<_> = terms.iter().collect();
+        Ok(PublicKey::combine_keys(&terms)?.x_only_public_key().0)
     }
 
     /// Calculates the number of bits of precision to remove from voting power
@@ -398,8 +658,28 @@ impl SignatorySet {
     /// for signatories beyond the threshold, but for fee estimation we err on
     /// the side of paying too much.
     pub fn est_witness_vsize(&self) -> u64 {
-        self.signatories.len() as u64 * 79 + 39
+        let est_backup_vsize = self
+            .backup
+            .as_ref()
+            .map_or(0, |backup| backup.keys.len() as u64 * 34 + 11);
+        match self.script_type {
+            ScriptType::P2wsh => self.signatories.len() as u64 * 79 + 39 + est_backup_vsize,
+            ScriptType::P2tr => self.signatories.len() as u64 * 28 + 28 + est_backup_vsize,
+            ScriptType::Musig2 => 17,
+        }
+    }
+}
+
+/// Computes a BIP340 tagged hash of the concatenation of the given data.
+fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    for bytes in data {
+        hasher.update(bytes);
     }
+    hasher.finalize().into()
 }
 
 #[wasm_bindgen]